@@ -0,0 +1,501 @@
+use crate::entry::Entry;
+use crate::error::PassmanError;
+use crate::service_name::ServiceName;
+use std::io;
+use std::path::Path;
+
+/// Seconds of inactivity after which the agent forgets the vault key.
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 15 * 60;
+
+/// Hidden command `passman agent` re-runs itself with to serve in the background.
+pub const AGENT_COMMAND: &str = "__agent";
+
+// Requests and responses are a u32 length followed by an opcode or status byte and its data
+const OP_PING: u8 = 0x01;
+const OP_RETRIEVE: u8 = 0x02;
+const OP_LOCK: u8 = 0x03;
+const OP_LIST: u8 = 0x04;
+const STATUS_OK: u8 = 0x00;
+const STATUS_ERROR: u8 = 0x01;
+const STATUS_OTHER_VAULT: u8 = 0x02;
+// The entry predates vault keys, only the master password opens it
+const STATUS_NEEDS_PASSWORD: u8 = 0x03;
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+/// Asks a running agent for an entry, `None` when no agent is unlocked for this vault.
+pub fn retrieve_entry(
+    storage_path: &Path,
+    service_name: &ServiceName,
+) -> Result<Option<Entry>, PassmanError> {
+    let mut request = vault_request(OP_RETRIEVE, storage_path);
+    request.extend_from_slice(service_name.file_name().as_bytes());
+
+    match vault_response(send_request(&request)?)? {
+        Some(data) => Ok(Some(Entry::from_bytes(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Asks a running agent for the services of a vault with encrypted names.
+pub fn list_services(storage_path: &Path) -> Result<Option<Vec<ServiceName>>, PassmanError> {
+    let request = vault_request(OP_LIST, storage_path);
+
+    match vault_response(send_request(&request)?)? {
+        Some(data) => {
+            let names = String::from_utf8(data)?;
+            let services = names
+                .lines()
+                .map(ServiceName::new)
+                .collect::<Result<_, _>>()?;
+            Ok(Some(services))
+        }
+        None => Ok(None),
+    }
+}
+
+fn vault_request(op: u8, storage_path: &Path) -> Vec<u8> {
+    let vault = storage_path.to_string_lossy();
+
+    let mut request = vec![op];
+    request.extend_from_slice(&(vault.len() as u32).to_le_bytes());
+    request.extend_from_slice(vault.as_bytes());
+    request
+}
+
+fn vault_response(response: Option<Vec<u8>>) -> Result<Option<Vec<u8>>, PassmanError> {
+    let Some(response) = response else {
+        return Ok(None);
+    };
+
+    match response.split_first() {
+        Some((&STATUS_OK, data)) => Ok(Some(data.to_vec())),
+        Some((&STATUS_OTHER_VAULT | &STATUS_NEEDS_PASSWORD, _)) => Ok(None),
+        Some((&STATUS_ERROR, message)) => Err(PassmanError::IoError(io::Error::other(format!(
+            "Agent: {}",
+            String::from_utf8_lossy(message)
+        )))),
+        _ => Err(PassmanError::InvalidFileFormat),
+    }
+}
+
+pub fn is_running() -> bool {
+    matches!(send_request(&[OP_PING]), Ok(Some(_)))
+}
+
+/// Tells the agent to forget everything and exit, returns whether one was running.
+pub fn lock() -> Result<bool, PassmanError> {
+    Ok(send_request(&[OP_LOCK])?.is_some())
+}
+
+#[cfg(unix)]
+pub use unix::{serve, socket_path};
+
+#[cfg(unix)]
+fn send_request(request: &[u8]) -> Result<Option<Vec<u8>>, PassmanError> {
+    use std::os::unix::net::UnixStream;
+
+    let path = socket_path()?;
+    let mut stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        // No socket or a stale one left by a killed agent both mean nobody is listening
+        Err(_) => return Ok(None),
+    };
+
+    unix::write_message(&mut stream, request)?;
+    Ok(Some(unix::read_message(&mut stream)?))
+}
+
+#[cfg(not(unix))]
+fn send_request(_request: &[u8]) -> Result<Option<Vec<u8>>, PassmanError> {
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+pub fn serve(
+    _storage_path: std::path::PathBuf,
+    _vault_key: [u8; 32],
+    _idle_timeout_secs: u64,
+) -> Result<(), PassmanError> {
+    Err(PassmanError::IoError(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The agent is only supported on Unix",
+    )))
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use crate::crypto;
+    use crate::storage::{FileKey, PassmanStorage};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use zeroize::Zeroize;
+
+    /// Bytes pinned in RAM so they never reach swap, wiped when dropped.
+    struct LockedBytes(Box<[u8]>);
+
+    impl LockedBytes {
+        fn new(bytes: &[u8]) -> Self {
+            let bytes: Box<[u8]> = bytes.into();
+            // Best effort, RLIMIT_MEMLOCK can be tiny and an unlocked secret still beats no agent
+            unsafe {
+                libc::mlock(bytes.as_ptr() as *const libc::c_void, bytes.len());
+            }
+            Self(bytes)
+        }
+    }
+
+    impl Drop for LockedBytes {
+        fn drop(&mut self) {
+            self.0.zeroize();
+            unsafe {
+                libc::munlock(self.0.as_ptr() as *const libc::c_void, self.0.len());
+            }
+        }
+    }
+
+    /// What an unlocked agent holds: the vault key, never the master password.
+    struct AgentState {
+        vault_key: LockedBytes,
+        last_used: Instant,
+    }
+
+    impl AgentState {
+        fn new(vault_key: &[u8; 32]) -> Self {
+            Self {
+                vault_key: LockedBytes::new(vault_key),
+                last_used: Instant::now(),
+            }
+        }
+
+        fn vault_key(&self) -> [u8; 32] {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&self.vault_key.0);
+            key
+        }
+    }
+
+    /// `$XDG_RUNTIME_DIR/passman-agent.sock`, or a private folder in the temp dir without one.
+    pub fn socket_path() -> Result<PathBuf, PassmanError> {
+        if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+            return Ok(PathBuf::from(runtime_dir).join("passman-agent.sock"));
+        }
+
+        let uid = unsafe { libc::getuid() };
+        let dir = std::env::temp_dir().join(format!("passman-{}", uid));
+
+        if let Err(e) = fs::DirBuilder::new().mode(0o700).create(&dir)
+            && e.kind() != io::ErrorKind::AlreadyExists
+        {
+            return Err(e.into());
+        }
+
+        check_private_dir(&dir, uid)?;
+        Ok(dir.join("agent.sock"))
+    }
+
+    /// Someone else could have created the folder first to listen in our place.
+    fn check_private_dir(dir: &Path, uid: u32) -> Result<(), PassmanError> {
+        let metadata = fs::symlink_metadata(dir)?;
+        if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+            return Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("'{}' is not a private folder owned by you", dir.display()),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Serves decrypt requests for the vault until locked or idle for too long.
+    pub fn serve(
+        storage_path: PathBuf,
+        vault_key: [u8; 32],
+        idle_timeout_secs: u64,
+    ) -> Result<(), PassmanError> {
+        // Keep other processes of the same user from reading our memory or getting a core dump
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::prctl(libc::PR_SET_DUMPABLE, 0);
+        }
+
+        let mut vault_key = vault_key;
+        let state = Arc::new(Mutex::new(Some(AgentState::new(&vault_key))));
+        vault_key.zeroize();
+
+        let path = socket_path()?;
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+        let watchdog_state = state.clone();
+        let watchdog_path = path.clone();
+        thread::spawn(move || {
+            let timeout = Duration::from_secs(idle_timeout_secs);
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                if lock_if_idle(&mut watchdog_state.lock().unwrap(), timeout) {
+                    let _ = fs::remove_file(&watchdog_path);
+                    std::process::exit(0);
+                }
+            }
+        });
+
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            // A client that connects and never writes must not hold everyone else up
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+
+            let request = match read_message(&mut stream) {
+                Ok(request) => request,
+                Err(_) => continue,
+            };
+
+            let mut guard = state.lock().unwrap();
+            let response = handle_request(&mut guard, &storage_path, &request);
+            let _ = write_message(&mut stream, &response);
+            if guard.is_none() {
+                break;
+            }
+        }
+
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    /// Answers one request, dropping the state when asked to lock.
+    fn handle_request(state: &mut Option<AgentState>, storage_path: &Path, request: &[u8]) -> Vec<u8> {
+        let Some(agent) = state.as_mut() else {
+            return error_response("locked");
+        };
+        agent.last_used = Instant::now();
+
+        match request.split_first() {
+            Some((&OP_PING, _)) => vec![STATUS_OK],
+            Some((&OP_RETRIEVE, data)) => handle_retrieve(agent, storage_path, data),
+            Some((&OP_LIST, data)) => handle_list(agent, storage_path, data),
+            Some((&OP_LOCK, _)) => {
+                *state = None;
+                vec![STATUS_OK]
+            }
+            _ => error_response("unknown request"),
+        }
+    }
+
+    /// Forgets the vault key once nothing asked for it in `timeout`, returns whether it did.
+    fn lock_if_idle(state: &mut Option<AgentState>, timeout: Duration) -> bool {
+        if state.as_ref().is_some_and(|agent| agent.last_used.elapsed() >= timeout) {
+            *state = None;
+            return true;
+        }
+        false
+    }
+
+    /// Splits off the vault path a request starts with, answering right away if it isn't ours.
+    fn check_vault<'a>(storage_path: &Path, data: &'a [u8]) -> Result<&'a [u8], Vec<u8>> {
+        if data.len() < 4 {
+            return Err(error_response("malformed request"));
+        }
+        let (len, rest) = data.split_at(4);
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        if len > rest.len() {
+            return Err(error_response("malformed request"));
+        }
+        let (vault, rest) = rest.split_at(len);
+
+        // One agent per user, clients of another vault fall back to asking for the password
+        if vault != storage_path.to_string_lossy().as_bytes() {
+            return Err(vec![STATUS_OTHER_VAULT]);
+        }
+
+        Ok(rest)
+    }
+
+    fn handle_retrieve(agent: &AgentState, storage_path: &Path, data: &[u8]) -> Vec<u8> {
+        let service = match check_vault(storage_path, data) {
+            Ok(service) => service,
+            Err(response) => return response,
+        };
+
+        let Some(service) = std::str::from_utf8(service)
+            .ok()
+            .and_then(ServiceName::from_file_name)
+        else {
+            return error_response("invalid service name");
+        };
+
+        let vault_key = agent.vault_key();
+        let mut needs_password = false;
+        let storage = PassmanStorage::with_vault_key(storage_path.to_path_buf(), Some(vault_key));
+        let entry = storage.retrieve_entry_with(&service, |file_key| match file_key {
+            FileKey::VaultKey(entry_id) => Ok(crypto::derive_entry_key(&vault_key, entry_id)),
+            FileKey::Password(_) => {
+                needs_password = true;
+                Err(PassmanError::WrongMasterPassword)
+            }
+        });
+
+        match entry {
+            Ok(entry) => {
+                let mut response = vec![STATUS_OK];
+                response.extend_from_slice(&entry.to_bytes());
+                response
+            }
+            Err(_) if needs_password => vec![STATUS_NEEDS_PASSWORD],
+            Err(e) => error_response(&e.to_string()),
+        }
+    }
+
+    fn handle_list(agent: &AgentState, storage_path: &Path, data: &[u8]) -> Vec<u8> {
+        if let Err(response) = check_vault(storage_path, data) {
+            return response;
+        }
+
+        let storage = PassmanStorage::with_vault_key(storage_path.to_path_buf(), Some(agent.vault_key()));
+        match storage.list_services() {
+            Ok(services) => {
+                let mut response = vec![STATUS_OK];
+                let names: Vec<&str> = services.iter().map(ServiceName::as_str).collect();
+                response.extend_from_slice(names.join("\n").as_bytes());
+                response
+            }
+            Err(e) => error_response(&e.to_string()),
+        }
+    }
+
+    fn error_response(message: &str) -> Vec<u8> {
+        let mut response = vec![STATUS_ERROR];
+        response.extend_from_slice(message.as_bytes());
+        response
+    }
+
+    pub(super) fn write_message(stream: &mut UnixStream, message: &[u8]) -> io::Result<()> {
+        stream.write_all(&(message.len() as u32).to_le_bytes())?;
+        stream.write_all(message)?;
+        stream.flush()
+    }
+
+    pub(super) fn read_message(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message too large"));
+        }
+
+        let mut message = vec![0u8; len];
+        stream.read_exact(&mut message)?;
+        Ok(message)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::entry::Entry;
+        use crate::vault::VaultMetadata;
+
+        const VAULT_KEY: [u8; 32] = [7; 32];
+
+        fn service(name: &str) -> ServiceName {
+            ServiceName::new(name).unwrap()
+        }
+
+        /// A vault at `dir` with one entry, "github".
+        fn new_vault(dir: &Path) {
+            VaultMetadata::create("master", &VAULT_KEY, 8, 1, 1).unwrap().save(dir).unwrap();
+            PassmanStorage::with_path("master".to_string(), dir.to_path_buf())
+                .store(&service("github"), "hunter2", 8, 1, 1)
+                .unwrap();
+        }
+
+        fn retrieve_request(storage_path: &Path, name: &str) -> Vec<u8> {
+            let mut request = vault_request(OP_RETRIEVE, storage_path);
+            request.extend_from_slice(service(name).file_name().as_bytes());
+            request
+        }
+
+        #[test]
+        fn frames_messages_with_their_length() {
+            let (mut client, mut server) = UnixStream::pair().unwrap();
+            write_message(&mut client, b"hello").unwrap();
+            write_message(&mut client, b"").unwrap();
+            assert_eq!(read_message(&mut server).unwrap(), b"hello");
+            assert_eq!(read_message(&mut server).unwrap(), b"");
+        }
+
+        #[test]
+        fn rejects_oversized_and_truncated_messages() {
+            let (mut client, mut server) = UnixStream::pair().unwrap();
+            client.write_all(&(MAX_MESSAGE_LEN as u32 + 1).to_le_bytes()).unwrap();
+            let error = read_message(&mut server).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+            let (mut client, mut server) = UnixStream::pair().unwrap();
+            client.write_all(&10u32.to_le_bytes()).unwrap();
+            client.write_all(b"abc").unwrap();
+            drop(client);
+            assert!(read_message(&mut server).is_err());
+        }
+
+        #[test]
+        fn only_trusts_a_private_folder_of_ours() {
+            let tmp = tempfile::tempdir().unwrap();
+            let uid = unsafe { libc::getuid() };
+            let dir = tmp.path().join("passman");
+            fs::DirBuilder::new().mode(0o700).create(&dir).unwrap();
+            assert!(check_private_dir(&dir, uid).is_ok());
+            assert!(check_private_dir(&dir, uid + 1).is_err());
+
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+            assert!(check_private_dir(&dir, uid).is_err());
+
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+            let link = tmp.path().join("link");
+            std::os::unix::fs::symlink(&dir, &link).unwrap();
+            assert!(check_private_dir(&link, uid).is_err());
+        }
+
+        #[test]
+        fn serves_entries_until_locked() {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = tmp.path().join("vault");
+            new_vault(&dir);
+            let mut state = Some(AgentState::new(&VAULT_KEY));
+
+            assert_eq!(handle_request(&mut state, &dir, &[OP_PING]), [STATUS_OK]);
+
+            let response = handle_request(&mut state, &dir, &retrieve_request(&dir, "github"));
+            let entry = vault_response(Some(response)).unwrap().unwrap();
+            assert_eq!(Entry::from_bytes(&entry).unwrap().password, "hunter2");
+
+            let other = tmp.path().join("other");
+            let response = handle_request(&mut state, &dir, &retrieve_request(&other, "github"));
+            assert_eq!(response, [STATUS_OTHER_VAULT]);
+
+            let response = handle_request(&mut state, &dir, &retrieve_request(&dir, "missing"));
+            assert_eq!(response[0], STATUS_ERROR);
+
+            assert_eq!(handle_request(&mut state, &dir, &[OP_LOCK]), [STATUS_OK]);
+            assert!(state.is_none());
+            let response = handle_request(&mut state, &dir, &retrieve_request(&dir, "github"));
+            assert_eq!(response[0], STATUS_ERROR);
+        }
+
+        #[test]
+        fn locks_once_idle() {
+            let mut state = Some(AgentState::new(&VAULT_KEY));
+            let timeout = Duration::from_secs(60);
+            assert!(!lock_if_idle(&mut state, timeout));
+            assert!(state.is_some());
+
+            state.as_mut().unwrap().last_used = Instant::now() - timeout;
+            assert!(lock_if_idle(&mut state, timeout));
+            assert!(state.is_none());
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::permissions;
+
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Replaces `path` with `data` so a reader only ever sees the old or the new content.
+///
+/// The data goes to a temporary file in the same directory, which is synced and
+/// renamed over the target, then the directory is synced so the rename survives a crash.
+pub fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = parent_dir(path);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let temp_path = dir.join(format!(
+        ".{}.{}{}",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TEMP_SUFFIX
+    ));
+
+    let result = (|| {
+        let mut file = permissions::private_open_options()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    sync_dir(dir)
+}
+
+/// Makes renames and removals inside `dir` durable. Does nothing where directories can't be opened.
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Leftovers of a write that was interrupted before its rename.
+pub fn is_temp_file(file_name: &str) -> bool {
+    file_name.starts_with('.') && file_name.ends_with(TEMP_SUFFIX)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_files(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| is_temp_file(name))
+            .collect()
+    }
+
+    #[test]
+    fn replaces_an_existing_file_entirely() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("entry");
+
+        write_file(&path, b"a much longer old content").unwrap();
+        write_file(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(temp_files(tmp.path()).is_empty());
+    }
+
+    #[test]
+    fn leaves_the_target_untouched_when_the_temp_file_cannot_be_created() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("entry");
+        write_file(&path, b"old").unwrap();
+
+        // An interrupted write of this very process left its temp file behind
+        let leftover = tmp.path().join(format!(".entry.{}{}", std::process::id(), TEMP_SUFFIX));
+        fs::write(&leftover, b"partial").unwrap();
+
+        assert!(write_file(&path, b"new").is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+    }
+
+    #[test]
+    fn removes_the_temp_file_when_the_rename_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        // A file can't be renamed over a non-empty directory
+        let path = tmp.path().join("entry");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside"), b"kept").unwrap();
+
+        assert!(write_file(&path, b"new").is_err());
+        assert!(temp_files(tmp.path()).is_empty());
+        assert_eq!(fs::read(path.join("inside")).unwrap(), b"kept");
+    }
+
+    #[test]
+    fn recognizes_its_temp_files() {
+        assert!(is_temp_file(".entry.1234.tmp"));
+        assert!(!is_temp_file("entry.tmp"));
+        assert!(!is_temp_file(".passman-vault"));
+    }
+}
@@ -0,0 +1,363 @@
+use argon2::Version;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use std::collections::HashSet;
+use std::io::{self, Cursor, Read};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, KdfParameters};
+use crate::entry::{self, Entry};
+use crate::error::PassmanError;
+use crate::service_name::ServiceName;
+use crate::storage::{ENCRYPTION_CHACHA20POLY1305, KDF_ARGON2ID};
+
+const BACKUP_MAGIC_NUMBER: &[u8; 4] = b"PMBK";
+const BACKUP_VERSION_1: u8 = 0x01;
+// Magic number, version, KDF type, salt, three costs, encryption type and nonce
+const HEADER_LEN: usize = 4 + 1 + 1 + 16 + 3 * 4 + 1 + 12;
+
+// The costs are read before anything is authenticated, a crafted file must not
+// make opening it allocate gigabytes or spin for hours
+const MAX_KDF_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const MAX_KDF_ITERATIONS: u32 = 1000;
+const MAX_KDF_LANES: u32 = 16;
+
+/// A whole vault in one file, sealed with its own passphrase.
+///
+/// The header (costs, salt, nonce) is authenticated along with the entries,
+/// so a damaged or tampered file fails to open as a whole and nothing is restored from it.
+pub struct Backup {
+    /// Seconds since the Unix epoch
+    pub created: u64,
+    /// The passman version that wrote it
+    pub generator: String,
+    pub entries: Vec<(ServiceName, Entry)>,
+}
+
+/// What `passman restore` does with entries already in the vault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestoreMode {
+    /// Add missing entries, replace existing ones only with a newer copy
+    #[default]
+    Merge,
+    /// Make the vault exactly the backup, removing entries it doesn't have
+    Replace,
+}
+
+impl FromStr for RestoreMode {
+    type Err = PassmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(RestoreMode::Merge),
+            "replace" => Ok(RestoreMode::Replace),
+            _ => Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown restore mode '{}', expected 'merge' or 'replace'", s),
+            ))),
+        }
+    }
+}
+
+impl Backup {
+    pub fn new(entries: Vec<(ServiceName, Entry)>) -> Self {
+        Self {
+            created: entry::unix_now(),
+            generator: format!("passman {}", env!("CARGO_PKG_VERSION")),
+            entries,
+        }
+    }
+
+    /// Encrypts the backup with `passphrase`.
+    pub fn seal(&self, passphrase: &str, m_cost: u32, t_cost: u32, p_cost: u32) -> Result<Vec<u8>, PassmanError> {
+        let (cipher, kdf_params, nonce) = crypto::gen_new_cipher(passphrase.as_bytes(), m_cost, t_cost, p_cost)?;
+
+        let mut data = header_bytes(&kdf_params, &nonce);
+        let sealed = cipher.encrypt(
+            chacha20poly1305::Nonce::from_slice(&nonce),
+            Payload {
+                msg: &self.to_bytes(),
+                aad: &data,
+            },
+        )?;
+        data.extend_from_slice(&sealed);
+
+        Ok(data)
+    }
+
+    /// Decrypts and checks a whole backup, failing if any part of it is damaged.
+    pub fn open(content: &[u8], passphrase: &str) -> Result<Self, PassmanError> {
+        if !content.starts_with(BACKUP_MAGIC_NUMBER) {
+            return Err(invalid("not a passman backup"));
+        }
+        if content.len() < HEADER_LEN {
+            return Err(invalid("the file is truncated"));
+        }
+        let mut cursor = Cursor::new(content);
+
+        let mut magic_number = [0u8; 4];
+        cursor.read_exact(&mut magic_number)?;
+        if magic_number != *BACKUP_MAGIC_NUMBER {
+            return Err(invalid("not a passman backup"));
+        }
+
+        let mut version_byte = [0u8; 1];
+        cursor.read_exact(&mut version_byte)?;
+        if version_byte[0] != BACKUP_VERSION_1 {
+            return Err(invalid("it was made by a newer version of passman"));
+        }
+
+        let mut kdf_type_byte = [0u8; 1];
+        cursor.read_exact(&mut kdf_type_byte)?;
+        if kdf_type_byte[0] != KDF_ARGON2ID {
+            return Err(invalid("unknown key derivation function"));
+        }
+
+        let mut salt = [0u8; 16];
+        cursor.read_exact(&mut salt)?;
+        let mut cost_bytes = [0u8; 4];
+        cursor.read_exact(&mut cost_bytes)?;
+        let m_cost = u32::from_le_bytes(cost_bytes);
+        cursor.read_exact(&mut cost_bytes)?;
+        let t_cost = u32::from_le_bytes(cost_bytes);
+        cursor.read_exact(&mut cost_bytes)?;
+        let p_cost = u32::from_le_bytes(cost_bytes);
+
+        if m_cost > MAX_KDF_MEMORY_KIB || t_cost > MAX_KDF_ITERATIONS || p_cost > MAX_KDF_LANES {
+            return Err(invalid(&format!(
+                "its key derivation costs are too high (m={} KiB, t={}, p={})",
+                m_cost, t_cost, p_cost
+            )));
+        }
+
+        let mut encryption_type_byte = [0u8; 1];
+        cursor.read_exact(&mut encryption_type_byte)?;
+        if encryption_type_byte[0] != ENCRYPTION_CHACHA20POLY1305 {
+            return Err(invalid("unknown encryption algorithm"));
+        }
+
+        let mut nonce = [0u8; 12];
+        cursor.read_exact(&mut nonce)?;
+        let header_len = cursor.position() as usize;
+
+        let kdf_params = KdfParameters {
+            salt,
+            version: Version::V0x13,
+            m_cost,
+            t_cost,
+            p_cost,
+        };
+        let cipher = crypto::gen_decrypt_cipher(passphrase.as_bytes(), &kdf_params)?;
+        let payload = Payload {
+            msg: &content[header_len..],
+            aad: &content[..header_len],
+        };
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(&GenericArray::clone_from_slice(&nonce), payload)
+                .map_err(|_| invalid("wrong passphrase, or the file is damaged"))?,
+        );
+
+        Self::from_bytes(&plaintext)
+    }
+
+    // created u64, generator, entry count u32, then a service name and entry per record,
+    // each string or entry prefixed with its u32 length
+    fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut data = Zeroizing::new(Vec::new());
+        data.extend_from_slice(&self.created.to_le_bytes());
+        write_record(&mut data, self.generator.as_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+
+        for (service, entry) in &self.entries {
+            write_record(&mut data, service.as_str().as_bytes());
+            write_record(&mut data, &Zeroizing::new(entry.to_bytes()));
+        }
+
+        data
+    }
+
+    fn from_bytes(content: &[u8]) -> Result<Self, PassmanError> {
+        let mut cursor = Cursor::new(content);
+
+        let mut created_bytes = [0u8; 8];
+        cursor.read_exact(&mut created_bytes)?;
+        let generator = String::from_utf8(read_record(&mut cursor)?)?;
+
+        let mut count_bytes = [0u8; 4];
+        cursor.read_exact(&mut count_bytes)?;
+        let count = u32::from_le_bytes(count_bytes);
+
+        let mut entries = Vec::new();
+        let mut seen = HashSet::new();
+        for _ in 0..count {
+            let service = ServiceName::new(&String::from_utf8(read_record(&mut cursor)?)?)?;
+            // Restoring would silently keep only one of them
+            if !seen.insert(service.clone()) {
+                return Err(invalid(&format!("duplicate entry '{}'", service)));
+            }
+            let entry = Entry::from_bytes(&Zeroizing::new(read_record(&mut cursor)?))?;
+            entries.push((service, entry));
+        }
+
+        if cursor.position() as usize != content.len() {
+            return Err(PassmanError::InvalidFileFormat);
+        }
+
+        Ok(Self {
+            created: u64::from_le_bytes(created_bytes),
+            generator,
+            entries,
+        })
+    }
+}
+
+fn header_bytes(kdf_params: &KdfParameters, nonce: &[u8; 12]) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    data.extend_from_slice(BACKUP_MAGIC_NUMBER);
+    data.push(BACKUP_VERSION_1);
+    data.push(KDF_ARGON2ID);
+    data.extend_from_slice(&kdf_params.salt);
+    data.extend_from_slice(&kdf_params.m_cost.to_le_bytes());
+    data.extend_from_slice(&kdf_params.t_cost.to_le_bytes());
+    data.extend_from_slice(&kdf_params.p_cost.to_le_bytes());
+    data.push(ENCRYPTION_CHACHA20POLY1305);
+    data.extend_from_slice(nonce);
+
+    data
+}
+
+fn write_record(data: &mut Vec<u8>, record: &[u8]) {
+    data.extend_from_slice(&(record.len() as u32).to_le_bytes());
+    data.extend_from_slice(record);
+}
+
+fn read_record(cursor: &mut Cursor<&[u8]>) -> Result<Vec<u8>, PassmanError> {
+    let mut len_bytes = [0u8; 4];
+    cursor.read_exact(&mut len_bytes)?;
+    let len = u32::from_le_bytes(len_bytes) as usize;
+
+    let remaining = cursor.get_ref().len() - cursor.position() as usize;
+    if len > remaining {
+        return Err(PassmanError::InvalidFileFormat);
+    }
+
+    let mut record = vec![0u8; len];
+    cursor.read_exact(&mut record)?;
+    Ok(record)
+}
+
+fn invalid(reason: &str) -> PassmanError {
+    PassmanError::InvalidBackup(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "backup passphrase";
+
+    fn sample() -> Backup {
+        let mut github = Entry::new("hunter2".to_string());
+        github.username = Some("octo".to_string());
+        github.set_field("totp", "otpauth://totp/x");
+        Backup::new(vec![
+            (ServiceName::new("work/github").unwrap(), github),
+            (ServiceName::new("mail").unwrap(), Entry::new("pw".to_string())),
+        ])
+    }
+
+    fn seal(backup: &Backup) -> Vec<u8> {
+        backup.seal(PASSPHRASE, 8, 1, 1).unwrap()
+    }
+
+    #[test]
+    fn opens_what_it_sealed() {
+        let backup = sample();
+        let opened = Backup::open(&seal(&backup), PASSPHRASE).unwrap();
+
+        assert_eq!(opened.created, backup.created);
+        assert_eq!(opened.generator, backup.generator);
+        assert_eq!(opened.entries, backup.entries);
+    }
+
+    #[test]
+    fn rejects_a_wrong_passphrase() {
+        assert!(matches!(
+            Backup::open(&seal(&sample()), "wrong"),
+            Err(PassmanError::InvalidBackup(_))
+        ));
+    }
+
+    #[test]
+    fn detects_tampering_anywhere() {
+        let sealed = seal(&sample());
+
+        // The salt, a cost, the nonce and the body
+        for i in [10, 22 + 4, HEADER_LEN - 1, HEADER_LEN + 3, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(Backup::open(&tampered, PASSPHRASE).is_err(), "byte {} was not covered", i);
+        }
+    }
+
+    #[test]
+    fn rejects_other_and_truncated_files() {
+        let sealed = seal(&sample());
+
+        assert!(Backup::open(b"PK\x03\x04", PASSPHRASE).is_err());
+        assert!(Backup::open(&sealed[..HEADER_LEN - 1], PASSPHRASE).is_err());
+        assert!(Backup::open(&sealed[..sealed.len() - 1], PASSPHRASE).is_err());
+
+        let mut newer = sealed.clone();
+        newer[4] = BACKUP_VERSION_1 + 1;
+        assert!(Backup::open(&newer, PASSPHRASE).is_err());
+    }
+
+    #[test]
+    fn rejects_excessive_costs_before_deriving() {
+        let sealed = seal(&sample());
+
+        // Memory, iterations and lanes follow the 16 byte salt
+        for (offset, max) in [(22, MAX_KDF_MEMORY_KIB), (26, MAX_KDF_ITERATIONS), (30, MAX_KDF_LANES)] {
+            let mut costly = sealed.clone();
+            costly[offset..offset + 4].copy_from_slice(&(max + 1).to_le_bytes());
+            match Backup::open(&costly, PASSPHRASE) {
+                Err(PassmanError::InvalidBackup(reason)) => {
+                    assert!(reason.starts_with("its key derivation costs are too high"), "{}", reason)
+                }
+                _ => panic!("a cost above the limit at byte {} was accepted", offset),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_entries() {
+        let mut backup = sample();
+        let copy = backup.entries[0].clone();
+        backup.entries.push(copy);
+
+        match Backup::from_bytes(&backup.to_bytes()) {
+            Err(PassmanError::InvalidBackup(reason)) => assert_eq!(reason, "duplicate entry 'work/github'"),
+            _ => panic!("a backup with a duplicate entry was accepted"),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_contents() {
+        let data = sample().to_bytes();
+        assert!(Backup::from_bytes(&data[..data.len() - 1]).is_err());
+
+        let mut trailing = data.to_vec();
+        trailing.push(0);
+        assert!(Backup::from_bytes(&trailing).is_err());
+
+        // One more entry than there is
+        let mut count = data.to_vec();
+        let count_at = 8 + 4 + sample().generator.len();
+        count[count_at] += 1;
+        assert!(Backup::from_bytes(&count).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use crate::config::KdfCosts;
+use crate::crypto;
+use crate::defaults::{MIN_M_COST, MIN_T_COST};
+use crate::error::PassmanError;
+
+// Quick to run even on slow machines, long enough to time
+const PROBE_M_COST: u32 = 32 * 1024;
+const M_COST_STEP: u32 = 1024;
+const REFINE_ROUNDS: usize = 4;
+// Close enough to the target to stop refining
+const TOLERANCE: f64 = 0.1;
+
+// The argon2 crate computes lanes one after another, more of them only add time
+const P_COST: u32 = 1;
+
+/// Times one key derivation with `costs`, the same work as unlocking the vault.
+pub fn measure(costs: KdfCosts) -> Result<Duration, PassmanError> {
+    let start = Instant::now();
+    crypto::gen_new_cipher(b"passman calibration", costs.m_cost, costs.t_cost, costs.p_cost)?;
+    Ok(start.elapsed())
+}
+
+/// Picks costs that take about `target` to unlock on this machine, using at most `max_m_cost` KiB.
+/// Returns them along with how long they took.
+///
+/// Memory goes up first, as it is what makes guessing on GPUs expensive,
+/// then iterations fill whatever time is left.
+pub fn calibrate(target: Duration, max_m_cost: u32) -> Result<(KdfCosts, Duration), PassmanError> {
+    let probe = KdfCosts {
+        m_cost: PROBE_M_COST.min(max_m_cost),
+        t_cost: 1,
+        p_cost: P_COST,
+    };
+    // The first run also pays for allocating the memory
+    measure(probe)?;
+    let mut costs = probe;
+    let mut elapsed = measure(probe)?;
+
+    // Time isn't quite proportional to memory times iterations, so correct a few times
+    for _ in 0..REFINE_ROUNDS {
+        let ratio = target.as_secs_f64() / elapsed.as_secs_f64();
+        let next = split_work(f64::from(costs.m_cost) * f64::from(costs.t_cost) * ratio, max_m_cost);
+        if next == costs {
+            break;
+        }
+
+        costs = next;
+        elapsed = measure(costs)?;
+        if (1.0 - elapsed.as_secs_f64() / target.as_secs_f64()).abs() < TOLERANCE {
+            break;
+        }
+    }
+
+    Ok((costs, elapsed))
+}
+
+/// Splits `work`, in KiB times iterations, into memory and iterations.
+fn split_work(work: f64, max_m_cost: u32) -> KdfCosts {
+    let max_m_cost = max_m_cost - max_m_cost % M_COST_STEP;
+    let min_m_cost = MIN_M_COST.min(max_m_cost);
+
+    let m_cost = (work / f64::from(MIN_T_COST)).min(f64::from(max_m_cost)) as u32;
+    let m_cost = (m_cost - m_cost % M_COST_STEP).max(min_m_cost);
+    let t_cost = ((work / f64::from(m_cost)).round() as u32).max(MIN_T_COST);
+
+    KdfCosts {
+        m_cost,
+        t_cost,
+        p_cost: P_COST,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_M_COST: u32 = 512 * 1024;
+
+    #[test]
+    fn fills_memory_before_iterations() {
+        let costs = split_work(f64::from(MAX_M_COST) * 10.0, MAX_M_COST);
+        assert_eq!((costs.m_cost, costs.t_cost, costs.p_cost), (MAX_M_COST, 10, P_COST));
+
+        // Below the cap, memory takes the work of the minimum iterations
+        let costs = split_work(150_000.0, MAX_M_COST);
+        assert_eq!((costs.m_cost, costs.t_cost), (73 * M_COST_STEP, MIN_T_COST));
+    }
+
+    #[test]
+    fn never_goes_below_the_minimums() {
+        let costs = split_work(1.0, MAX_M_COST);
+        assert_eq!((costs.m_cost, costs.t_cost), (MIN_M_COST, MIN_T_COST));
+    }
+
+    #[test]
+    fn keeps_to_the_memory_cap_in_whole_steps() {
+        let costs = split_work(1e9, MAX_M_COST + 100);
+        assert_eq!(costs.m_cost, MAX_M_COST);
+
+        // A cap under the minimum wins, iterations make up for it
+        let costs = split_work(1e9, 8 * 1024);
+        assert_eq!((costs.m_cost, costs.t_cost), (8 * 1024, 122_070));
+    }
+}
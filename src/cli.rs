@@ -1,34 +1,168 @@
+use crate::agent;
+use crate::clipboard::ClipboardBackend;
+use crate::defaults;
 use crate::error::PassmanError;
-use crate::storage::PassmanStorage;
-use arboard::Clipboard;
-use rand::Rng;
-use std::io::{self, Write};
+use crate::service_name::ServiceName;
+use crate::storage::{self, PassmanStorage};
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
 
-pub fn list_all_services() -> Result<Vec<String>, PassmanError> {
-    let storage_path = PassmanStorage::get_default_path();
+/// Hidden command the CLI re-runs itself with to clear the clipboard once it exited.
+pub const CLEAR_CLIPBOARD_COMMAND: &str = "__clear-clipboard";
+
+/// Environment variable holding the master password for non-interactive use.
+pub const PASSWORD_ENV_VAR: &str = "PASSMAN_PASSWORD";
+
+/// Where the master password comes from when no one is at the keyboard.
+pub enum MasterPasswordSource {
+    Fd(i32),
+    File(PathBuf),
+    /// Taken out of `PASSMAN_PASSWORD`, which is then removed from the environment
+    Env(String),
+}
+
+static MASTER_PASSWORD_SOURCE: OnceLock<MasterPasswordSource> = OnceLock::new();
+static CACHED_MASTER_PASSWORD: OnceLock<String> = OnceLock::new();
 
-    if !storage_path.exists() {
-        return Ok(Vec::new());
+/// Strips the options accepted before or after any command (`--password-fd`,
+/// `--password-file`, `--clipboard`) from the arguments and remembers them.
+/// The master password falls back to `PASSMAN_PASSWORD` when it is set.
+pub fn take_global_options(args: &[String]) -> Result<Vec<String>, PassmanError> {
+    let mut remaining = Vec::with_capacity(args.len());
+    let mut source = None;
+    let mut clipboard = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--password-fd" => {
+                // Reading from stdout or stderr would wait on the terminal, or worse, a pipe the caller reads
+                let fd = iter
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|fd| *fd == 0 || *fd > 2)
+                    .ok_or_else(|| {
+                        PassmanError::IoError(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "'--password-fd' expects a file descriptor number other than 1 or 2",
+                        ))
+                    })?;
+                source = Some(MasterPasswordSource::Fd(fd));
+            }
+            "--password-file" => {
+                let path = iter.next().ok_or_else(|| {
+                    PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "'--password-file' expects a path",
+                    ))
+                })?;
+                source = Some(MasterPasswordSource::File(PathBuf::from(path)));
+            }
+            "--vault" => {
+                let path = iter.next().ok_or_else(|| {
+                    PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "'--vault' expects a path",
+                    ))
+                })?;
+                PassmanStorage::set_default_path(PathBuf::from(path));
+            }
+            "--clipboard" => {
+                let value = iter.next().ok_or_else(|| {
+                    PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "'--clipboard' expects 'system', 'osc52' or 'auto'",
+                    ))
+                })?;
+                clipboard = Some(match value.as_str() {
+                    "auto" => ClipboardBackend::detect(),
+                    name => name.parse()?,
+                });
+            }
+            _ => remaining.push(arg.clone()),
+        }
     }
 
-    let mut services = Vec::new();
-    let entries = std::fs::read_dir(&storage_path)?;
+    if let Some(value) = std::env::var_os(PASSWORD_ENV_VAR) {
+        // Don't hand the password down to editors, clipboard helpers or the agent.
+        // SAFETY: this runs first thing in main, before any other thread exists
+        unsafe { std::env::remove_var(PASSWORD_ENV_VAR) };
 
-    for entry in entries {
-        let entry = entry?;
-        if let Some(name) = entry.file_name().to_str() {
-            services.push(name.to_string());
+        let password = value.into_string().map_err(|_| {
+            PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not valid UTF-8", PASSWORD_ENV_VAR),
+            ))
+        })?;
+        if source.is_none() {
+            source = Some(MasterPasswordSource::Env(password));
         }
     }
 
-    services.sort();
-    Ok(services)
+    if let Some(source) = source {
+        let _ = MASTER_PASSWORD_SOURCE.set(source);
+    }
+    if let Some(clipboard) = clipboard {
+        ClipboardBackend::select(clipboard);
+    }
+
+    Ok(remaining)
 }
 
-pub fn prompt_service_selection(services: &[String]) -> Result<String, PassmanError> {
-    println!("Available services:");
+fn read_non_interactive_password(source: &MasterPasswordSource) -> Result<String, PassmanError> {
+    let content = match source {
+        #[cfg(unix)]
+        MasterPasswordSource::Fd(fd) => {
+            use std::mem::ManuallyDrop;
+            use std::os::fd::FromRawFd;
+            // SAFETY: the caller handed us this descriptor explicitly to read the password from.
+            // It stays theirs, so it is never closed here
+            let mut file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(*fd) });
+            let mut content = String::new();
+            file.read_to_string(&mut content)?;
+            content
+        }
+        #[cfg(not(unix))]
+        MasterPasswordSource::Fd(_) => {
+            return Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "'--password-fd' is only supported on Unix",
+            )));
+        }
+        MasterPasswordSource::File(path) => std::fs::read_to_string(path)?,
+        MasterPasswordSource::Env(password) => password.clone(),
+    };
+
+    // Only the first line counts, like a password typed at the prompt
+    Ok(content.lines().next().unwrap_or("").to_string())
+}
+
+/// Lists the vault, unlocking it first when service names are encrypted.
+/// The agent is asked before the master password, which is then kept for the rest of the command.
+pub fn list_all_services() -> Result<Vec<ServiceName>, PassmanError> {
+    let storage_path = PassmanStorage::get_default_path();
+
+    match storage::list_services_in(&storage_path) {
+        Err(PassmanError::ServiceNamesEncrypted) => {
+            if let Some(services) = agent::list_services(&storage_path)? {
+                return Ok(services);
+            }
+
+            let master_password = prompt_master_password()?;
+            let services = PassmanStorage::unlock(master_password.clone())?.list_services()?;
+            let _ = CACHED_MASTER_PASSWORD.set(master_password);
+            Ok(services)
+        }
+        result => result,
+    }
+}
+
+pub fn prompt_service_selection(services: &[ServiceName]) -> Result<ServiceName, PassmanError> {
+    eprintln!("Available services:");
     for (i, service) in services.iter().enumerate() {
-        println!("  {}. {}", i + 1, service);
+        eprintln!("  {}. {}", i + 1, service);
     }
 
     let input = prompt_input("Enter number", false)?;
@@ -50,25 +184,50 @@ pub fn prompt_service_selection(services: &[String]) -> Result<String, PassmanEr
 }
 
 pub fn prompt_input(message: &str, is_password: bool) -> Result<String, PassmanError> {
-    print!("{}: ", message);
-    io::stdout().flush()?;
-
-    let input = if is_password {
-        rpassword::read_password()?
-    } else {
-        let mut buffer = String::new();
-        io::stdin().read_line(&mut buffer)?;
-        buffer
-    };
+    // Prompts go to stderr so stdout only ever carries what was asked for
+    eprint!("{}: ", message);
+    io::stderr().flush()?;
+
+    if is_password {
+        // Like `--stdin`, leading/trailing spaces may be part of the secret
+        let input = rpassword::read_password()?;
+        return Ok(input.trim_end_matches(['\r', '\n']).to_string());
+    }
 
-    Ok(input.trim().to_string())
+    let mut buffer = String::new();
+    io::stdin().read_line(&mut buffer)?;
+    Ok(buffer.trim().to_string())
 }
 
 pub fn prompt_master_password() -> Result<String, PassmanError> {
-    Ok(self::prompt_master_password_with_text("Master password")?)
+    prompt_master_password_with_text("Master password")
 }
 
+/// Reads the master password from `--password-fd`, `--password-file` or
+/// `PASSMAN_PASSWORD` when given, prompting on the terminal otherwise.
 pub fn prompt_master_password_with_text(text: &str) -> Result<String, PassmanError> {
+    let password = match (CACHED_MASTER_PASSWORD.get(), MASTER_PASSWORD_SOURCE.get()) {
+        (Some(password), _) => password.clone(),
+        (None, Some(source)) => {
+            // A descriptor can only be read once, keep the password for confirmations
+            let password = read_non_interactive_password(source)?;
+            CACHED_MASTER_PASSWORD.get_or_init(|| password).clone()
+        }
+        (None, None) => prompt_input(text, true)?,
+    };
+
+    if password.is_empty() {
+        return Err(PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Master password cannot be empty",
+        )));
+    }
+
+    Ok(password)
+}
+
+/// Always asks on the terminal, for passwords that must not come from the non-interactive source.
+pub fn prompt_new_master_password(text: &str) -> Result<String, PassmanError> {
     let password = prompt_input(text, true)?;
 
     if password.is_empty() {
@@ -81,47 +240,184 @@ pub fn prompt_master_password_with_text(text: &str) -> Result<String, PassmanErr
     Ok(password)
 }
 
-pub fn generate_random_password(length: usize) -> String {
-    const CHARSET: &[u8] =
-        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!@#$%^&*()_+-=[]{}|;:,.<>?";
+pub fn prompt_secret_with_confirmation(text: &str) -> Result<String, PassmanError> {
+    let secret = prompt_input(text, true)?;
+    let confirm_secret = prompt_input("Retype password", true)?;
 
-    let mut rng = rand::rng();
-    (0..length)
-        .map(|_| CHARSET[rng.random_range(0..CHARSET.len())] as char)
-        .collect()
+    if secret != confirm_secret {
+        return Err(PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Passwords do not match",
+        )));
+    }
+
+    if secret.is_empty() {
+        return Err(PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Password cannot be empty",
+        )));
+    }
+
+    Ok(secret)
+}
+
+pub fn read_secret_from_stdin() -> Result<String, PassmanError> {
+    let mut buffer = String::new();
+    io::stdin().lock().read_line(&mut buffer)?;
+
+    // Only strip the line ending, leading/trailing spaces may be part of the secret
+    let secret = buffer.trim_end_matches(['\r', '\n']).to_string();
+
+    if secret.is_empty() {
+        return Err(PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No password received on stdin",
+        )));
+    }
+
+    Ok(secret)
 }
 
 pub fn copy_to_clipboard(text: &str) -> Result<(), PassmanError> {
-    let mut clipboard = Clipboard::new().map_err(|e| {
-        PassmanError::IoError(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Failed to access clipboard: {}", e),
-        ))
-    })?;
+    ClipboardBackend::current().set_text(text)
+}
 
-    clipboard.set_text(text).map_err(|e| {
-        PassmanError::IoError(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Failed to copy to clipboard: {}", e),
-        ))
-    })?;
+/// Blocks for `seconds` then clears the clipboard, unless something else was copied meanwhile.
+pub fn clear_clipboard_after(text: &str, seconds: u64) -> Result<(), PassmanError> {
+    ClipboardBackend::current().clear_after(text, seconds)
+}
+
+/// Hands the secret to a background copy of passman that clears the clipboard later.
+/// The secret goes through a pipe, never the command line.
+pub fn spawn_clipboard_clearer(text: &str, seconds: u64) -> Result<(), PassmanError> {
+    let exe = std::env::current_exe()?;
+
+    let mut command = Command::new(exe);
+    command
+        .arg("--clipboard")
+        .arg(ClipboardBackend::current().name())
+        .arg(CLEAR_CLIPBOARD_COMMAND)
+        .arg(seconds.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // Detach from the terminal so closing it doesn't take the helper down
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x00000008;
+        command.creation_flags(DETACHED_PROCESS);
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
 
     Ok(())
 }
 
+/// Entry point of the helper started by `spawn_clipboard_clearer`.
+pub fn run_clipboard_clearer(seconds: u64) -> Result<(), PassmanError> {
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text)?;
+    clear_clipboard_after(&text, seconds)
+}
+
 pub fn print_help() {
     println!("USAGE:");
     println!("    passman <COMMAND> [OPTIONS]");
     println!();
     println!("COMMANDS:");
-    println!("    new <service>     Create new random password for a service");
-    println!("    get [service]     Retrieve password (copies to clipboard)");
+    println!("    init [--force]    Set up the vault and its master password");
+    println!("    passwd            Change the master password, re-encrypting every entry");
+    println!("    migrate           Upgrade password files to the current file format");
+    println!("    calibrate [--target-ms <ms>] [--max-memory <MiB>]");
+    println!("                      Pick key derivation costs for this machine (default {} ms, {} MiB) and save them", defaults::CALIBRATION_TARGET_MS, defaults::CALIBRATION_MAX_MEMORY_MIB);
+    println!("    upgrade-kdf       Re-protect the vault key and old entries that use weaker costs than the config");
+    println!("    new <service> [GENERATOR OPTIONS]");
+    println!("                      Create new random password for a service");
+    println!("    generate [GENERATOR OPTIONS]");
+    println!("                      Print a random password without storing it");
+    println!("    get [service] [--field <name>]");
+    println!("                      Retrieve password or another field (copies to clipboard)");
+    println!("    show <service>    Print username, URLs, notes and field names");
+    println!("    edit <service> [--username <u>] [--url <url>] [--clear-urls] [--notes <text>]");
+    println!("                   [--set <key>=<value>] [--unset <key>]");
+    println!("                      Update the details stored alongside a password");
+    println!("    register [service] [--stdin] [--force]");
+    println!("                      Store an existing password (prompted, or read from stdin)");
+    println!("    import --format <format> [--on-conflict skip|rename|overwrite] [--dry-run] <file>");
+    println!("                      Import an export: bitwarden-json, keepassxc-csv, chrome-csv, firefox-csv");
+    println!("                      generic-csv (name, username, password, url, notes columns) or kdbx");
+    println!("    export --out <file> [--format pmb|kdbx] [--force] [service...]");
+    println!("                      Back up all entries, or the given ones, under a separate passphrase (pmb),");
+    println!("                      or write them to a KeePass database (kdbx)");
+    println!("    restore <file> [--mode merge|replace] [--dry-run] [-y]");
+    println!("                      Restore a pmb backup, checking all of it before writing anything");
+    println!("    restore --list <file>");
+    println!("                      Show what a backup holds without restoring it");
+    println!("    rm <service> [-y] Delete a stored password (asks for confirmation)");
+    println!("    mv <from> <to>    Rename a stored password");
+    println!("    cp <from> <to>    Copy a stored password under a new name");
+    println!("    agent             Keep the vault unlocked in the background (--idle-timeout <s>)");
+    println!("    lock              Stop the agent, forgetting the master password");
+    println!("    names <encrypt|decrypt>");
+    println!("                      Hide service names behind random file names, or restore them");
+    println!("    doctor [--fix]    Check the vault for loose permissions, damaged files and weak settings");
+    println!("    config get <key> | set <key> <value> | list");
+    println!("                      Read or change settings in the config file");
     println!("    list              List all stored services");
     println!("    help              Show this help message");
     println!();
+    println!("GLOBAL OPTIONS:");
+    println!("    --password-fd <n>       Read the master password from file descriptor n");
+    println!("    --password-file <path>  Read the master password from a file");
+    println!("                            ({} is used when set and neither is given)", PASSWORD_ENV_VAR);
+    println!("    --vault <path>          Use the vault in this folder ({} does the same)", storage::VAULT_DIR_ENV_VAR);
+    println!("    --clipboard <kind>      system, osc52 (terminal clipboard, works over SSH) or auto");
+    println!("                            (default: osc52 when no display is available)");
+    println!();
+    println!("CLIPBOARD OPTIONS (new, get):");
+    println!("    --stdout          (get only) Print the secret instead of copying it, nothing else goes to stdout");
+    println!("    --clear-after <s> Clear the clipboard after this many seconds (default {}, 0 = never)", defaults::CLEAR_AFTER_SECS);
+    println!();
+    println!("GENERATOR OPTIONS (defaults can be changed with 'passman config'):");
+    println!("    --length, -l <n>  Password length (default {})", defaults::PASSWORD_LENGTH);
+    println!("    --no-lowercase, --no-uppercase, --no-digits, --no-symbols");
+    println!("                      Leave a character class out");
+    println!("    --symbols <set>   Use these symbols instead of the default set");
+    println!("    --exclude <chars> Never use these characters");
+    println!("    --no-ambiguous    Avoid look-alike characters such as 0O1l");
+    println!("    --min-each <n>    Minimum characters from each class (default 1)");
+    println!("    --passphrase      Generate words instead of random characters, then:");
+    println!("    --no-passphrase   Generate random characters when the config asks for words");
+    println!("      --words <n>     Number of words (default {})", defaults::WORD_COUNT);
+    println!("      --separator <s> Put this between words (default '{}')", defaults::SEPARATOR);
+    println!("      --capitalize    Capitalize every word");
+    println!("      --with-digit, --with-symbol");
+    println!("                      Add a digit or a symbol to a random word");
+    println!();
     println!("EXAMPLES:");
+    println!("    passman init");
     println!("    passman new github");
+    println!("    passman new github --length 32 --no-symbols --exclude '<>'");
+    println!("    passman generate --no-ambiguous");
+    println!("    passman new wifi --passphrase --words 5 --capitalize");
     println!("    passman get github");
     println!("    passman get              # Interactive selection");
+    println!("    passman get github --field username");
+    println!("    passman get github --stdout --password-file ~/.passman-pw | ssh-add -");
+    println!("    passman edit github --username octocat --url https://github.com");
+    println!("    passman register github");
+    println!("    echo \"$SECRET\" | passman register github --stdin");
+    println!("    passman mv github github-work");
+    println!("    passman rm github-work");
     println!("    passman list");
 }
@@ -0,0 +1,225 @@
+use crate::config::Config;
+use crate::error::PassmanError;
+use arboard::Clipboard;
+use base64ct::{Base64, Encoding};
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+// GNU screen drops DCS strings longer than this, longer sequences are sent in pieces
+const SCREEN_CHUNK_SIZE: usize = 76;
+
+static SELECTED_BACKEND: OnceLock<ClipboardBackend> = OnceLock::new();
+
+/// Where copied secrets end up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardBackend {
+    /// The desktop clipboard, through arboard
+    System,
+    /// The terminal's clipboard, through the OSC 52 escape sequence
+    Osc52,
+}
+
+impl ClipboardBackend {
+    /// The backend picked with `--clipboard` or in the config file,
+    /// otherwise OSC 52 when there is no display to talk to.
+    pub fn current() -> Self {
+        *SELECTED_BACKEND.get_or_init(|| Config::current().clipboard.unwrap_or_else(Self::detect))
+    }
+
+    /// Forces a backend for the rest of the process.
+    pub fn select(backend: Self) {
+        let _ = SELECTED_BACKEND.set(backend);
+    }
+
+    /// Picks a backend from the environment, ignoring the config file.
+    pub fn detect() -> Self {
+        let has_display = if cfg!(any(target_os = "windows", target_os = "macos")) {
+            true
+        } else {
+            std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some()
+        };
+
+        if has_display {
+            ClipboardBackend::System
+        } else {
+            ClipboardBackend::Osc52
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClipboardBackend::System => "system",
+            ClipboardBackend::Osc52 => "osc52",
+        }
+    }
+
+    pub fn set_text(&self, text: &str) -> Result<(), PassmanError> {
+        match self {
+            ClipboardBackend::System => {
+                let mut clipboard = open_system_clipboard()?;
+                clipboard.set_text(text).map_err(|e| {
+                    PassmanError::IoError(io::Error::other(format!(
+                        "Failed to copy to clipboard: {}",
+                        e
+                    )))
+                })
+            }
+            ClipboardBackend::Osc52 => write_to_terminal(&osc52_sequence(text, Multiplexer::detect())),
+        }
+    }
+
+    /// Blocks for `seconds` then clears the clipboard, unless something else was copied meanwhile.
+    pub fn clear_after(&self, text: &str, seconds: u64) -> Result<(), PassmanError> {
+        match self {
+            ClipboardBackend::System => {
+                let mut clipboard = open_system_clipboard()?;
+
+                // Owning the contents keeps them available on X11 after the CLI exits
+                let _ = clipboard.set_text(text);
+
+                thread::sleep(Duration::from_secs(seconds));
+
+                if clipboard.get_text().is_ok_and(|current| current == text) {
+                    let _ = clipboard.clear();
+                }
+
+                Ok(())
+            }
+            ClipboardBackend::Osc52 => {
+                thread::sleep(Duration::from_secs(seconds));
+
+                // Terminals rarely let us read the clipboard back, so this clears unconditionally
+                write_to_terminal(&osc52_sequence("", Multiplexer::detect()))
+            }
+        }
+    }
+}
+
+impl FromStr for ClipboardBackend {
+    type Err = PassmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(ClipboardBackend::System),
+            "osc52" => Ok(ClipboardBackend::Osc52),
+            _ => Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown clipboard '{}', expected 'system', 'osc52' or 'auto'", s),
+            ))),
+        }
+    }
+}
+
+fn open_system_clipboard() -> Result<Clipboard, PassmanError> {
+    Clipboard::new().map_err(|e| {
+        PassmanError::IoError(io::Error::other(format!(
+            "Failed to access clipboard: {} (try '--clipboard osc52' over SSH)",
+            e
+        )))
+    })
+}
+
+/// Terminal multiplexers that swallow escape sequences unless they are wrapped for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+impl Multiplexer {
+    fn detect() -> Option<Self> {
+        if std::env::var_os("TMUX").is_some() {
+            Some(Multiplexer::Tmux)
+        } else if std::env::var_os("STY").is_some() {
+            Some(Multiplexer::Screen)
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds the escape sequence setting the clipboard, wrapped for the multiplexer it goes through.
+fn osc52_sequence(text: &str, multiplexer: Option<Multiplexer>) -> Vec<u8> {
+    let sequence = format!("\x1b]52;c;{}\x07", Base64::encode_string(text.as_bytes()));
+
+    match multiplexer {
+        // tmux passes DCS content through as is once every ESC inside is doubled
+        Some(Multiplexer::Tmux) => {
+            format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")).into_bytes()
+        }
+        Some(Multiplexer::Screen) => {
+            let mut wrapped = Vec::new();
+            for chunk in sequence.as_bytes().chunks(SCREEN_CHUNK_SIZE) {
+                wrapped.extend_from_slice(b"\x1bP");
+                wrapped.extend_from_slice(chunk);
+                wrapped.extend_from_slice(b"\x1b\\");
+            }
+            wrapped
+        }
+        None => sequence.into_bytes(),
+    }
+}
+
+/// Writes to the controlling terminal, so it works even with stdout and stderr redirected.
+fn write_to_terminal(bytes: &[u8]) -> Result<(), PassmanError> {
+    #[cfg(unix)]
+    if let Ok(mut tty) = std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+        tty.write_all(bytes)?;
+        tty.flush()?;
+        return Ok(());
+    }
+
+    let mut stderr = io::stderr();
+    if !stderr.is_terminal() {
+        return Err(PassmanError::IoError(io::Error::other(
+            "No terminal to send the OSC 52 sequence to",
+        )));
+    }
+
+    stderr.write_all(bytes)?;
+    stderr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_text_in_base64() {
+        assert_eq!(osc52_sequence("hunter2", None), b"\x1b]52;c;aHVudGVyMg==\x07");
+        assert_eq!(osc52_sequence("pässword", None), b"\x1b]52;c;cMOkc3N3b3Jk\x07");
+        // Clearing sends an empty selection
+        assert_eq!(osc52_sequence("", None), b"\x1b]52;c;\x07");
+    }
+
+    #[test]
+    fn wraps_for_tmux() {
+        assert_eq!(
+            osc52_sequence("hunter2", Some(Multiplexer::Tmux)),
+            b"\x1bPtmux;\x1b\x1b]52;c;aHVudGVyMg==\x07\x1b\\"
+        );
+    }
+
+    #[test]
+    fn wraps_for_screen_in_chunks() {
+        assert_eq!(
+            osc52_sequence("hunter2", Some(Multiplexer::Screen)),
+            b"\x1bP\x1b]52;c;aHVudGVyMg==\x07\x1b\\"
+        );
+
+        // 60 bytes make 80 base64 characters, 88 bytes of sequence in all
+        let text = "a".repeat(60);
+        let sequence = osc52_sequence(&text, None);
+        assert_eq!(sequence.len(), 88);
+        let mut expected = b"\x1bP".to_vec();
+        expected.extend_from_slice(&sequence[..SCREEN_CHUNK_SIZE]);
+        expected.extend_from_slice(b"\x1b\\\x1bP");
+        expected.extend_from_slice(&sequence[SCREEN_CHUNK_SIZE..]);
+        expected.extend_from_slice(b"\x1b\\");
+        assert_eq!(osc52_sequence(&text, Some(Multiplexer::Screen)), expected);
+    }
+}
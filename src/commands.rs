@@ -1,54 +1,566 @@
 use crate::cli::{
-    copy_to_clipboard, generate_random_password, list_all_services, print_help,
-    prompt_master_password, prompt_master_password_with_text, prompt_service_selection,
+    CLEAR_CLIPBOARD_COMMAND, copy_to_clipboard, list_all_services,
+    print_help, run_clipboard_clearer, spawn_clipboard_clearer,
+    prompt_input, prompt_master_password, prompt_master_password_with_text, prompt_new_master_password,
+    prompt_secret_with_confirmation, prompt_service_selection, read_secret_from_stdin,
 };
+use crate::agent::{self, AGENT_COMMAND, DEFAULT_IDLE_TIMEOUT_SECS};
+use crate::config::{self, Config, KdfCosts};
+use crate::atomic;
+use crate::backup::{Backup, RestoreMode};
+use crate::calibrate;
+use crate::crypto;
+use crate::defaults;
+use crate::doctor;
+use crate::entry::{Entry, format_timestamp};
 use crate::error::PassmanError;
+use crate::generator::Generator;
+use crate::import::{self, Action, ConflictPolicy, ImportFormat};
+use crate::index::ServiceIndex;
+use crate::kdbx;
+use crate::service_name::ServiceName;
 use crate::storage::PassmanStorage;
-use std::io;
-
-// Default Argon2 parameters for production use
-const DEFAULT_M_COST: u32 = 65536; // 64 MiB
-const DEFAULT_T_COST: u32 = 10;    // 10 iterations
-const DEFAULT_P_COST: u32 = 2;     // 2 parallel threads
+use crate::vault::VaultMetadata;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use zeroize::Zeroizing;
 
 pub struct PassmanSession {
     storage: PassmanStorage,
+    kdf: KdfCosts,
 }
 
 impl PassmanSession {
     pub fn new(master_pwd: String) -> Result<Self, PassmanError> {
-        let storage = PassmanStorage::new(master_pwd);
-        Ok(Self { storage })
+        let storage = PassmanStorage::unlock(master_pwd)?;
+        Ok(Self {
+            storage,
+            kdf: Config::current().kdf,
+        })
     }
 
-    fn cmd_new(&self, service: &str) -> Result<(), PassmanError> {
+    fn cmd_new(
+        &self,
+        service: &ServiceName,
+        generator: &Generator,
+        clear_after: u64,
+    ) -> Result<(), PassmanError> {
         if self.storage.has_service(service) {
             println!("Service '{}' already exists.", service);
             return Ok(());
         }
 
-        let password = generate_random_password(20);
-        self.storage.store(service, &password, DEFAULT_M_COST, DEFAULT_T_COST, DEFAULT_P_COST)?;
+        let password = generator.generate()?;
+        self.storage.store(service, &password, self.kdf.m_cost, self.kdf.t_cost, self.kdf.p_cost)?;
 
+        println!(
+            "✓ New password created for '{}' ({:.0} bits of entropy)",
+            service,
+            generator.entropy_bits()
+        );
         copy_to_clipboard(&password)?;
-        println!("✓ New password created for '{}'", service);
         println!("Password copied to clipboard!");
+        schedule_clipboard_clear(&password, clear_after);
 
         Ok(())
     }
 
-    fn cmd_get(&self, service: &str) -> Result<(), PassmanError> {
-        let password = self.storage.retrieve(service)?;
-        copy_to_clipboard(&password)?;
-        println!("✓ Password for '{}' copied to clipboard!", service);
+    fn cmd_register(&self, service: &ServiceName, password: &str, force: bool) -> Result<(), PassmanError> {
+        if self.storage.has_service(service) && !force {
+            println!(
+                "Service '{}' already exists. Use --force to overwrite it.",
+                service
+            );
+            return Ok(());
+        }
+
+        // Overwriting only replaces the password, the rest of the entry is kept
+        if self.storage.has_service(service) {
+            let mut entry = self.storage.retrieve_entry(service)?;
+            entry.password = password.to_string();
+            entry.touch();
+            self.storage.replace_entry(service, &entry, self.kdf.m_cost, self.kdf.t_cost, self.kdf.p_cost)?;
+        } else {
+            let entry = Entry::new(password.to_string());
+            self.storage.store_entry(service, &entry, self.kdf.m_cost, self.kdf.t_cost, self.kdf.p_cost)?;
+        }
+        println!("✓ Password registered for '{}'", service);
+
+        Ok(())
+    }
+
+    fn cmd_remove(&self, service: &ServiceName) -> Result<(), PassmanError> {
+        self.storage.remove(service)?;
+        println!("✓ Removed '{}'", service);
+
+        Ok(())
+    }
+
+    fn cmd_move(&self, service: &ServiceName, new_service: &ServiceName) -> Result<(), PassmanError> {
+        self.storage.rename(service, new_service)?;
+        println!("✓ Renamed '{}' to '{}'", service, new_service);
+
+        Ok(())
+    }
+
+    fn cmd_copy(&self, service: &ServiceName, new_service: &ServiceName) -> Result<(), PassmanError> {
+        self.storage.copy(service, new_service)?;
+        println!("✓ Copied '{}' to '{}'", service, new_service);
+
+        Ok(())
+    }
+
+    fn cmd_passwd(&self, new_master_pwd: &str) -> Result<(), PassmanError> {
+        self.storage.rotate_master_password(
+            new_master_pwd,
+            self.kdf.m_cost,
+            self.kdf.t_cost,
+            self.kdf.p_cost,
+            |current, total, service| {
+                // Entries from before vault keys cost a key derivation each, keep the user posted
+                print!("\r\x1b[2K[{}/{}] Re-encrypting '{}'...", current, total, service);
+                let _ = io::stdout().flush();
+            },
+        )?;
+
+        println!();
+        println!("✓ Master password changed");
+
+        Ok(())
+    }
+
+    fn cmd_migrate(self) -> Result<(), PassmanError> {
+        // Vaults created before vault keys get one first, entries are then moved over to it
+        let had_vault_key = self.storage.vault_key()?.is_some();
+        let kdf = self.kdf;
+        let storage = self
+            .storage
+            .upgrade_vault_key(kdf.m_cost, kdf.t_cost, kdf.p_cost)?;
+        if !had_vault_key {
+            println!("✓ Vault upgraded to a vault key");
+        }
+
+        let services = storage.list_services()?;
+        let mut upgraded = 0;
+
+        for (i, service) in services.iter().enumerate() {
+            print!("\r\x1b[2K[{}/{}] Checking '{}'...", i + 1, services.len(), service);
+            io::stdout().flush()?;
+
+            if storage.migrate(service, kdf.m_cost, kdf.t_cost, kdf.p_cost)? {
+                upgraded += 1;
+            }
+        }
+
+        if !services.is_empty() {
+            println!();
+        }
+        println!("✓ Upgraded {} of {} entries to the current file format", upgraded, services.len());
+
+        Ok(())
+    }
+
+    fn cmd_upgrade_kdf(&self) -> Result<(), PassmanError> {
+        let kdf = self.kdf;
+        let storage_path = PassmanStorage::get_default_path();
+        let metadata = VaultMetadata::load(&storage_path)?;
+        if !metadata.has_vault_key() {
+            return Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "This vault has no vault key yet, run 'passman migrate' first",
+            )));
+        }
+
+        let params = metadata.kdf_params();
+        if params.is_weaker_than(kdf.m_cost, kdf.t_cost, kdf.p_cost) {
+            self.storage.rewrap_vault_key(kdf.m_cost, kdf.t_cost, kdf.p_cost)?;
+            println!(
+                "✓ Vault key now protected with {} KiB, {} iterations and {} lanes (was {} KiB, {} and {})",
+                kdf.m_cost, kdf.t_cost, kdf.p_cost, params.m_cost, params.t_cost, params.p_cost
+            );
+        } else {
+            println!("✓ Vault key already meets the configured costs");
+        }
+
+        // Entries from before the vault key carry their own Argon2 costs
+        let services = self.storage.list_services()?;
+        let mut upgraded = 0;
+
+        for (i, service) in services.iter().enumerate() {
+            print!("\r\x1b[2K[{}/{}] Checking '{}'...", i + 1, services.len(), service);
+            io::stdout().flush()?;
+
+            if self
+                .storage
+                .upgrade_entry_kdf(service, kdf.m_cost, kdf.t_cost, kdf.p_cost)?
+            {
+                upgraded += 1;
+            }
+        }
+
+        if !services.is_empty() {
+            println!();
+        }
+        println!("✓ Re-encrypted {} of {} entries that used weaker key derivation", upgraded, services.len());
+
+        Ok(())
+    }
+
+    fn cmd_import(
+        &self,
+        path: &Path,
+        format: ImportFormat,
+        policy: ConflictPolicy,
+        dry_run: bool,
+    ) -> Result<(), PassmanError> {
+        // Read straight into memory and wiped afterwards, the export is never copied
+        let data = Zeroizing::new(fs::read(path)?);
+        let password = match format.needs_password() {
+            true => Some(Zeroizing::new(prompt_input("Password of the file to import", true)?)),
+            false => None,
+        };
+        let (imported, mut rejected) = import::parse(format, &data, password.as_deref().map(String::as_str))?;
+        drop(data);
+
+        let existing = self.storage.list_services()?;
+        let planned = import::plan(imported, &existing, policy, &mut rejected);
+
+        let mut written = 0;
+        let mut skipped = 0;
+        for item in &planned {
+            let write = match &item.action {
+                Action::Create => {
+                    println!("  + {}", item.service);
+                    Some(false)
+                }
+                Action::Overwrite => {
+                    println!("  ~ {} (overwrites the existing entry)", item.service);
+                    Some(true)
+                }
+                Action::Rename { original } => {
+                    println!("  + {} (renamed, '{}' exists)", item.service, original);
+                    Some(false)
+                }
+                Action::Skip => {
+                    println!("  = {} (skipped, the name is taken)", item.service);
+                    None
+                }
+            };
+
+            let Some(replace) = write else {
+                skipped += 1;
+                continue;
+            };
+            if dry_run {
+                written += 1;
+                continue;
+            }
+
+            let kdf = self.kdf;
+            if replace {
+                self.storage.replace_entry(&item.service, &item.entry, kdf.m_cost, kdf.t_cost, kdf.p_cost)?;
+            } else {
+                self.storage.store_entry(&item.service, &item.entry, kdf.m_cost, kdf.t_cost, kdf.p_cost)?;
+            }
+            written += 1;
+        }
+
+        for rejected in &rejected {
+            println!("  ! {}: {}", rejected.origin, rejected.reason);
+        }
+
+        let verb = if dry_run { "Would import" } else { "✓ Imported" };
+        println!(
+            "{} {} entries, {} skipped, {} not importable",
+            verb,
+            written,
+            skipped,
+            rejected.len()
+        );
+        if dry_run {
+            println!("Nothing was written, run again without --dry-run to import.");
+        } else if !format.needs_password() {
+            println!("Delete {} once you no longer need it, it holds your passwords in plain text.", path.display());
+        }
+
+        Ok(())
+    }
+
+    fn cmd_export(
+        &self,
+        out: &Path,
+        format: ExportFormat,
+        services: &[ServiceName],
+        force: bool,
+    ) -> Result<(), PassmanError> {
+        if out.exists() && !force {
+            println!("{} already exists. Use --force to overwrite it.", out.display());
+            return Ok(());
+        }
+
+        if let Some(service) = services.iter().find(|s| !self.storage.has_service(s)) {
+            println!("Service '{}' not found.", service);
+            return Ok(());
+        }
+        let services = if services.is_empty() {
+            self.storage.list_services()?
+        } else {
+            services.to_vec()
+        };
+
+        let mut entries = Vec::with_capacity(services.len());
+        for service in services {
+            let entry = self.storage.retrieve_entry(&service)?;
+            entries.push((service, entry));
+        }
+
+        let count = entries.len();
+        let data = match format {
+            ExportFormat::Backup => {
+                let passphrase = Zeroizing::new(prompt_secret_with_confirmation("Passphrase for the backup")?);
+                // Whoever holds the backup should not also hold the key to the vault
+                if *passphrase == self.storage.master_password {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Use a passphrase different from the master password",
+                    )));
+                }
+                let kdf = self.kdf;
+                Backup::new(entries).seal(&passphrase, kdf.m_cost, kdf.t_cost, kdf.p_cost)?
+            }
+            ExportFormat::Kdbx => {
+                let password = Zeroizing::new(prompt_secret_with_confirmation("Password for the KeePass database")?);
+                kdbx::write(&entries, &password, self.kdf)?
+            }
+        };
+        atomic::write_file(out, &data)?;
+
+        println!("✓ Exported {} entries to {}", count, out.display());
+        Ok(())
+    }
+
+    fn cmd_restore(&self, backup: Backup, mode: RestoreMode, dry_run: bool) -> Result<(), PassmanError> {
+        let existing = self.storage.list_services()?;
+        let kdf = self.kdf;
+
+        let mut written = 0;
+        let mut kept = 0;
+        for (service, entry) in &backup.entries {
+            let replace = if !existing.contains(service) {
+                println!("  + {}", service);
+                false
+            } else if mode == RestoreMode::Replace {
+                println!("  ~ {} (replaced with the backup copy)", service);
+                true
+            } else if entry.modified > self.storage.retrieve_entry(service)?.modified {
+                println!("  ~ {} (the backup copy is newer)", service);
+                true
+            } else {
+                println!("  = {} (kept, the vault copy is as recent)", service);
+                kept += 1;
+                continue;
+            };
+
+            if !dry_run {
+                if replace {
+                    self.storage.replace_entry(service, entry, kdf.m_cost, kdf.t_cost, kdf.p_cost)?;
+                } else {
+                    self.storage.store_entry(service, entry, kdf.m_cost, kdf.t_cost, kdf.p_cost)?;
+                }
+            }
+            written += 1;
+        }
+
+        let mut removed = 0;
+        if mode == RestoreMode::Replace {
+            for service in existing.iter().filter(|s| !backup.entries.iter().any(|(b, _)| b == *s)) {
+                println!("  - {} (not in the backup)", service);
+                if !dry_run {
+                    self.storage.remove(service)?;
+                }
+                removed += 1;
+            }
+        }
+
+        let verb = if dry_run { "Would restore" } else { "✓ Restored" };
+        println!("{} {} entries, {} kept, {} removed", verb, written, kept, removed);
+        if dry_run {
+            println!("Nothing was written, run again without --dry-run to restore.");
+        }
 
         Ok(())
     }
+
+    fn cmd_names(&self, encrypt: bool) -> Result<(), PassmanError> {
+        if encrypt {
+            let moved = self.storage.encrypt_service_names()?;
+            println!("✓ Service names encrypted, {} files renamed", moved);
+            println!("Listing the vault now needs the master password.");
+        } else {
+            let moved = self.storage.decrypt_service_names()?;
+            println!("✓ Service names stored in plain again, {} files renamed", moved);
+        }
+
+        Ok(())
+    }
+
+    fn cmd_get(
+        &self,
+        service: &ServiceName,
+        field: Option<&str>,
+        clear_after: u64,
+        to_stdout: bool,
+    ) -> Result<(), PassmanError> {
+        let entry = self.storage.retrieve_entry(service)?;
+        output_entry_field(service, &entry, field, clear_after, to_stdout)
+    }
+
+    fn cmd_show(&self, service: &ServiceName) -> Result<(), PassmanError> {
+        let entry = self.storage.retrieve_entry(service)?;
+        print_entry(service, &entry);
+        Ok(())
+    }
+
+    fn cmd_edit(&self, service: &ServiceName, edit: &EntryEdit) -> Result<(), PassmanError> {
+        let mut entry = self.storage.retrieve_entry(service)?;
+
+        if let Some(username) = &edit.username {
+            entry.username = (!username.is_empty()).then(|| username.clone());
+        }
+        if edit.clear_urls {
+            entry.urls.clear();
+        }
+        entry.urls.extend(edit.add_urls.iter().cloned());
+        if let Some(notes) = &edit.notes {
+            entry.notes = (!notes.is_empty()).then(|| notes.clone());
+        }
+        for (key, value) in &edit.set_fields {
+            entry.set_field(key, value);
+        }
+        for key in &edit.remove_fields {
+            if !entry.remove_field(key) {
+                println!("Field '{}' not found, skipping.", key);
+            }
+        }
+        entry.touch();
+
+        self.storage.replace_entry(service, &entry, self.kdf.m_cost, self.kdf.t_cost, self.kdf.p_cost)?;
+        println!("✓ Updated '{}'", service);
+
+        Ok(())
+    }
+}
+
+/// Changes requested by `passman edit`, applied on top of the stored entry.
+#[derive(Default)]
+pub struct EntryEdit {
+    pub username: Option<String>,
+    pub add_urls: Vec<String>,
+    pub clear_urls: bool,
+    pub notes: Option<String>,
+    pub set_fields: Vec<(String, String)>,
+    pub remove_fields: Vec<String>,
+}
+
+impl EntryEdit {
+    fn is_empty(&self) -> bool {
+        self.username.is_none()
+            && self.add_urls.is_empty()
+            && !self.clear_urls
+            && self.notes.is_none()
+            && self.set_fields.is_empty()
+            && self.remove_fields.is_empty()
+    }
+}
+
+/// What `passman export` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// A passman backup, restored with `passman restore`
+    #[default]
+    Backup,
+    Kdbx,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = PassmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pmb" => Ok(ExportFormat::Backup),
+            "kdbx" => Ok(ExportFormat::Kdbx),
+            _ => Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown export format '{}', expected 'pmb' or 'kdbx'", s),
+            ))),
+        }
+    }
+}
+
+/// What `passman config` should do.
+pub enum ConfigAction {
+    Get { key: String },
+    Set { key: String, value: String },
+    List,
 }
 
 pub enum CommandType {
-    New { service: String },
-    Get { service: Option<String> },
+    Init { force: bool },
+    Passwd,
+    Migrate,
+    Calibrate { target_ms: u64, max_memory_mib: u32 },
+    UpgradeKdf,
+    Import {
+        path: PathBuf,
+        format: ImportFormat,
+        policy: ConflictPolicy,
+        dry_run: bool,
+    },
+    Export {
+        out: PathBuf,
+        format: ExportFormat,
+        services: Vec<ServiceName>,
+        force: bool,
+    },
+    Restore {
+        path: PathBuf,
+        mode: RestoreMode,
+        list: bool,
+        dry_run: bool,
+        assume_yes: bool,
+    },
+    New {
+        service: ServiceName,
+        generator: Generator,
+        clear_after: u64,
+    },
+    Generate { generator: Generator },
+    ClearClipboard { seconds: u64 },
+    Get {
+        service: Option<ServiceName>,
+        field: Option<String>,
+        clear_after: u64,
+        to_stdout: bool,
+    },
+    Show { service: ServiceName },
+    Edit { service: ServiceName, edit: EntryEdit },
+    Register {
+        service: Option<ServiceName>,
+        force: bool,
+        from_stdin: bool,
+    },
+    Remove { service: ServiceName, assume_yes: bool },
+    Move { service: ServiceName, new_service: ServiceName },
+    Copy { service: ServiceName, new_service: ServiceName },
+    Names { encrypt: bool },
+    Agent { idle_timeout: u64 },
+    RunAgent { idle_timeout: u64 },
+    Lock,
+    Doctor { fix: bool },
+    Config { action: ConfigAction },
     List,
     Help,
 }
@@ -65,22 +577,378 @@ impl CommandType {
         let cmd = args[1].as_str();
 
         match cmd {
+            "init" => {
+                let force = args[2..].iter().any(|a| a == "--force" || a == "-f");
+                Ok(CommandType::Init { force })
+            }
+            "passwd" => Ok(CommandType::Passwd),
+            "migrate" => Ok(CommandType::Migrate),
+            "calibrate" => {
+                let mut target_ms = defaults::CALIBRATION_TARGET_MS;
+                let mut max_memory_mib = defaults::CALIBRATION_MAX_MEMORY_MIB;
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    match arg.as_str() {
+                        "--target-ms" => target_ms = next_number(&mut iter, arg)?,
+                        "--max-memory" => max_memory_mib = next_number(&mut iter, arg)?,
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                if target_ms == 0 || max_memory_mib == 0 {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "'--target-ms' and '--max-memory' must be at least 1",
+                    )));
+                }
+
+                Ok(CommandType::Calibrate {
+                    target_ms,
+                    max_memory_mib,
+                })
+            }
+            "upgrade-kdf" => Ok(CommandType::UpgradeKdf),
+            "import" => {
+                let mut path = None;
+                let mut format = None;
+                let mut policy = ConflictPolicy::default();
+                let mut dry_run = false;
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    match arg.as_str() {
+                        "--format" => format = Some(next_value(&mut iter, arg)?.parse()?),
+                        "--on-conflict" => policy = next_value(&mut iter, arg)?.parse()?,
+                        "--dry-run" | "-n" => dry_run = true,
+                        _ if arg.starts_with('-') => return Err(unexpected_argument(arg)),
+                        _ if path.is_none() => path = Some(PathBuf::from(arg)),
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                let (Some(path), Some(format)) = (path, format) else {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Usage: passman import --format <format> [--on-conflict skip|rename|overwrite] [--dry-run] <file>",
+                    )));
+                };
+
+                Ok(CommandType::Import {
+                    path,
+                    format,
+                    policy,
+                    dry_run,
+                })
+            }
+            "export" => {
+                let mut out = None;
+                let mut format = ExportFormat::default();
+                let mut services = Vec::new();
+                let mut force = false;
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    match arg.as_str() {
+                        "--out" | "-o" => out = Some(PathBuf::from(next_value(&mut iter, arg)?)),
+                        "--format" => format = next_value(&mut iter, arg)?.parse()?,
+                        "--force" | "-f" => force = true,
+                        _ if arg.starts_with('-') => return Err(unexpected_argument(arg)),
+                        _ => services.push(ServiceName::new(arg)?),
+                    }
+                }
+
+                let Some(out) = out else {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Usage: passman export --out <file> [--format pmb|kdbx] [--force] [service...]",
+                    )));
+                };
+
+                Ok(CommandType::Export {
+                    out,
+                    format,
+                    services,
+                    force,
+                })
+            }
+            "restore" => {
+                let mut path = None;
+                let mut mode = RestoreMode::default();
+                let mut list = false;
+                let mut dry_run = false;
+                let mut assume_yes = false;
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    match arg.as_str() {
+                        "--mode" => mode = next_value(&mut iter, arg)?.parse()?,
+                        "--list" | "-l" => list = true,
+                        "--dry-run" | "-n" => dry_run = true,
+                        "--yes" | "-y" => assume_yes = true,
+                        _ if arg.starts_with('-') => return Err(unexpected_argument(arg)),
+                        _ if path.is_none() => path = Some(PathBuf::from(arg)),
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                let Some(path) = path else {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Usage: passman restore [--mode merge|replace] [--list] [--dry-run] [-y] <file>",
+                    )));
+                };
+
+                Ok(CommandType::Restore {
+                    path,
+                    mode,
+                    list,
+                    dry_run,
+                    assume_yes,
+                })
+            }
             "new" => {
-                let service = args
-                    .get(2)
-                    .ok_or_else(|| {
-                        PassmanError::IoError(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "Service name required for 'new' command",
-                        ))
-                    })?
-                    .clone();
-                Ok(CommandType::New { service })
+                let mut service = None;
+                let mut generator = Config::current().generator.clone();
+                let mut clear_after = Config::current().clear_after;
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    if generator.apply_flag(arg, &mut iter)? {
+                        continue;
+                    }
+                    match arg.as_str() {
+                        "--clear-after" => clear_after = next_seconds(&mut iter, arg)?,
+                        _ if service.is_none() => service = Some(ServiceName::new(arg)?),
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                let service = service.ok_or_else(|| {
+                    PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Service name required for 'new' command",
+                    ))
+                })?;
+
+                // Catch a bad policy before asking for the master password
+                generator.validate()?;
+                Ok(CommandType::New {
+                    service,
+                    generator,
+                    clear_after,
+                })
+            }
+            "generate" | "gen" => {
+                let mut generator = Config::current().generator.clone();
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    if !generator.apply_flag(arg, &mut iter)? {
+                        return Err(unexpected_argument(arg));
+                    }
+                }
+
+                generator.validate()?;
+                Ok(CommandType::Generate { generator })
             }
             "get" => {
-                let service = args.get(2).cloned();
-                Ok(CommandType::Get { service })
+                let mut service = None;
+                let mut field = None;
+                let mut clear_after = Config::current().clear_after;
+                let mut to_stdout = false;
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    match arg.as_str() {
+                        "--field" => field = Some(next_value(&mut iter, "--field")?),
+                        "--stdout" => to_stdout = true,
+                        "--clear-after" => clear_after = next_seconds(&mut iter, arg)?,
+                        _ if service.is_none() => service = Some(ServiceName::new(arg)?),
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                Ok(CommandType::Get {
+                    service,
+                    field,
+                    clear_after,
+                    to_stdout,
+                })
+            }
+            "show" => {
+                let service = args.get(2).ok_or_else(|| {
+                    PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Service name required for 'show' command",
+                    ))
+                })?;
+                Ok(CommandType::Show {
+                    service: ServiceName::new(service)?,
+                })
+            }
+            "edit" => {
+                let mut service = None;
+                let mut edit = EntryEdit::default();
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    match arg.as_str() {
+                        "--username" => edit.username = Some(next_value(&mut iter, arg)?),
+                        "--url" => edit.add_urls.push(next_value(&mut iter, arg)?),
+                        "--clear-urls" => edit.clear_urls = true,
+                        "--notes" => edit.notes = Some(next_value(&mut iter, arg)?),
+                        "--set" => {
+                            let value = next_value(&mut iter, arg)?;
+                            let (key, value) = value.split_once('=').ok_or_else(|| {
+                                PassmanError::IoError(io::Error::new(
+                                    io::ErrorKind::InvalidInput,
+                                    "--set expects KEY=VALUE",
+                                ))
+                            })?;
+                            edit.set_fields.push((key.to_string(), value.to_string()));
+                        }
+                        "--unset" => edit.remove_fields.push(next_value(&mut iter, arg)?),
+                        _ if service.is_none() => service = Some(ServiceName::new(arg)?),
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                let service = service.ok_or_else(|| {
+                    PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Service name required for 'edit' command",
+                    ))
+                })?;
+
+                if edit.is_empty() {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Nothing to edit, see 'passman help'",
+                    )));
+                }
+
+                Ok(CommandType::Edit { service, edit })
+            }
+            "register" => {
+                let mut service = None;
+                let mut force = false;
+                let mut from_stdin = false;
+
+                for arg in &args[2..] {
+                    match arg.as_str() {
+                        "--force" | "-f" => force = true,
+                        "--stdin" => from_stdin = true,
+                        _ if service.is_none() => service = Some(ServiceName::new(arg)?),
+                        _ => {
+                            return Err(PassmanError::IoError(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "Passwords are not accepted as arguments, you will be prompted for it (or use --stdin)",
+                            )));
+                        }
+                    }
+                }
+
+                Ok(CommandType::Register {
+                    service,
+                    force,
+                    from_stdin,
+                })
+            }
+            "rm" | "remove" => {
+                let mut service = None;
+                let mut assume_yes = false;
+
+                for arg in &args[2..] {
+                    match arg.as_str() {
+                        "--yes" | "-y" => assume_yes = true,
+                        _ if service.is_none() => service = Some(ServiceName::new(arg)?),
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                let service = service.ok_or_else(|| {
+                    PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Service name required for 'rm' command",
+                    ))
+                })?;
+                Ok(CommandType::Remove {
+                    service,
+                    assume_yes,
+                })
+            }
+            "mv" | "move" => {
+                let (service, new_service) = parse_source_and_target(args, "mv")?;
+                Ok(CommandType::Move {
+                    service,
+                    new_service,
+                })
+            }
+            "cp" | "copy" => {
+                let (service, new_service) = parse_source_and_target(args, "cp")?;
+                Ok(CommandType::Copy {
+                    service,
+                    new_service,
+                })
+            }
+            CLEAR_CLIPBOARD_COMMAND => {
+                let seconds = next_seconds(&mut args[2..].iter(), cmd)?;
+                Ok(CommandType::ClearClipboard { seconds })
+            }
+            "agent" => {
+                let mut idle_timeout = DEFAULT_IDLE_TIMEOUT_SECS;
+
+                let mut iter = args[2..].iter();
+                while let Some(arg) = iter.next() {
+                    match arg.as_str() {
+                        "--idle-timeout" => idle_timeout = next_seconds(&mut iter, arg)?,
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+
+                Ok(CommandType::Agent { idle_timeout })
+            }
+            AGENT_COMMAND => {
+                let idle_timeout = next_seconds(&mut args[2..].iter(), cmd)?;
+                Ok(CommandType::RunAgent { idle_timeout })
             }
+            "lock" => Ok(CommandType::Lock),
+            "doctor" => {
+                let mut fix = false;
+                for arg in &args[2..] {
+                    match arg.as_str() {
+                        "--fix" => fix = true,
+                        _ => return Err(unexpected_argument(arg)),
+                    }
+                }
+                Ok(CommandType::Doctor { fix })
+            }
+            "config" => {
+                let action = match &args[2..] {
+                    [action, key] if action == "get" => ConfigAction::Get { key: key.clone() },
+                    [action, key, value] if action == "set" => ConfigAction::Set {
+                        key: key.clone(),
+                        value: value.clone(),
+                    },
+                    [action] if action == "list" => ConfigAction::List,
+                    _ => {
+                        return Err(PassmanError::IoError(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Usage: passman config get <key> | set <key> <value> | list",
+                        )));
+                    }
+                };
+                Ok(CommandType::Config { action })
+            }
+            "names" => match args.get(2).map(String::as_str) {
+                Some("encrypt") => Ok(CommandType::Names { encrypt: true }),
+                Some("decrypt") => Ok(CommandType::Names { encrypt: false }),
+                _ => Err(PassmanError::IoError(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "'names' expects 'encrypt' or 'decrypt'",
+                ))),
+            },
             "list" | "ls" => Ok(CommandType::List),
             "help" | "--help" | "-h" => Ok(CommandType::Help),
             _ => Err(PassmanError::IoError(io::Error::new(
@@ -91,12 +959,57 @@ impl CommandType {
     }
 
     pub fn requires_auth(&self) -> bool {
-        matches!(self, CommandType::New { .. } | CommandType::Get { .. })
+        matches!(
+            self,
+            CommandType::Init { .. }
+                | CommandType::Passwd
+                | CommandType::Migrate
+                | CommandType::UpgradeKdf
+                | CommandType::Import { .. }
+                | CommandType::Export { .. }
+                | CommandType::Restore { list: false, .. }
+                | CommandType::New { .. }
+                | CommandType::Get { .. }
+                | CommandType::Show { .. }
+                | CommandType::Edit { .. }
+                | CommandType::Register { .. }
+                | CommandType::Remove { .. }
+                | CommandType::Move { .. }
+                | CommandType::Copy { .. }
+                | CommandType::Agent { .. }
+                | CommandType::Names { .. }
+        )
     }
 
     pub fn execute_public(&self) -> Result<(), PassmanError> {
         match self {
             CommandType::List => cmd_list(),
+            CommandType::Doctor { fix } => cmd_doctor(*fix),
+            CommandType::Config { action } => cmd_config(action),
+            CommandType::Calibrate {
+                target_ms,
+                max_memory_mib,
+            } => cmd_calibrate(*target_ms, *max_memory_mib),
+            CommandType::Restore { path, .. } => cmd_restore_list(path),
+            CommandType::ClearClipboard { seconds } => run_clipboard_clearer(*seconds),
+            CommandType::RunAgent { idle_timeout } => {
+                let mut vault_key = Zeroizing::new([0u8; 32]);
+                io::stdin().read_exact(vault_key.as_mut())?;
+                agent::serve(PassmanStorage::get_default_path(), *vault_key, *idle_timeout)
+            }
+            CommandType::Lock => {
+                if agent::lock()? {
+                    println!("✓ Agent locked.");
+                } else {
+                    println!("No agent running.");
+                }
+                Ok(())
+            }
+            CommandType::Generate { generator } => {
+                println!("{}", generator.generate()?);
+                eprintln!("({:.0} bits of entropy)", generator.entropy_bits());
+                Ok(())
+            }
             CommandType::Help => {
                 print_help();
                 Ok(())
@@ -107,14 +1020,73 @@ impl CommandType {
 
     pub fn execute_with_auth(&self) -> Result<(), PassmanError> {
         match self {
-            CommandType::New { service } => cmd_new_with_auth(service),
-            CommandType::Get { service } => cmd_get_with_auth(service.as_deref()),
+            CommandType::Init { force } => cmd_init_with_auth(*force),
+            CommandType::Passwd => cmd_passwd_with_auth(),
+            CommandType::Agent { idle_timeout } => cmd_agent_with_auth(*idle_timeout),
+            CommandType::Names { encrypt } => cmd_names_with_auth(*encrypt),
+            CommandType::Migrate => cmd_migrate_with_auth(),
+            CommandType::UpgradeKdf => cmd_upgrade_kdf_with_auth(),
+            CommandType::Import {
+                path,
+                format,
+                policy,
+                dry_run,
+            } => cmd_import_with_auth(path, *format, *policy, *dry_run),
+            CommandType::Export {
+                out,
+                format,
+                services,
+                force,
+            } => cmd_export_with_auth(out, *format, services, *force),
+            CommandType::Restore {
+                path,
+                mode,
+                dry_run,
+                assume_yes,
+                ..
+            } => cmd_restore_with_auth(path, *mode, *dry_run, *assume_yes),
+            CommandType::New {
+                service,
+                generator,
+                clear_after,
+            } => cmd_new_with_auth(service, generator, *clear_after),
+            CommandType::Get {
+                service,
+                field,
+                clear_after,
+                to_stdout,
+            } => cmd_get_with_auth(service.as_ref(), field.as_deref(), *clear_after, *to_stdout),
+            CommandType::Show { service } => cmd_show_with_auth(service),
+            CommandType::Edit { service, edit } => cmd_edit_with_auth(service, edit),
+            CommandType::Register {
+                service,
+                force,
+                from_stdin,
+            } => cmd_register_with_auth(service.as_ref(), *force, *from_stdin),
+            CommandType::Remove {
+                service,
+                assume_yes,
+            } => cmd_remove_with_auth(service, *assume_yes),
+            CommandType::Move {
+                service,
+                new_service,
+            } => cmd_move_with_auth(service, new_service),
+            CommandType::Copy {
+                service,
+                new_service,
+            } => cmd_copy_with_auth(service, new_service),
             _ => unreachable!("Only authenticated commands should reach here"),
         }
     }
 }
 
-fn cmd_new_with_auth(service: &str) -> Result<(), PassmanError> {
+fn cmd_init_with_auth(force: bool) -> Result<(), PassmanError> {
+    let storage_path = PassmanStorage::get_default_path();
+    if VaultMetadata::exists(&storage_path) {
+        println!("Vault already initialized at {}", storage_path.display());
+        return Ok(());
+    }
+
     let master_pwd = prompt_master_password()?;
     let confirm_master_pwd = prompt_master_password_with_text("Retype master password")?;
 
@@ -123,41 +1095,641 @@ fn cmd_new_with_auth(service: &str) -> Result<(), PassmanError> {
         return Ok(());
     }
 
+    // Entries created before the vault had a verifier may use another master password
+    let storage = PassmanStorage::with_path(master_pwd, storage_path);
+    let services = list_all_services()?;
+    if !services.is_empty() {
+        println!("Checking {} existing entries...", services.len());
+    }
+
+    let mismatched: Vec<&ServiceName> = services
+        .iter()
+        .filter(|service| storage.retrieve(service).is_err())
+        .collect();
+
+    if !mismatched.is_empty() {
+        println!("These entries can't be opened with this master password:");
+        for service in &mismatched {
+            println!("  - {}", service);
+        }
+
+        if !force {
+            println!("Vault not initialized. Use --force to initialize anyway.");
+            return Ok(());
+        }
+    }
+
+    let vault_key = crypto::gen_vault_key();
+    let kdf = Config::current().kdf;
+    VaultMetadata::create(
+        &storage.master_password,
+        &vault_key,
+        kdf.m_cost,
+        kdf.t_cost,
+        kdf.p_cost,
+    )?
+    .save(&storage.storage_path)?;
+    println!("✓ Vault initialized at {}", storage.storage_path.display());
+
+    Ok(())
+}
+
+fn cmd_passwd_with_auth() -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password_with_text("Current master password")?;
+    let session = PassmanSession::new(master_pwd)?;
+
+    let new_master_pwd = prompt_new_master_password("New master password")?;
+    let confirm_new_master_pwd = prompt_new_master_password("Retype new master password")?;
+
+    if new_master_pwd != confirm_new_master_pwd {
+        println!("Master passwords do not match");
+        return Ok(());
+    }
+
+    session.cmd_passwd(&new_master_pwd)?;
+
+    // A running agent still holds the old password
+    let _ = agent::lock();
+    Ok(())
+}
+
+fn cmd_agent_with_auth(idle_timeout: u64) -> Result<(), PassmanError> {
+    if agent::is_running() {
+        println!("Agent is already running. Use 'passman lock' to stop it.");
+        return Ok(());
+    }
+
+    // Unlocked here, the agent only ever gets the vault key and never the master password
+    let storage = PassmanStorage::unlock(prompt_master_password()?)?;
+    let vault_key = Zeroizing::new(storage.vault_key()?.ok_or_else(|| {
+        PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The agent needs a vault key, run 'passman migrate' first",
+        ))
+    })?);
+
+    let exe = std::env::current_exe()?;
+    let mut command = Command::new(exe);
+    command
+        .arg("--vault")
+        .arg(PassmanStorage::get_default_path())
+        .arg(AGENT_COMMAND)
+        .arg(idle_timeout.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // Detach from the terminal so closing it doesn't take the agent down
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(vault_key.as_ref())?;
+    }
+
+    // Give it a moment to bind the socket so the next command finds it
+    for _ in 0..50 {
+        if agent::is_running() {
+            println!(
+                "✓ Agent started, it locks after {} seconds of inactivity.",
+                idle_timeout
+            );
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(PassmanError::IoError(io::Error::other(format!(
+                "Agent exited early ({})",
+                status
+            ))));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    Err(PassmanError::IoError(io::Error::other(
+        "Agent did not start listening in time",
+    )))
+}
+
+fn cmd_migrate_with_auth() -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+    session.cmd_migrate()
+}
+
+fn cmd_upgrade_kdf_with_auth() -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+    session.cmd_upgrade_kdf()
+}
+
+fn cmd_import_with_auth(
+    path: &Path,
+    format: ImportFormat,
+    policy: ConflictPolicy,
+    dry_run: bool,
+) -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+    session.cmd_import(path, format, policy, dry_run)
+}
+
+fn cmd_export_with_auth(
+    out: &Path,
+    format: ExportFormat,
+    services: &[ServiceName],
+    force: bool,
+) -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+    session.cmd_export(out, format, services, force)
+}
+
+/// Reads and fully checks a backup, before anything is written from it.
+fn open_backup(path: &Path) -> Result<Backup, PassmanError> {
+    let data = fs::read(path)?;
+    let passphrase = Zeroizing::new(prompt_input("Passphrase of the backup", true)?);
+    Backup::open(&data, &passphrase)
+}
+
+fn cmd_restore_with_auth(path: &Path, mode: RestoreMode, dry_run: bool, assume_yes: bool) -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+    let backup = open_backup(path)?;
+
+    println!(
+        "Backup from {}, {} entries",
+        format_timestamp(backup.created),
+        backup.entries.len()
+    );
+
+    if mode == RestoreMode::Replace && !dry_run && !assume_yes {
+        let answer = prompt_input(
+            "Replace the vault with the backup? Entries not in it are deleted [y/N]",
+            false,
+        )?;
+        if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    session.cmd_restore(backup, mode, dry_run)
+}
+
+fn cmd_restore_list(path: &Path) -> Result<(), PassmanError> {
+    let backup = open_backup(path)?;
+
+    println!(
+        "Backup from {} by {}, {} entries:",
+        format_timestamp(backup.created),
+        backup.generator,
+        backup.entries.len()
+    );
+    for (service, entry) in &backup.entries {
+        match entry.modified {
+            Some(modified) => println!("  {} (modified {})", service, format_timestamp(modified)),
+            None => println!("  {}", service),
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_names_with_auth(encrypt: bool) -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
     let session = PassmanSession::new(master_pwd)?;
-    session.cmd_new(service)
+    session.cmd_names(encrypt)
+}
+
+fn cmd_new_with_auth(
+    service: &ServiceName,
+    generator: &Generator,
+    clear_after: u64,
+) -> Result<(), PassmanError> {
+    // The vault verifier rejects a mistyped master password, no need to ask twice
+    let session = PassmanSession::new(prompt_master_password()?)?;
+    session.cmd_new(service, generator, clear_after)
+}
+
+fn cmd_register_with_auth(
+    service: Option<&ServiceName>,
+    force: bool,
+    from_stdin: bool,
+) -> Result<(), PassmanError> {
+    let from_stdin = from_stdin || !io::stdin().is_terminal();
+
+    let service = match service {
+        Some(service) => service.clone(),
+        None if from_stdin => {
+            return Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Service name required when reading the password from stdin",
+            )));
+        }
+        None => ServiceName::new(&prompt_input("Service name", false)?)?,
+    };
+
+    // Bail out before asking for any secrets
+    if !force && list_all_services()?.contains(&service) {
+        println!(
+            "Service '{}' already exists. Use --force to overwrite it.",
+            service
+        );
+        return Ok(());
+    }
+
+    // Checked against the vault verifier before anything else is asked
+    let session = PassmanSession::new(prompt_master_password()?)?;
+
+    // Read from stdin when asked to, or when something is piped in
+    let password = if from_stdin {
+        read_secret_from_stdin()?
+    } else {
+        prompt_secret_with_confirmation(&format!("Password for '{}'", service))?
+    };
+
+    session.cmd_register(&service, &password, force)
+}
+
+fn next_value<'a>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<String, PassmanError> {
+    iter.next().cloned().ok_or_else(|| {
+        PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Missing value for '{}'", flag),
+        ))
+    })
+}
+
+fn next_number<'a, T: std::str::FromStr>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<T, PassmanError> {
+    next_value(iter, flag)?.parse().map_err(|_| {
+        PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' expects a number", flag),
+        ))
+    })
+}
+
+fn next_seconds<'a>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<u64, PassmanError> {
+    next_value(iter, flag)?.parse().map_err(|_| {
+        PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' expects a number of seconds", flag),
+        ))
+    })
+}
+
+fn schedule_clipboard_clear(text: &str, clear_after: u64) {
+    if clear_after == 0 {
+        return;
+    }
+
+    match spawn_clipboard_clearer(text, clear_after) {
+        Ok(_) => println!("Clipboard will be cleared in {} seconds.", clear_after),
+        Err(e) => eprintln!("Warning: clipboard will not be cleared automatically: {}", e),
+    }
+}
+
+fn unexpected_argument(arg: &str) -> PassmanError {
+    PassmanError::IoError(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Unexpected argument: '{}'", arg),
+    ))
 }
 
-fn cmd_get_with_auth(service: Option<&str>) -> Result<(), PassmanError> {
+fn parse_source_and_target(
+    args: &[String],
+    cmd: &str,
+) -> Result<(ServiceName, ServiceName), PassmanError> {
+    match (args.get(2), args.get(3)) {
+        (Some(service), Some(new_service)) => {
+            Ok((ServiceName::new(service)?, ServiceName::new(new_service)?))
+        }
+        _ => Err(PassmanError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Source and target service names required for '{}' command", cmd),
+        ))),
+    }
+}
+
+fn cmd_remove_with_auth(service: &ServiceName, assume_yes: bool) -> Result<(), PassmanError> {
+    if !list_all_services()?.contains(service) {
+        println!("Service '{}' not found.", service);
+        return Ok(());
+    }
+
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+
+    if !assume_yes {
+        let answer = prompt_input(&format!("Delete '{}'? This cannot be undone [y/N]", service), false)?;
+        if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    session.cmd_remove(service)
+}
+
+fn cmd_move_with_auth(service: &ServiceName, new_service: &ServiceName) -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+
+    if !session.storage.has_service(service) {
+        println!("Service '{}' not found.", service);
+        return Ok(());
+    }
+
+    session.cmd_move(service, new_service)
+}
+
+fn cmd_copy_with_auth(service: &ServiceName, new_service: &ServiceName) -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+
+    if !session.storage.has_service(service) {
+        println!("Service '{}' not found.", service);
+        return Ok(());
+    }
+
+    session.cmd_copy(service, new_service)
+}
+
+/// Copies or prints the requested field of an entry, the password by default.
+fn output_entry_field(
+    service: &ServiceName,
+    entry: &Entry,
+    field: Option<&str>,
+    clear_after: u64,
+    to_stdout: bool,
+) -> Result<(), PassmanError> {
+    if to_stdout {
+        let name = field.unwrap_or("password");
+        let value = entry.field(name).ok_or_else(|| {
+            PassmanError::IoError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Field '{}' not found for '{}'", name, service),
+            ))
+        })?;
+        println!("{}", value);
+        return Ok(());
+    }
+
+    match field {
+        None | Some("password") => {
+            copy_to_clipboard(&entry.password)?;
+            println!("✓ Password for '{}' copied to clipboard!", service);
+            schedule_clipboard_clear(&entry.password, clear_after);
+        }
+        Some(field) => match entry.field(field) {
+            Some(value) => {
+                copy_to_clipboard(value)?;
+                println!("✓ Field '{}' for '{}' copied to clipboard!", field, service);
+                schedule_clipboard_clear(value, clear_after);
+            }
+            None => println!("Field '{}' not found for '{}'.", field, service),
+        },
+    }
+
+    Ok(())
+}
+
+fn print_entry(service: &ServiceName, entry: &Entry) {
+    println!("{}", service);
+    println!("  Username: {}", entry.username.as_deref().unwrap_or("-"));
+
+    if entry.urls.is_empty() {
+        println!("  URL:      -");
+    }
+    for url in &entry.urls {
+        println!("  URL:      {}", url);
+    }
+
+    match &entry.notes {
+        Some(notes) => {
+            let mut lines = notes.lines();
+            println!("  Notes:    {}", lines.next().unwrap_or(""));
+            for line in lines {
+                println!("            {}", line);
+            }
+        }
+        None => println!("  Notes:    -"),
+    }
+
+    // Custom fields often hold recovery codes and the like, never print their values
+    for (key, _) in &entry.fields {
+        println!("  Field:    {} (hidden, use 'get {} --field {}')", key, service, key);
+    }
+
+    let format_time = |t: Option<u64>| t.map(format_timestamp).unwrap_or("-".to_string());
+    println!("  Created:  {}", format_time(entry.created));
+    println!("  Modified: {}", format_time(entry.modified));
+}
+
+/// Asks a running agent for the entry, so no master password is needed.
+fn retrieve_from_agent(service: &ServiceName) -> Result<Option<Entry>, PassmanError> {
+    let storage_path = PassmanStorage::get_default_path();
+
+    // Let the usual path report missing services, unless only the index knows about them
+    if !ServiceIndex::exists(&storage_path) && !storage_path.join(service.file_name()).exists() {
+        return Ok(None);
+    }
+
+    agent::retrieve_entry(&storage_path, service)
+}
+
+fn cmd_show_with_auth(service: &ServiceName) -> Result<(), PassmanError> {
+    if let Some(entry) = retrieve_from_agent(service)? {
+        print_entry(service, &entry);
+        return Ok(());
+    }
+
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+
+    if !session.storage.has_service(service) {
+        println!("Service '{}' not found.", service);
+        return Ok(());
+    }
+
+    session.cmd_show(service)
+}
+
+fn cmd_edit_with_auth(service: &ServiceName, edit: &EntryEdit) -> Result<(), PassmanError> {
+    let master_pwd = prompt_master_password()?;
+    let session = PassmanSession::new(master_pwd)?;
+
+    if !session.storage.has_service(service) {
+        println!("Service '{}' not found.", service);
+        return Ok(());
+    }
+
+    session.cmd_edit(service, edit)
+}
+
+fn cmd_get_with_auth(
+    service: Option<&ServiceName>,
+    field: Option<&str>,
+    clear_after: u64,
+    to_stdout: bool,
+) -> Result<(), PassmanError> {
     match service {
         Some(service) => {
+            if let Some(entry) = retrieve_from_agent(service)? {
+                return output_entry_field(service, &entry, field, clear_after, to_stdout);
+            }
+
             let master_pwd = prompt_master_password()?;
             let session = PassmanSession::new(master_pwd)?;
 
             if !session.storage.has_service(service) {
+                // Scripts need a failing exit code, not a message on stdout
+                if to_stdout {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Service '{}' not found", service),
+                    )));
+                }
                 println!("Service '{}' not found.", service);
                 return Ok(());
             }
 
-            session.cmd_get(service)
+            session.cmd_get(service, field, clear_after, to_stdout)
         }
         None => {
             let services = list_all_services()?;
             if services.is_empty() {
+                if to_stdout {
+                    return Err(PassmanError::IoError(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "No passwords stored yet",
+                    )));
+                }
                 println!("No passwords stored yet. Use 'new' to create one.");
                 return Ok(());
             }
 
             let selected_service = prompt_service_selection(&services)?;
 
+            if let Some(entry) = retrieve_from_agent(&selected_service)? {
+                return output_entry_field(&selected_service, &entry, field, clear_after, to_stdout);
+            }
+
             let master_pwd = prompt_master_password()?;
 
             let session = PassmanSession::new(master_pwd)?;
 
-            session.cmd_get(&selected_service)
+            session.cmd_get(&selected_service, field, clear_after, to_stdout)
         }
     }
 }
 
+fn cmd_doctor(fix: bool) -> Result<(), PassmanError> {
+    // Resolved without creating it, a missing vault folder is something to report
+    let storage_path = PassmanStorage::find_default_path();
+    println!("Checking {}", storage_path.display());
+
+    let report = doctor::check_vault(&storage_path, fix)?;
+
+    if !report.versions.is_empty() {
+        let versions: Vec<String> = report
+            .versions
+            .iter()
+            .map(|(version, count)| format!("v{}: {}", version, count))
+            .collect();
+        println!("Entry file formats: {}", versions.join(", "));
+    }
+
+    if report.issues.is_empty() {
+        println!("✓ No problems found");
+        return Ok(());
+    }
+
+    for issue in &report.issues {
+        if issue.fixed {
+            println!("  ✓ {} (fixed)", issue.message);
+        } else {
+            println!("  - {}", issue.message);
+        }
+    }
+
+    let remaining = report.issues.iter().filter(|issue| !issue.fixed).count();
+    let fixable = report.issues.iter().any(|issue| issue.fixable && !issue.fixed);
+    if remaining > 0 {
+        println!("{} problem(s) found.", remaining);
+        if !fix && fixable {
+            println!("Run 'passman doctor --fix' to tighten permissions.");
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_calibrate(target_ms: u64, max_memory_mib: u32) -> Result<(), PassmanError> {
+    let target = Duration::from_millis(target_ms);
+    let max_m_cost = max_memory_mib.saturating_mul(1024).min(defaults::MAX_M_COST);
+
+    println!("Measuring Argon2 on this machine...");
+    let (kdf, elapsed) = calibrate::calibrate(target, max_m_cost)?;
+
+    println!(
+        "✓ {} MiB, {} iterations, {} lane(s): {} ms per unlock (target {} ms)",
+        kdf.m_cost / 1024,
+        kdf.t_cost,
+        kdf.p_cost,
+        elapsed.as_millis(),
+        target_ms
+    );
+    if kdf.m_cost < defaults::MIN_M_COST {
+        println!("Warning: less memory than the recommended minimum of {} MiB", defaults::MIN_M_COST / 1024);
+    }
+
+    Config::set_all(&[
+        ("kdf.m_cost", kdf.m_cost.to_string()),
+        ("kdf.t_cost", kdf.t_cost.to_string()),
+        ("kdf.p_cost", kdf.p_cost.to_string()),
+    ])?;
+    if let Some(path) = Config::path() {
+        println!("✓ Saved to {}", path.display());
+    }
+    println!("Run 'passman upgrade-kdf' to apply them to your vault.");
+
+    Ok(())
+}
+
+fn cmd_config(action: &ConfigAction) -> Result<(), PassmanError> {
+    match action {
+        ConfigAction::Get { key } => println!("{}", Config::load()?.get(key)?),
+        ConfigAction::Set { key, value } => {
+            Config::set(key, value)?;
+            println!("✓ Set {} = {}", key, value);
+        }
+        ConfigAction::List => {
+            let config = Config::load()?;
+            if let Some(path) = Config::path() {
+                println!("# {}", path.display());
+            }
+            for key in config::KEYS {
+                println!("{} = {}", key, config.get(key)?);
+            }
+        }
+    }
+    Ok(())
+}
+
 fn cmd_list() -> Result<(), PassmanError> {
     let services = list_all_services()?;
     println!("Stored services ({}):", services.len());
@@ -0,0 +1,595 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use crate::atomic;
+use crate::clipboard::ClipboardBackend;
+use crate::defaults;
+use crate::error::PassmanError;
+use crate::generator::Generator;
+
+const CONFIG_DIR: &str = "passman";
+const CONFIG_FILE: &str = "config.toml";
+
+static CURRENT: OnceLock<Config> = OnceLock::new();
+
+/// Every setting the config file accepts, as `section.name`.
+pub const KEYS: &[&str] = &[
+    "vault",
+    "kdf.m_cost",
+    "kdf.t_cost",
+    "kdf.p_cost",
+    "generator.length",
+    "generator.min_each",
+    "generator.lowercase",
+    "generator.uppercase",
+    "generator.digits",
+    "generator.symbols",
+    "generator.exclude",
+    "generator.no_ambiguous",
+    "generator.passphrase",
+    "generator.words",
+    "generator.separator",
+    "generator.capitalize",
+    "generator.with_digit",
+    "generator.with_symbol",
+    "clipboard.clear_after",
+    "clipboard.backend",
+    "storage.padding",
+    "gui.theme",
+    "gui.width",
+    "gui.height",
+];
+
+/// Argon2 costs for new vault keys and for entries of vaults without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfCosts {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GuiTheme {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl GuiTheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GuiTheme::System => "system",
+            GuiTheme::Light => "light",
+            GuiTheme::Dark => "dark",
+        }
+    }
+}
+
+/// User settings, read from `config.toml` in the platform config folder
+/// (`$XDG_CONFIG_HOME/passman` on Linux). Both binaries read it.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Where the vault lives, used when neither `--vault` nor `PASSMAN_DIR` is given
+    pub vault: Option<PathBuf>,
+    pub kdf: KdfCosts,
+    /// Starting point for `new`, `generate` and the GUI, flags still apply on top
+    pub generator: Generator,
+    pub clear_after: u64,
+    /// `None` picks a backend from the environment
+    pub clipboard: Option<ClipboardBackend>,
+    pub padding: usize,
+    pub gui_theme: GuiTheme,
+    pub gui_size: (f32, f32),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vault: None,
+            kdf: KdfCosts {
+                m_cost: defaults::M_COST,
+                t_cost: defaults::T_COST,
+                p_cost: defaults::P_COST,
+            },
+            generator: Generator::default(),
+            clear_after: defaults::CLEAR_AFTER_SECS,
+            clipboard: None,
+            padding: defaults::PADDING_BUCKET,
+            gui_theme: GuiTheme::System,
+            gui_size: (600.0, 500.0),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    /// Loads the config file for the rest of the process, failing on any invalid setting.
+    pub fn init() -> Result<&'static Self, PassmanError> {
+        let config = Self::load()?;
+        Ok(CURRENT.get_or_init(|| config))
+    }
+
+    /// The settings loaded by `init`. Loads them on first use otherwise,
+    /// falling back to the defaults with a warning if the file is invalid.
+    pub fn current() -> &'static Self {
+        CURRENT.get_or_init(|| {
+            Self::load().unwrap_or_else(|e| {
+                eprintln!("Warning: {} Using the default settings.", e);
+                Self::default()
+            })
+        })
+    }
+
+    /// The saved settings, or the defaults when there is no config file.
+    pub fn load() -> Result<Self, PassmanError> {
+        Self::from_document(&read_document()?)
+    }
+
+    fn from_document(document: &DocumentMut) -> Result<Self, PassmanError> {
+        let mut config = Self::default();
+
+        for (name, item) in document.iter() {
+            match item {
+                Item::Table(table) => {
+                    for (sub_name, sub_item) in table.iter() {
+                        let key = format!("{}.{}", name, sub_name);
+                        config.apply(&key, sub_item.as_value())?;
+                    }
+                }
+                Item::Value(Value::InlineTable(table)) => {
+                    for (sub_name, value) in table.iter() {
+                        config.apply(&format!("{}.{}", name, sub_name), Some(value))?;
+                    }
+                }
+                _ => config.apply(name, item.as_value())?,
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The value of `key` as `config get` prints it, empty when unset.
+    pub fn get(&self, key: &str) -> Result<String, PassmanError> {
+        let password = &self.generator.password;
+        let passphrase = &self.generator.passphrase;
+
+        let value = match key {
+            "vault" => self
+                .vault
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            "kdf.m_cost" => self.kdf.m_cost.to_string(),
+            "kdf.t_cost" => self.kdf.t_cost.to_string(),
+            "kdf.p_cost" => self.kdf.p_cost.to_string(),
+            "generator.length" => password.length.to_string(),
+            "generator.min_each" => password.min_per_class.to_string(),
+            "generator.lowercase" => password.lowercase.to_string(),
+            "generator.uppercase" => password.uppercase.to_string(),
+            "generator.digits" => password.digits.to_string(),
+            "generator.symbols" => password.symbol_set.clone(),
+            "generator.exclude" => password.exclude.clone(),
+            "generator.no_ambiguous" => password.exclude_ambiguous.to_string(),
+            "generator.passphrase" => self.generator.use_passphrase.to_string(),
+            "generator.words" => passphrase.word_count.to_string(),
+            "generator.separator" => passphrase.separator.clone(),
+            "generator.capitalize" => passphrase.capitalize.to_string(),
+            "generator.with_digit" => passphrase.include_digit.to_string(),
+            "generator.with_symbol" => passphrase.include_symbol.to_string(),
+            "clipboard.clear_after" => self.clear_after.to_string(),
+            "clipboard.backend" => self.clipboard.map_or("auto", |b| b.name()).to_string(),
+            "storage.padding" => self.padding.to_string(),
+            "gui.theme" => self.gui_theme.name().to_string(),
+            "gui.width" => self.gui_size.0.to_string(),
+            "gui.height" => self.gui_size.1.to_string(),
+            _ => return Err(unknown_key(key)),
+        };
+
+        Ok(value)
+    }
+
+    /// Checks `value` for `key` and saves it to the config file, keeping its comments and layout.
+    pub fn set(key: &str, value: &str) -> Result<(), PassmanError> {
+        Self::set_all(&[(key, value.to_string())])
+    }
+
+    /// Like `set` for several settings, saved together.
+    pub fn set_all(settings: &[(&str, String)]) -> Result<(), PassmanError> {
+        let mut document = read_document()?;
+
+        for (key, value) in settings {
+            let value = parse_cli_value(key, value)?;
+            match key.split_once('.') {
+                Some((section, name)) => {
+                    let table = document
+                        .entry(section)
+                        .or_insert_with(|| Item::Table(Table::new()))
+                        .as_table_like_mut()
+                        .ok_or_else(|| invalid(section, "must be a table"))?;
+                    table.insert(name, Item::Value(value));
+                }
+                None => {
+                    document.insert(key, Item::Value(value));
+                }
+            }
+        }
+
+        // Refuse to save anything the next run couldn't load
+        Self::from_document(&document)?;
+
+        let path = Self::path().ok_or_else(no_config_dir)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        atomic::write_file(&path, document.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    fn apply(&mut self, key: &str, value: Option<&Value>) -> Result<(), PassmanError> {
+        if !KEYS.contains(&key) {
+            return Err(unknown_key(key));
+        }
+        let value = value.ok_or_else(|| invalid(key, "must be a value, not a table"))?;
+        let password = &mut self.generator.password;
+        let passphrase = &mut self.generator.passphrase;
+
+        match key {
+            "vault" => self.vault = Some(PathBuf::from(string(key, value)?)),
+            "kdf.m_cost" => self.kdf.m_cost = number(key, value)?,
+            "kdf.t_cost" => self.kdf.t_cost = number(key, value)?,
+            "kdf.p_cost" => self.kdf.p_cost = number(key, value)?,
+            "generator.length" => password.length = number(key, value)?,
+            "generator.min_each" => password.min_per_class = number(key, value)?,
+            "generator.lowercase" => password.lowercase = boolean(key, value)?,
+            "generator.uppercase" => password.uppercase = boolean(key, value)?,
+            "generator.digits" => password.digits = boolean(key, value)?,
+            "generator.symbols" => password.symbol_set = string(key, value)?,
+            "generator.exclude" => password.exclude = string(key, value)?,
+            "generator.no_ambiguous" => password.exclude_ambiguous = boolean(key, value)?,
+            "generator.passphrase" => self.generator.use_passphrase = boolean(key, value)?,
+            "generator.words" => passphrase.word_count = number(key, value)?,
+            "generator.separator" => passphrase.separator = string(key, value)?,
+            "generator.capitalize" => passphrase.capitalize = boolean(key, value)?,
+            "generator.with_digit" => passphrase.include_digit = boolean(key, value)?,
+            "generator.with_symbol" => passphrase.include_symbol = boolean(key, value)?,
+            "clipboard.clear_after" => self.clear_after = number(key, value)?,
+            "clipboard.backend" => {
+                self.clipboard = match string(key, value)?.as_str() {
+                    "auto" => None,
+                    "system" => Some(ClipboardBackend::System),
+                    "osc52" => Some(ClipboardBackend::Osc52),
+                    _ => return Err(invalid(key, "must be 'auto', 'system' or 'osc52'")),
+                };
+            }
+            "storage.padding" => self.padding = number(key, value)?,
+            "gui.theme" => {
+                self.gui_theme = match string(key, value)?.as_str() {
+                    "system" => GuiTheme::System,
+                    "light" => GuiTheme::Light,
+                    "dark" => GuiTheme::Dark,
+                    _ => return Err(invalid(key, "must be 'system', 'light' or 'dark'")),
+                };
+            }
+            "gui.width" => self.gui_size.0 = number::<u16>(key, value)? as f32,
+            "gui.height" => self.gui_size.1 = number::<u16>(key, value)? as f32,
+            _ => return Err(unknown_key(key)),
+        }
+
+        Ok(())
+    }
+
+    /// Checks settings that only make sense together.
+    fn validate(&self) -> Result<(), PassmanError> {
+        if self.kdf.t_cost == 0 {
+            return Err(invalid("kdf.t_cost", "must be at least 1"));
+        }
+        if self.kdf.p_cost == 0 {
+            return Err(invalid("kdf.p_cost", "must be at least 1"));
+        }
+        if self.kdf.m_cost < 8 * self.kdf.p_cost {
+            return Err(invalid("kdf.m_cost", "must be at least 8 times kdf.p_cost"));
+        }
+        if self.kdf.m_cost > defaults::MAX_M_COST {
+            return Err(invalid(
+                "kdf.m_cost",
+                &format!("must be at most {} (4 GiB)", defaults::MAX_M_COST),
+            ));
+        }
+
+        let password = &self.generator.password;
+        if password.length == 0 {
+            return Err(invalid("generator.length", "must be at least 1"));
+        }
+        let classes = password.classes();
+        if classes.iter().any(Vec::is_empty) {
+            let key = if password.symbol_set.is_empty() { "generator.symbols" } else { "generator.exclude" };
+            return Err(invalid(key, "leaves a character class with no characters"));
+        }
+        if classes.len() * password.min_per_class > password.length {
+            return Err(invalid(
+                "generator.min_each",
+                &format!("needs generator.length to be at least {}", classes.len() * password.min_per_class),
+            ));
+        }
+        if let Err(PassmanError::InvalidPasswordPolicy(reason)) = password.validate() {
+            return Err(PassmanError::InvalidConfig(format!("generator settings: {}", reason)));
+        }
+        if self.generator.passphrase.word_count == 0 {
+            return Err(invalid("generator.words", "must be at least 1"));
+        }
+
+        if self.padding == 0 || self.padding > defaults::MAX_PADDING_BUCKET {
+            return Err(invalid(
+                "storage.padding",
+                &format!("must be between 1 (no padding) and {}", defaults::MAX_PADDING_BUCKET),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn read_document() -> Result<DocumentMut, PassmanError> {
+    let Some(path) = Config::path() else {
+        return Ok(DocumentMut::new());
+    };
+
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DocumentMut::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    content.parse().map_err(|e: toml_edit::TomlError| {
+        PassmanError::InvalidConfig(format!("{} is not valid TOML: {}", path.display(), e.message()))
+    })
+}
+
+/// Turns a value typed on the command line into the TOML type `key` expects.
+fn parse_cli_value(key: &str, value: &str) -> Result<Value, PassmanError> {
+    match key {
+        "generator.lowercase" | "generator.uppercase" | "generator.digits" | "generator.no_ambiguous"
+        | "generator.passphrase" | "generator.capitalize" | "generator.with_digit" | "generator.with_symbol" => {
+            let value: bool = value
+                .parse()
+                .map_err(|_| invalid(key, "must be true or false"))?;
+            Ok(value.into())
+        }
+        "kdf.m_cost" | "kdf.t_cost" | "kdf.p_cost" | "generator.length" | "generator.min_each"
+        | "generator.words" | "clipboard.clear_after" | "storage.padding" | "gui.width"
+        | "gui.height" => {
+            let value: i64 = value
+                .parse()
+                .map_err(|_| invalid(key, "must be a whole number"))?;
+            Ok(value.into())
+        }
+        _ if KEYS.contains(&key) => Ok(value.into()),
+        _ => Err(unknown_key(key)),
+    }
+}
+
+fn number<T: TryFrom<i64>>(key: &str, value: &Value) -> Result<T, PassmanError> {
+    let value = value
+        .as_integer()
+        .ok_or_else(|| invalid(key, "must be a whole number"))?;
+    T::try_from(value).map_err(|_| invalid(key, "is out of range"))
+}
+
+fn string(key: &str, value: &Value) -> Result<String, PassmanError> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| invalid(key, "must be a string"))
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, PassmanError> {
+    value
+        .as_bool()
+        .ok_or_else(|| invalid(key, "must be true or false"))
+}
+
+fn invalid(key: &str, reason: &str) -> PassmanError {
+    PassmanError::InvalidConfig(format!("'{}' {}", key, reason))
+}
+
+fn unknown_key(key: &str) -> PassmanError {
+    PassmanError::InvalidConfig(format!("unknown setting '{}', see 'passman config list'", key))
+}
+
+fn no_config_dir() -> PassmanError {
+    PassmanError::IoError(io::Error::new(
+        io::ErrorKind::NotFound,
+        "No config folder on this system",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(config: &Config) -> String {
+        config.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn padding_must_be_within_bounds() {
+        let mut config = Config::default();
+        for padding in [1, 256, defaults::MAX_PADDING_BUCKET] {
+            config.padding = padding;
+            assert!(config.validate().is_ok(), "padding {} was rejected", padding);
+        }
+
+        for padding in [0, defaults::MAX_PADDING_BUCKET + 1] {
+            config.padding = padding;
+            let error = config.validate().unwrap_err().to_string();
+            assert!(error.contains("storage.padding"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn kdf_errors_name_the_key() {
+        let mut config = Config::default();
+        config.kdf.t_cost = 0;
+        assert!(error_of(&config).contains("kdf.t_cost"));
+
+        let mut config = Config::default();
+        config.kdf.p_cost = 0;
+        assert!(error_of(&config).contains("kdf.p_cost"));
+
+        let mut config = Config::default();
+        config.kdf.p_cost = 4;
+        config.kdf.m_cost = 31;
+        assert!(error_of(&config).contains("kdf.m_cost"));
+
+        let mut config = Config::default();
+        config.kdf.m_cost = defaults::MAX_M_COST;
+        assert!(config.validate().is_ok());
+        config.kdf.m_cost = defaults::MAX_M_COST + 1;
+        assert!(error_of(&config).contains("kdf.m_cost"));
+    }
+
+    #[test]
+    fn generator_errors_name_the_key() {
+        let mut config = Config::default();
+        config.generator.password.length = 0;
+        assert!(error_of(&config).contains("generator.length"));
+
+        let mut config = Config::default();
+        config.generator.password.length = 6;
+        config.generator.password.min_per_class = 2;
+        assert!(error_of(&config).contains("generator.min_each"));
+
+        let mut config = Config::default();
+        config.generator.password.exclude = "0123456789".to_string();
+        assert!(error_of(&config).contains("generator.exclude"));
+
+        let mut config = Config::default();
+        config.generator.password.symbol_set = String::new();
+        assert!(error_of(&config).contains("generator.symbols"));
+
+        let mut config = Config::default();
+        config.generator.passphrase.word_count = 0;
+        assert!(error_of(&config).contains("generator.words"));
+    }
+
+    #[test]
+    fn reads_every_section() {
+        let document: DocumentMut = r#"
+            vault = "/tmp/vault"
+            kdf = { m_cost = 65536, t_cost = 3, p_cost = 2 }
+
+            [generator]
+            length = 32
+            digits = false
+            passphrase = true
+            separator = "_"
+            with_symbol = true
+
+            [clipboard]
+            clear_after = 0
+            backend = "osc52"
+
+            [gui]
+            theme = "dark"
+            width = 800
+        "#
+        .parse()
+        .unwrap();
+        let config = Config::from_document(&document).unwrap();
+
+        for (key, value) in [
+            ("vault", "/tmp/vault"),
+            ("kdf.m_cost", "65536"),
+            ("kdf.p_cost", "2"),
+            ("generator.length", "32"),
+            ("generator.lowercase", "true"),
+            ("generator.digits", "false"),
+            ("generator.passphrase", "true"),
+            ("generator.separator", "_"),
+            ("generator.capitalize", "false"),
+            ("generator.with_symbol", "true"),
+            ("clipboard.clear_after", "0"),
+            ("clipboard.backend", "osc52"),
+            ("storage.padding", "256"),
+            ("gui.theme", "dark"),
+            ("gui.width", "800"),
+        ] {
+            assert_eq!(config.get(key).unwrap(), value, "{}", key);
+        }
+        for key in KEYS {
+            assert!(config.get(key).is_ok(), "{} can't be read", key);
+        }
+        assert!(config.get("kdf.memory").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_wrong_types() {
+        for text in [
+            "colour = 1",
+            "[kdf]\nmemory = 1",
+            "[kdf]\nt_cost = \"3\"",
+            "[kdf]\nt_cost = -1",
+            "[generator]\npassphrase = 1",
+            "[generator]\nuppercase = \"no\"",
+            "[clipboard]\nbackend = \"x11\"",
+            "[gui]\nwidth = 70000",
+            "[generator.length]\nvalue = 3",
+        ] {
+            let document: DocumentMut = text.parse().unwrap();
+            assert!(Config::from_document(&document).is_err(), "{:?} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn parses_values_typed_on_the_command_line() {
+        assert_eq!(parse_cli_value("kdf.t_cost", "4").unwrap().as_integer(), Some(4));
+        assert_eq!(parse_cli_value("generator.passphrase", "true").unwrap().as_bool(), Some(true));
+        assert_eq!(parse_cli_value("generator.separator", "42").unwrap().as_str(), Some("42"));
+        assert!(parse_cli_value("kdf.t_cost", "four").is_err());
+        assert!(parse_cli_value("generator.no_ambiguous", "yes").is_err());
+        assert!(parse_cli_value("kdf.memory", "1").is_err());
+        assert_eq!(parse_cli_value("generator.with_digit", "true").unwrap().as_bool(), Some(true));
+    }
+
+    #[test]
+    fn every_key_reads_back_what_get_prints() {
+        let mut config = Config {
+            vault: Some(PathBuf::from("/tmp/vault")),
+            ..Default::default()
+        };
+        config.generator.password.digits = false;
+        config.generator.passphrase.capitalize = true;
+
+        let mut document = DocumentMut::new();
+        for key in KEYS {
+            let value = parse_cli_value(key, &config.get(key).unwrap()).unwrap();
+            match key.split_once('.') {
+                Some((section, name)) => {
+                    let table = document.entry(section).or_insert_with(|| Item::Table(Table::new()));
+                    table.as_table_mut().unwrap().insert(name, Item::Value(value));
+                }
+                None => {
+                    document.insert(key, Item::Value(value));
+                }
+            }
+        }
+
+        let read = Config::from_document(&document).unwrap();
+        for key in KEYS {
+            assert_eq!(read.get(key).unwrap(), config.get(key).unwrap(), "{}", key);
+        }
+    }
+}
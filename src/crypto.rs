@@ -2,6 +2,8 @@ use crate::error::PassmanError;
 use argon2::{Argon2, Params, Version};
 use chacha20poly1305::aead::KeyInit;
 use chacha20poly1305::{ChaCha20Poly1305, Key};
+use hkdf::Hkdf;
+use sha2::Sha256;
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 
@@ -15,48 +17,131 @@ pub struct KdfParameters {
     pub p_cost: u32,
 }
 
+impl KdfParameters {
+    /// Whether any of these costs is below `m_cost` KiB, `t_cost` iterations or `p_cost` lanes.
+    pub fn is_weaker_than(&self, m_cost: u32, t_cost: u32, p_cost: u32) -> bool {
+        self.m_cost < m_cost || self.t_cost < t_cost || self.p_cost < p_cost
+    }
+}
+
 pub fn gen_new_cipher(
     pwd: &[u8],
     m_cost: u32,
     t_cost: u32,
     p_cost: u32,
 ) -> Result<(ChaCha20Poly1305, KdfParameters, [u8; 12]), PassmanError> {
-    let mut rng = ChaCha20Rng::from_os_rng();
+    let kdf_params = gen_kdf_parameters(m_cost, t_cost, p_cost);
+    let nonce = gen_nonce();
 
-    let mut random_salt = [0u8; 16];
-    rng.fill_bytes(&mut random_salt);
+    let output_key = derive_key(pwd, &kdf_params)?;
 
-    let mut nonce = [0u8; 12];
-    rng.fill_bytes(&mut nonce);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&output_key));
+    Ok((cipher, kdf_params, nonce))
+}
+
+pub fn gen_decrypt_cipher(
+    pwd: &[u8],
+    params: &KdfParameters,
+) -> Result<ChaCha20Poly1305, PassmanError> {
+    let decrypt_key = derive_key(pwd, params)?;
 
-    let kdf_params = KdfParameters {
-        salt: random_salt,
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&decrypt_key)))
+}
+
+pub fn cipher_from_key(key: &[u8; 32]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+pub fn gen_kdf_parameters(m_cost: u32, t_cost: u32, p_cost: u32) -> KdfParameters {
+    let mut rng = ChaCha20Rng::from_os_rng();
+
+    let mut salt = [0u8; 16];
+    rng.fill_bytes(&mut salt);
+
+    KdfParameters {
+        salt,
         version: Version::V0x13,
         m_cost,
         t_cost,
         p_cost,
-    };
+    }
+}
 
-    let params = Params::new(m_cost, t_cost, p_cost, None)?;
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params);
+pub fn gen_nonce() -> [u8; 12] {
+    let mut rng = ChaCha20Rng::from_os_rng();
 
-    let mut output_key = [0u8; 32];
-    argon2.hash_password_into(pwd, &random_salt, &mut output_key)?;
+    let mut nonce = [0u8; 12];
+    rng.fill_bytes(&mut nonce);
+    nonce
+}
 
-    let cipher = ChaCha20Poly1305::new(Key::from_slice(&output_key));
-    Ok((cipher, kdf_params, nonce))
+/// A fresh random key that every entry key of a vault is derived from.
+pub fn gen_vault_key() -> [u8; 32] {
+    let mut rng = ChaCha20Rng::from_os_rng();
+
+    let mut vault_key = [0u8; 32];
+    rng.fill_bytes(&mut vault_key);
+    vault_key
 }
 
-pub fn gen_decrypt_cipher(
-    pwd: &[u8],
-    params: &KdfParameters,
-) -> Result<ChaCha20Poly1305, PassmanError> {
-    let mut decrypt_key = [0u8; 32];
+pub fn gen_entry_id() -> [u8; 16] {
+    let mut rng = ChaCha20Rng::from_os_rng();
+
+    let mut entry_id = [0u8; 16];
+    rng.fill_bytes(&mut entry_id);
+    entry_id
+}
+
+/// Derives the key of a single entry from the vault key, cheap enough to run for every entry.
+pub fn derive_entry_key(vault_key: &[u8; 32], entry_id: &[u8; 16]) -> [u8; 32] {
+    let mut info = b"passman entry key".to_vec();
+    info.extend_from_slice(entry_id);
+    derive_subkey(vault_key, &info)
+}
+
+/// Derives the key of the encrypted service name index from the vault key.
+pub fn derive_index_key(vault_key: &[u8; 32]) -> [u8; 32] {
+    derive_subkey(vault_key, b"passman index key")
+}
+
+fn derive_subkey(vault_key: &[u8; 32], info: &[u8]) -> [u8; 32] {
+    let mut subkey = [0u8; 32];
+    Hkdf::<Sha256>::new(None, vault_key)
+        .expand(info, &mut subkey)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    subkey
+}
+
+pub fn derive_key(pwd: &[u8], params: &KdfParameters) -> Result<[u8; 32], PassmanError> {
+    let mut output_key = [0u8; 32];
 
     let argon2_params = Params::new(params.m_cost, params.t_cost, params.p_cost, None)?;
     let argon2 = Argon2::new(argon2::Algorithm::Argon2id, params.version, argon2_params);
 
-    argon2.hash_password_into(pwd, &params.salt, &mut decrypt_key)?;
+    argon2.hash_password_into(pwd, &params.salt, &mut output_key)?;
 
-    Ok(ChaCha20Poly1305::new(Key::from_slice(&decrypt_key)))
+    Ok(output_key)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_lower_cost_is_weaker() {
+        let params = KdfParameters {
+            salt: [0; 16],
+            version: Version::V0x13,
+            m_cost: 64 * 1024,
+            t_cost: 3,
+            p_cost: 2,
+        };
+
+        assert!(!params.is_weaker_than(64 * 1024, 3, 2));
+        assert!(!params.is_weaker_than(19 * 1024, 2, 1));
+        assert!(params.is_weaker_than(128 * 1024, 3, 2));
+        assert!(params.is_weaker_than(64 * 1024, 4, 2));
+        assert!(params.is_weaker_than(64 * 1024, 3, 4));
+    }
 }
@@ -0,0 +1,33 @@
+// Built-in settings, used when the config file does not say otherwise
+
+/// Argon2 memory cost in KiB (64 MiB)
+pub const M_COST: u32 = 65536;
+/// Argon2 iterations
+pub const T_COST: u32 = 10;
+/// Argon2 parallel lanes
+pub const P_COST: u32 = 2;
+
+// OWASP's minimum for Argon2id, doctor flags anything cheaper and calibrate never goes below it
+pub const MIN_M_COST: u32 = 19 * 1024;
+pub const MIN_T_COST: u32 = 2;
+pub const MIN_P_COST: u32 = 1;
+/// Most memory the config accepts for one key derivation, in KiB (4 GiB)
+pub const MAX_M_COST: u32 = 4 * 1024 * 1024;
+
+/// How long `passman calibrate` aims for one unlock to take
+pub const CALIBRATION_TARGET_MS: u64 = 1000;
+/// Most memory `passman calibrate` lets one unlock use, in MiB
+pub const CALIBRATION_MAX_MEMORY_MIB: u32 = 512;
+
+pub const PASSWORD_LENGTH: usize = 20;
+pub const SYMBOLS: &str = "!@#$%^&*()_+-=[]{}|;:,.<>?";
+pub const WORD_COUNT: usize = 6;
+pub const SEPARATOR: &str = "-";
+
+/// Seconds before a copied secret is cleared from the clipboard, 0 keeps it
+pub const CLEAR_AFTER_SECS: u64 = 45;
+
+/// Entry plaintexts are padded to a multiple of this many bytes, 1 turns padding off
+pub const PADDING_BUCKET: usize = 256;
+/// Largest padding bucket the config accepts, every entry file grows to at least this size
+pub const MAX_PADDING_BUCKET: usize = 64 * 1024;
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::atomic;
+use crate::crypto::KdfParameters;
+use crate::defaults::{MIN_M_COST, MIN_P_COST, MIN_T_COST};
+use crate::error::PassmanError;
+use crate::index::SERVICE_INDEX_FILE;
+use crate::lock::{self, VaultLock};
+use crate::permissions;
+use crate::storage::{CURRENT_FILE_VERSION, FileHeader, FileKey};
+use crate::vault::{VAULT_METADATA_FILE, VaultMetadata};
+
+/// Something wrong with the vault, and whether `--fix` can or did take care of it.
+pub struct Issue {
+    pub message: String,
+    pub fixable: bool,
+    pub fixed: bool,
+}
+
+/// What a vault check found.
+#[derive(Default)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+    /// Number of entry files per file format version
+    pub versions: BTreeMap<u8, usize>,
+}
+
+impl Report {
+    fn issue(&mut self, message: String) {
+        self.issues.push(Issue {
+            message,
+            fixable: false,
+            fixed: false,
+        });
+    }
+}
+
+/// Checks the vault at `storage_path` without the master password.
+/// With `fix`, loose permissions are tightened on the way.
+pub fn check_vault(storage_path: &Path, fix: bool) -> Result<Report, PassmanError> {
+    let mut report = Report::default();
+    if !storage_path.exists() {
+        report.issue(format!("Vault folder {} does not exist", storage_path.display()));
+        return Ok(report);
+    }
+
+    let _lock = if fix {
+        VaultLock::exclusive(storage_path)?
+    } else {
+        VaultLock::shared(storage_path)?
+    };
+
+    check_permissions(&mut report, storage_path, "Vault folder", fix);
+    let lock_path = lock::lock_file_path(storage_path);
+    check_permissions(&mut report, &lock_path, "Lock file", fix);
+
+    match VaultMetadata::load(storage_path) {
+        Ok(metadata) => {
+            if !metadata.has_vault_key() {
+                report.issue(
+                    "Vault has no vault key, each entry needs its own key derivation (run 'passman migrate')"
+                        .to_string(),
+                );
+            }
+            check_kdf(&mut report, "Vault", metadata.kdf_params());
+        }
+        Err(e) => report.issue(format!("Can't read the vault metadata: {}", e)),
+    }
+
+    let mut entries: Vec<_> = fs::read_dir(storage_path)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        check_permissions(&mut report, &path, &format!("'{}'", name), fix);
+
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if atomic::is_temp_file(&name) {
+            report.issue(format!("'{}' is left over from an interrupted write", name));
+            continue;
+        }
+        if name == VAULT_METADATA_FILE || name == SERVICE_INDEX_FILE || name.starts_with('.') {
+            continue;
+        }
+
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                report.issue(format!("Can't read '{}': {}", name, e));
+                continue;
+            }
+        };
+        let header = match FileHeader::read(&content) {
+            Ok((header, _)) => header,
+            Err(_) => {
+                report.issue(format!("'{}' has an unknown or damaged header", name));
+                continue;
+            }
+        };
+
+        *report.versions.entry(header.version).or_default() += 1;
+        if let FileKey::Password(kdf_params) = &header.key {
+            check_kdf(&mut report, &format!("'{}'", name), kdf_params);
+        }
+    }
+
+    let outdated: usize = report
+        .versions
+        .range(..CURRENT_FILE_VERSION)
+        .map(|(_, count)| count)
+        .sum();
+    if outdated > 0 {
+        report.issue(format!(
+            "{} entries use an older file format (run 'passman migrate')",
+            outdated
+        ));
+    }
+
+    Ok(report)
+}
+
+fn check_permissions(report: &mut Report, path: &Path, what: &str, fix: bool) {
+    let mode = match permissions::loose_mode(path) {
+        Ok(Some(mode)) => mode,
+        Ok(None) => return,
+        Err(e) => {
+            report.issue(format!("Can't check permissions of {}: {}", what, e));
+            return;
+        }
+    };
+
+    report.issues.push(Issue {
+        message: format!("{} is accessible by other users (mode {:o})", what, mode),
+        fixable: true,
+        fixed: fix && permissions::restrict(path).is_ok(),
+    });
+}
+
+fn check_kdf(report: &mut Report, what: &str, kdf_params: &KdfParameters) {
+    if kdf_params.is_weaker_than(MIN_M_COST, MIN_T_COST, MIN_P_COST) {
+        report.issue(format!(
+            "{} uses weak key derivation settings ({} KiB, {} iterations, {} lanes)",
+            what, kdf_params.m_cost, kdf_params.t_cost, kdf_params.p_cost
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_missing_vault_folder_without_creating_it() {
+        let tmp = tempfile::tempdir().unwrap();
+        let storage_path = tmp.path().join("vault");
+
+        for fix in [false, true] {
+            let report = check_vault(&storage_path, fix).unwrap();
+            assert_eq!(report.issues.len(), 1);
+            assert!(report.issues[0].message.contains("does not exist"));
+            assert!(!storage_path.exists());
+        }
+    }
+}
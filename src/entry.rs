@@ -0,0 +1,285 @@
+use crate::error::PassmanError;
+use std::io::{Cursor, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Each record in a serialized entry is a tag byte, a u32 length and the data
+const TAG_PASSWORD: u8 = 0x01;
+const TAG_USERNAME: u8 = 0x02;
+const TAG_URL: u8 = 0x03;
+const TAG_NOTES: u8 = 0x04;
+const TAG_FIELD: u8 = 0x05;
+const TAG_CREATED: u8 = 0x06;
+const TAG_MODIFIED: u8 = 0x07;
+
+/// Everything stored for a single service, kept inside the encrypted payload.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub password: String,
+    pub username: Option<String>,
+    pub urls: Vec<String>,
+    pub notes: Option<String>,
+    pub fields: Vec<(String, String)>,
+    /// Seconds since the Unix epoch, unknown for entries created before they were tracked
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+}
+
+impl Entry {
+    pub fn new(password: String) -> Self {
+        let now = unix_now();
+        Self {
+            password,
+            created: Some(now),
+            modified: Some(now),
+            ..Default::default()
+        }
+    }
+
+    /// Wraps a payload from before entries were structured.
+    pub fn from_password(password: String) -> Self {
+        Self {
+            password,
+            ..Default::default()
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.modified = Some(unix_now());
+    }
+
+    /// Looks up a value by name, built-in fields first then custom ones.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "password" => Some(&self.password),
+            "username" => self.username.as_deref(),
+            "url" => self.urls.first().map(String::as_str),
+            "notes" => self.notes.as_deref(),
+            _ => self
+                .fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str()),
+        }
+    }
+
+    pub fn set_field(&mut self, name: &str, value: &str) {
+        match self.fields.iter_mut().find(|(key, _)| key == name) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.fields.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn remove_field(&mut self, name: &str) -> bool {
+        let len = self.fields.len();
+        self.fields.retain(|(key, _)| key != name);
+        self.fields.len() != len
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        write_record(&mut data, TAG_PASSWORD, self.password.as_bytes());
+        if let Some(username) = &self.username {
+            write_record(&mut data, TAG_USERNAME, username.as_bytes());
+        }
+        for url in &self.urls {
+            write_record(&mut data, TAG_URL, url.as_bytes());
+        }
+        if let Some(notes) = &self.notes {
+            write_record(&mut data, TAG_NOTES, notes.as_bytes());
+        }
+        for (key, value) in &self.fields {
+            let mut field = Vec::new();
+            field.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            field.extend_from_slice(key.as_bytes());
+            field.extend_from_slice(value.as_bytes());
+            write_record(&mut data, TAG_FIELD, &field);
+        }
+        if let Some(created) = self.created {
+            write_record(&mut data, TAG_CREATED, &created.to_le_bytes());
+        }
+        if let Some(modified) = self.modified {
+            write_record(&mut data, TAG_MODIFIED, &modified.to_le_bytes());
+        }
+
+        data
+    }
+
+    pub fn from_bytes(content: &[u8]) -> Result<Self, PassmanError> {
+        let mut cursor = Cursor::new(content);
+        let mut entry = Entry::default();
+        let mut has_password = false;
+
+        while (cursor.position() as usize) < content.len() {
+            let mut tag = [0u8; 1];
+            cursor.read_exact(&mut tag)?;
+            let mut len_bytes = [0u8; 4];
+            cursor.read_exact(&mut len_bytes)?;
+            let len = u32::from_le_bytes(len_bytes) as usize;
+
+            if len > content.len() - cursor.position() as usize {
+                return Err(PassmanError::InvalidFileFormat);
+            }
+            let mut value = vec![0u8; len];
+            cursor.read_exact(&mut value)?;
+
+            match tag[0] {
+                TAG_PASSWORD => {
+                    entry.password = String::from_utf8(value)?;
+                    has_password = true;
+                }
+                TAG_USERNAME => entry.username = Some(String::from_utf8(value)?),
+                TAG_URL => entry.urls.push(String::from_utf8(value)?),
+                TAG_NOTES => entry.notes = Some(String::from_utf8(value)?),
+                TAG_FIELD => {
+                    if value.len() < 4 {
+                        return Err(PassmanError::InvalidFileFormat);
+                    }
+                    let (key_len, rest) = value.split_at(4);
+                    let key_len = u32::from_le_bytes(key_len.try_into().unwrap()) as usize;
+                    if key_len > rest.len() {
+                        return Err(PassmanError::InvalidFileFormat);
+                    }
+                    let (key, field_value) = rest.split_at(key_len);
+                    entry.fields.push((
+                        String::from_utf8(key.to_vec())?,
+                        String::from_utf8(field_value.to_vec())?,
+                    ));
+                }
+                TAG_CREATED => entry.created = Some(read_timestamp(&value)?),
+                TAG_MODIFIED => entry.modified = Some(read_timestamp(&value)?),
+                // Records from newer versions are skipped rather than rejected
+                _ => {}
+            }
+        }
+
+        if !has_password {
+            return Err(PassmanError::InvalidFileFormat);
+        }
+
+        Ok(entry)
+    }
+}
+
+fn write_record(data: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    data.push(tag);
+    data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    data.extend_from_slice(value);
+}
+
+fn read_timestamp(value: &[u8]) -> Result<u64, PassmanError> {
+    let bytes: [u8; 8] = value
+        .try_into()
+        .map_err(|_| PassmanError::InvalidFileFormat)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds_of_day = timestamp % 86400;
+
+    // Civil date from days since epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        (seconds_of_day % 3600) / 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_entry() -> Entry {
+        Entry {
+            password: "s3cret".to_string(),
+            username: Some("octocat".to_string()),
+            urls: vec!["https://github.com".to_string(), "https://gist.github.com".to_string()],
+            notes: Some("line one\nline two".to_string()),
+            fields: vec![("totp".to_string(), "ABC".to_string()), ("empty".to_string(), String::new())],
+            created: Some(1_600_000_000),
+            modified: Some(1_700_000_000),
+        }
+    }
+
+    #[test]
+    fn round_trips_every_record() {
+        let entry = full_entry();
+        assert_eq!(Entry::from_bytes(&entry.to_bytes()).unwrap(), entry);
+
+        let bare = Entry::from_password(String::new());
+        assert_eq!(Entry::from_bytes(&bare.to_bytes()).unwrap(), bare);
+    }
+
+    #[test]
+    fn skips_unknown_tags() {
+        let entry = full_entry();
+        let mut data = entry.to_bytes();
+        write_record(&mut data, 0xEE, b"from a newer version");
+        assert_eq!(Entry::from_bytes(&data).unwrap(), entry);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let data = full_entry().to_bytes();
+        for len in [1, 4, 6, data.len() - 1] {
+            assert!(Entry::from_bytes(&data[..len]).is_err(), "accepted {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_lengths() {
+        let mut data = Vec::new();
+        write_record(&mut data, TAG_PASSWORD, b"pw");
+        data[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Entry::from_bytes(&data), Err(PassmanError::InvalidFileFormat)));
+
+        // A field whose key runs past its record
+        let mut field = 100u32.to_le_bytes().to_vec();
+        field.extend_from_slice(b"key");
+        let mut data = Vec::new();
+        write_record(&mut data, TAG_PASSWORD, b"pw");
+        write_record(&mut data, TAG_FIELD, &field);
+        assert!(matches!(Entry::from_bytes(&data), Err(PassmanError::InvalidFileFormat)));
+    }
+
+    #[test]
+    fn rejects_bad_timestamps_and_missing_passwords() {
+        let mut data = Vec::new();
+        write_record(&mut data, TAG_PASSWORD, b"pw");
+        write_record(&mut data, TAG_CREATED, &[1, 2, 3]);
+        assert!(Entry::from_bytes(&data).is_err());
+
+        let mut data = Vec::new();
+        write_record(&mut data, TAG_USERNAME, b"octocat");
+        assert!(Entry::from_bytes(&data).is_err());
+        assert!(Entry::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn formats_timestamps_in_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13 UTC");
+    }
+}
@@ -8,6 +8,15 @@ pub enum PassmanError {
     Argon2(argon2::Error),
     InvalidFileFormat,
     UnsupportedVersion,
+    InvalidServiceName(String),
+    WrongMasterPassword,
+    VaultNotInitialized,
+    InvalidPasswordPolicy(String),
+    ServiceNamesEncrypted,
+    VaultLocked(Option<u32>),
+    InvalidConfig(String),
+    InvalidImport(String),
+    InvalidBackup(String),
 }
 
 impl Display for PassmanError {
@@ -34,6 +43,36 @@ impl Display for PassmanError {
             PassmanError::UnsupportedVersion => {
                 write!(f, "Unsupported file version.")
             }
+            PassmanError::InvalidServiceName(reason) => {
+                write!(f, "Invalid service name: {}.", reason)
+            }
+            PassmanError::WrongMasterPassword => {
+                write!(f, "Wrong master password.")
+            }
+            PassmanError::VaultNotInitialized => {
+                write!(f, "Vault is not initialized, run 'passman init' first.")
+            }
+            PassmanError::InvalidPasswordPolicy(reason) => {
+                write!(f, "Invalid password policy: {}.", reason)
+            }
+            PassmanError::ServiceNamesEncrypted => {
+                write!(f, "Service names are encrypted, unlock the vault to list them.")
+            }
+            PassmanError::VaultLocked(Some(pid)) => {
+                write!(f, "Vault is in use by another passman process (PID {}).", pid)
+            }
+            PassmanError::VaultLocked(None) => {
+                write!(f, "Vault is in use by another passman process.")
+            }
+            PassmanError::InvalidConfig(reason) => {
+                write!(f, "Invalid config: {}.", reason)
+            }
+            PassmanError::InvalidImport(reason) => {
+                write!(f, "Can't import this file: {}.", reason)
+            }
+            PassmanError::InvalidBackup(reason) => {
+                write!(f, "Can't restore this backup: {}.", reason)
+            }
         }
     }
 }
@@ -0,0 +1,394 @@
+use crate::defaults;
+use crate::error::PassmanError;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use std::sync::OnceLock;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+
+// Characters easily mistaken for one another when read or typed
+const AMBIGUOUS: &str = "0O1lI|";
+
+// One word per line, dice-indexed lists like the EFF ones ("11111\tabacus") work as is
+const WORDLIST: &str = include_str!("../assets/wordlist.txt");
+
+fn wordlist() -> &'static [&'static str] {
+    static WORDS: OnceLock<Vec<&'static str>> = OnceLock::new();
+    WORDS.get_or_init(|| parse_wordlist(WORDLIST))
+}
+
+/// Takes the last column of each line, dropping the dice indices of lists that have them.
+fn parse_wordlist(text: &str) -> Vec<&str> {
+    text.lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .collect()
+}
+
+/// Rules for generating a random password.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    pub symbol_set: String,
+    /// Characters that must never appear, on top of the ambiguous ones if excluded
+    pub exclude: String,
+    pub exclude_ambiguous: bool,
+    /// Minimum number of characters from each enabled class
+    pub min_per_class: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: defaults::PASSWORD_LENGTH,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            symbol_set: defaults::SYMBOLS.to_string(),
+            exclude: String::new(),
+            exclude_ambiguous: false,
+            min_per_class: 1,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// The characters of every enabled class, after exclusions.
+    pub(crate) fn classes(&self) -> Vec<Vec<char>> {
+        let enabled = [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, self.symbol_set.as_str()),
+        ];
+
+        enabled
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, charset)| {
+                let mut chars: Vec<char> = charset
+                    .chars()
+                    .filter(|c| !self.exclude.contains(*c))
+                    .filter(|c| !(self.exclude_ambiguous && AMBIGUOUS.contains(*c)))
+                    .collect();
+                chars.sort_unstable();
+                chars.dedup();
+                chars
+            })
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), PassmanError> {
+        let invalid = |reason: &str| Err(PassmanError::InvalidPasswordPolicy(reason.to_string()));
+
+        if self.length == 0 {
+            return invalid("length must be at least 1");
+        }
+
+        let classes = self.classes();
+        if classes.is_empty() {
+            return invalid("at least one character class must be enabled");
+        }
+
+        if classes.iter().any(|class| class.is_empty()) {
+            return invalid("a character class has no characters left after exclusions");
+        }
+
+        if classes.len() * self.min_per_class > self.length {
+            return invalid("length is too short for the minimum count per class");
+        }
+
+        Ok(())
+    }
+
+    pub fn generate(&self) -> Result<String, PassmanError> {
+        self.validate()?;
+
+        let mut rng = rand::rng();
+        let classes = self.classes();
+
+        let mut all_chars: Vec<char> = classes.concat();
+        all_chars.sort_unstable();
+        all_chars.dedup();
+
+        let mut password = Vec::with_capacity(self.length);
+
+        for class in &classes {
+            for _ in 0..self.min_per_class {
+                password.push(class[rng.random_range(0..class.len())]);
+            }
+        }
+
+        while password.len() < self.length {
+            password.push(all_chars[rng.random_range(0..all_chars.len())]);
+        }
+
+        // The guaranteed characters were pushed first, don't leave them in front
+        password.shuffle(&mut rng);
+
+        Ok(password.into_iter().collect())
+    }
+
+    /// Applies a generator flag from the command line, returns whether it was one.
+    pub fn apply_flag<'a>(
+        &mut self,
+        flag: &str,
+        values: &mut impl Iterator<Item = &'a String>,
+    ) -> Result<bool, PassmanError> {
+        let mut value = || {
+            values.next().cloned().ok_or_else(|| {
+                PassmanError::InvalidPasswordPolicy(format!("missing value for '{}'", flag))
+            })
+        };
+
+        match flag {
+            "--length" | "-l" => {
+                self.length = value()?.parse().map_err(|_| {
+                    PassmanError::InvalidPasswordPolicy("length must be a number".to_string())
+                })?;
+            }
+            "--min-each" => {
+                self.min_per_class = value()?.parse().map_err(|_| {
+                    PassmanError::InvalidPasswordPolicy("min-each must be a number".to_string())
+                })?;
+            }
+            "--no-lowercase" => self.lowercase = false,
+            "--no-uppercase" => self.uppercase = false,
+            "--no-digits" => self.digits = false,
+            "--no-symbols" => self.symbols = false,
+            "--symbols" => {
+                self.symbol_set = value()?;
+                self.symbols = true;
+            }
+            "--exclude" => self.exclude.push_str(&value()?),
+            "--no-ambiguous" => self.exclude_ambiguous = true,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Upper bound on the entropy of a generated password, in bits.
+    pub fn entropy_bits(&self) -> f64 {
+        let mut all_chars: Vec<char> = self.classes().concat();
+        all_chars.sort_unstable();
+        all_chars.dedup();
+
+        self.length as f64 * (all_chars.len() as f64).log2()
+    }
+}
+
+/// Rules for generating a diceware-style passphrase.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassphrasePolicy {
+    pub word_count: usize,
+    pub separator: String,
+    pub capitalize: bool,
+    /// Appends a random digit to one random word
+    pub include_digit: bool,
+    /// Appends a random symbol to one random word
+    pub include_symbol: bool,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        Self {
+            word_count: defaults::WORD_COUNT,
+            separator: defaults::SEPARATOR.to_string(),
+            capitalize: false,
+            include_digit: false,
+            include_symbol: false,
+        }
+    }
+}
+
+impl PassphrasePolicy {
+    pub fn validate(&self) -> Result<(), PassmanError> {
+        if self.word_count == 0 {
+            return Err(PassmanError::InvalidPasswordPolicy(
+                "word count must be at least 1".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn generate(&self) -> Result<String, PassmanError> {
+        self.validate()?;
+
+        let mut rng = rand::rng();
+        let words = wordlist();
+
+        let mut chosen: Vec<String> = (0..self.word_count)
+            .map(|_| {
+                let word = words[rng.random_range(0..words.len())];
+                if self.capitalize {
+                    let mut chars = word.chars();
+                    chars
+                        .next()
+                        .map(|first| first.to_uppercase().chain(chars).collect())
+                        .unwrap_or_default()
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect();
+
+        if self.include_digit {
+            let index = rng.random_range(0..chosen.len());
+            let digit = DIGITS.as_bytes()[rng.random_range(0..DIGITS.len())] as char;
+            chosen[index].push(digit);
+        }
+
+        if self.include_symbol {
+            let index = rng.random_range(0..chosen.len());
+            let symbol = defaults::SYMBOLS.as_bytes()[rng.random_range(0..defaults::SYMBOLS.len())] as char;
+            chosen[index].push(symbol);
+        }
+
+        Ok(chosen.join(&self.separator))
+    }
+
+    /// Entropy of a generated passphrase in bits, assuming the wordlist is public.
+    pub fn entropy_bits(&self) -> f64 {
+        let words = self.word_count as f64;
+        let mut bits = words * (wordlist().len() as f64).log2();
+
+        // Each extra character adds its own choice plus which word it went on
+        if self.include_digit {
+            bits += (DIGITS.len() as f64).log2() + words.log2();
+        }
+        if self.include_symbol {
+            bits += (defaults::SYMBOLS.len() as f64).log2() + words.log2();
+        }
+
+        bits
+    }
+
+    /// Applies a passphrase flag from the command line, returns whether it was one.
+    pub fn apply_flag<'a>(
+        &mut self,
+        flag: &str,
+        values: &mut impl Iterator<Item = &'a String>,
+    ) -> Result<bool, PassmanError> {
+        match flag {
+            "--words" => {
+                self.word_count = values
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| {
+                        PassmanError::InvalidPasswordPolicy("words must be a number".to_string())
+                    })?;
+            }
+            "--separator" => {
+                self.separator = values.next().cloned().ok_or_else(|| {
+                    PassmanError::InvalidPasswordPolicy("missing value for '--separator'".to_string())
+                })?;
+            }
+            "--capitalize" => self.capitalize = true,
+            "--with-digit" => self.include_digit = true,
+            "--with-symbol" => self.include_symbol = true,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+/// Either kind of generated secret, as picked on the command line or in the GUI.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Generator {
+    pub use_passphrase: bool,
+    pub password: PasswordPolicy,
+    pub passphrase: PassphrasePolicy,
+}
+
+impl Generator {
+    pub fn validate(&self) -> Result<(), PassmanError> {
+        if self.use_passphrase {
+            self.passphrase.validate()
+        } else {
+            self.password.validate()
+        }
+    }
+
+    pub fn generate(&self) -> Result<String, PassmanError> {
+        if self.use_passphrase {
+            self.passphrase.generate()
+        } else {
+            self.password.generate()
+        }
+    }
+
+    pub fn entropy_bits(&self) -> f64 {
+        if self.use_passphrase {
+            self.passphrase.entropy_bits()
+        } else {
+            self.password.entropy_bits()
+        }
+    }
+
+    pub fn apply_flag<'a>(
+        &mut self,
+        flag: &str,
+        values: &mut impl Iterator<Item = &'a String>,
+    ) -> Result<bool, PassmanError> {
+        match flag {
+            "--passphrase" => {
+                self.use_passphrase = true;
+                return Ok(true);
+            }
+            // Undoes `generator.passphrase = true` from the config
+            "--no-passphrase" => {
+                self.use_passphrase = false;
+                return Ok(true);
+            }
+            _ => {}
+        }
+
+        Ok(self.password.apply_flag(flag, values)? || self.passphrase.apply_flag(flag, values)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_dice_indices() {
+        let words = parse_wordlist("11111\tabacus\n11112\tabdomen\n\n11113 abdominal\n");
+        assert_eq!(words, ["abacus", "abdomen", "abdominal"]);
+        assert_eq!(parse_wordlist("abandon\nability\n"), ["abandon", "ability"]);
+    }
+
+    #[test]
+    fn bundled_wordlist_has_unique_plain_words() {
+        let words = wordlist();
+        let unique: std::collections::HashSet<_> = words.iter().collect();
+        assert_eq!(unique.len(), words.len());
+        assert!(words.iter().all(|word| word.chars().all(|c| c.is_ascii_lowercase() || c == '-')));
+    }
+
+    #[test]
+    fn passphrase_entropy_follows_the_wordlist_size() {
+        // Words never hold whitespace, some hold the default '-' separator
+        let policy = PassphrasePolicy {
+            word_count: 6,
+            separator: " ".to_string(),
+            ..Default::default()
+        };
+        let expected = 6.0 * (wordlist().len() as f64).log2();
+        assert!((policy.entropy_bits() - expected).abs() < 1e-9);
+
+        let passphrase = policy.generate().unwrap();
+        let words: Vec<&str> = passphrase.split(' ').collect();
+        assert_eq!(words.len(), 6);
+        assert!(words.iter().all(|word| wordlist().contains(word)));
+    }
+}
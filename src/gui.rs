@@ -1,29 +1,114 @@
+use crate::agent;
 use crate::cli;
+use crate::config::{Config, GuiTheme};
+use crate::entry::{Entry, format_timestamp};
+use crate::generator::Generator;
+use crate::service_name::ServiceName;
 use crate::error::PassmanError;
-use crate::storage::PassmanStorage;
+use crate::index::ServiceIndex;
+use crate::storage::{self, PassmanStorage};
 use eframe::egui;
 use std::process::Command;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-const DEFAULT_M_COST: u32 = 65536;
-const DEFAULT_T_COST: u32 = 10;
-const DEFAULT_P_COST: u32 = 2;
+impl From<GuiTheme> for egui::ThemePreference {
+    fn from(theme: GuiTheme) -> Self {
+        match theme {
+            GuiTheme::System => egui::ThemePreference::System,
+            GuiTheme::Light => egui::ThemePreference::Light,
+            GuiTheme::Dark => egui::ThemePreference::Dark,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EntryAction {
+    Delete,
+    Rename,
+    Copy,
+}
+
+/// Editable copy of an entry, shown in the details window.
+struct EntryDetails {
+    service: ServiceName,
+    master: String,
+    entry: Entry,
+    username: String,
+    urls: String,
+    notes: String,
+    show_password: bool,
+    new_field_key: String,
+    new_field_value: String,
+}
+
+impl EntryDetails {
+    fn new(service: ServiceName, master: String, entry: Entry) -> Self {
+        Self {
+            service,
+            master,
+            username: entry.username.clone().unwrap_or_default(),
+            urls: entry.urls.join("\n"),
+            notes: entry.notes.clone().unwrap_or_default(),
+            entry,
+            show_password: false,
+            new_field_key: String::new(),
+            new_field_value: String::new(),
+        }
+    }
+
+    /// Folds the form fields back into the entry.
+    fn to_entry(&self) -> Entry {
+        let mut entry = self.entry.clone();
+        let username = self.username.trim();
+        let notes = self.notes.trim();
+
+        entry.username = (!username.is_empty()).then(|| username.to_string());
+        entry.urls = self
+            .urls
+            .lines()
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(str::to_string)
+            .collect();
+        entry.notes = (!notes.is_empty()).then(|| notes.to_string());
+        entry.touch();
+        entry
+    }
+}
 
 pub struct PassmanGui {
     master_password: String,
-    services: Vec<String>,
+    services: Vec<ServiceName>,
     status: Arc<Mutex<String>>,
 
     // Popups
     show_new_file_popup: bool,
     new_service_name: String,
     confirm_master_password: String,
+    new_generator: Generator,
 
     show_get_popup: bool,
     get_service_name: String,
     get_master_input: String,
 
+    show_action_popup: Option<EntryAction>,
+    action_service_name: String,
+    action_target_name: String,
+    action_master_input: String,
+    action_confirmed: bool,
+
+    show_details_popup: bool,
+    details_service_name: String,
+    details_master_input: String,
+    details: Arc<Mutex<Option<EntryDetails>>>,
+
+    clear_after_secs: u64,
+
+    // Encrypted service names need unlocking before the list shows anything
+    names_encrypted: bool,
+    list_master_input: String,
+
     // Refresh trigger
     needs_refresh: Arc<Mutex<bool>>,
 }
@@ -38,11 +123,28 @@ impl Default for PassmanGui {
             show_new_file_popup: false,
             new_service_name: String::new(),
             confirm_master_password: String::new(),
+            new_generator: Config::current().generator.clone(),
 
             show_get_popup: false,
             get_service_name: String::new(),
             get_master_input: String::new(),
 
+            show_action_popup: None,
+            action_service_name: String::new(),
+            action_target_name: String::new(),
+            action_master_input: String::new(),
+            action_confirmed: false,
+
+            show_details_popup: false,
+            details_service_name: String::new(),
+            details_master_input: String::new(),
+            details: Arc::new(Mutex::new(None)),
+
+            clear_after_secs: Config::current().clear_after,
+
+            names_encrypted: false,
+            list_master_input: String::new(),
+
             needs_refresh: Arc::new(Mutex::new(false)),
         };
         gui.refresh_services();
@@ -52,7 +154,9 @@ impl Default for PassmanGui {
 
 impl PassmanGui {
     fn refresh_services(&mut self) {
-        match cli::list_all_services() {
+        self.names_encrypted = ServiceIndex::exists(&PassmanStorage::get_default_path());
+
+        match self.list_services() {
             Ok(list) => {
                 self.services = list;
                 *self.status.lock().unwrap() =
@@ -65,29 +169,85 @@ impl PassmanGui {
         }
     }
 
+    /// With encrypted service names, the agent or the master password field unlocks the list.
+    fn list_services(&self) -> Result<Vec<ServiceName>, PassmanError> {
+        let storage_path = PassmanStorage::get_default_path();
+
+        match storage::list_services_in(&storage_path) {
+            Err(PassmanError::ServiceNamesEncrypted) => {
+                if let Some(services) = agent::list_services(&storage_path)? {
+                    return Ok(services);
+                }
+                if self.list_master_input.is_empty() {
+                    return Err(PassmanError::ServiceNamesEncrypted);
+                }
+                PassmanStorage::unlock(self.list_master_input.clone())?.list_services()
+            }
+            result => result,
+        }
+    }
+
+    /// Copies a secret, then has a separate process clear it from the clipboard after the timeout.
+    fn copy_secret(&self, text: &str) {
+        if let Err(e) = cli::copy_to_clipboard(text) {
+            *self.status.lock().unwrap() = format!("Failed to copy: {}", e);
+            return;
+        }
+
+        *self.status.lock().unwrap() = match schedule_clipboard_clear(text, self.clear_after_secs) {
+            Ok(()) => "Copied!".to_string(),
+            Err(e) => format!("Copied, but the clipboard will not be cleared: {}", e),
+        };
+    }
+
     fn copy_password(&self, service: &str, master: &str) {
-        let service = service.to_string();
         let master = master.to_string();
         let status = self.status.clone();
+        let clear_after = self.clear_after_secs;
 
-        std::thread::spawn(move || {
-            if master.is_empty() {
-                *status.lock().unwrap() =
-                    "Please enter master password first.".to_string();
+        let service = match ServiceName::new(service) {
+            Ok(service) => service,
+            Err(e) => {
+                *status.lock().unwrap() = e.to_string();
                 return;
             }
+        };
 
+        std::thread::spawn(move || {
             *status.lock().unwrap() = "Decrypting...".to_string();
 
-            let storage = PassmanStorage::new(master.clone());
+            // A running agent answers without the master password
+            let from_agent =
+                agent::retrieve_entry(&PassmanStorage::get_default_path(), &service);
 
-            match storage.retrieve(&service) {
+            let password = match from_agent {
+                Ok(Some(entry)) => Ok(entry.password),
+                _ if master.is_empty() => {
+                    *status.lock().unwrap() =
+                        "Please enter master password first.".to_string();
+                    return;
+                }
+                _ => match PassmanStorage::unlock(master) {
+                    Ok(storage) => storage.retrieve(&service),
+                    Err(e) => {
+                        *status.lock().unwrap() = format!("Failed to unlock vault: {}", e);
+                        return;
+                    }
+                },
+            };
+
+            match password {
                 Ok(password) => {
                     if let Err(e) = cli::copy_to_clipboard(&password) {
                         *status.lock().unwrap() = format!("Failed to copy: {}", e);
                     } else {
-                        *status.lock().unwrap() =
-                            format!("Password for '{}' copied!", service);
+                        *status.lock().unwrap() = match schedule_clipboard_clear(&password, clear_after) {
+                            Ok(()) => format!("Password for '{}' copied!", service),
+                            Err(e) => format!(
+                                "Password for '{}' copied, but the clipboard will not be cleared: {}",
+                                service, e
+                            ),
+                        };
                     }
                 }
                 Err(e) => {
@@ -98,23 +258,260 @@ impl PassmanGui {
         });
     }
 
-    fn open_folder(&self) {
-        if let path = PassmanStorage::get_default_path() {
-            let path_str = path.to_string_lossy().to_string();
-            *self.status.lock().unwrap() = format!("Opening folder: {}", path_str);
-
-            #[cfg(target_os = "windows")]
-            let _ = Command::new("explorer").arg(path_str).spawn();
-            #[cfg(target_os = "linux")]
-            let _ = Command::new("xdg-open").arg(path_str).spawn();
-            #[cfg(target_os = "macos")]
-            let _ = Command::new("open").arg(path_str).spawn();
-        } else {
-            *self.status.lock().unwrap() =
-                "Failed to locate storage folder.".to_string();
+    fn run_entry_action(&self, action: EntryAction, service: &str, target: &str, master: &str) {
+        let master = master.to_string();
+        let status = self.status.clone();
+        let refresh_flag = self.needs_refresh.clone();
+
+        if master.is_empty() {
+            *status.lock().unwrap() = "Please enter master password first.".to_string();
+            return;
+        }
+
+        let service = match ServiceName::new(service) {
+            Ok(service) => service,
+            Err(e) => {
+                *status.lock().unwrap() = e.to_string();
+                return;
+            }
+        };
+
+        // Deleting has no target, reuse the source so the name is always valid
+        let target = match action {
+            EntryAction::Delete => service.clone(),
+            _ => match ServiceName::new(target.trim()) {
+                Ok(target) => target,
+                Err(e) => {
+                    *status.lock().unwrap() = e.to_string();
+                    return;
+                }
+            },
+        };
+
+        std::thread::spawn(move || {
+            *status.lock().unwrap() = "Decrypting...".to_string();
+
+            let storage = match PassmanStorage::unlock(master) {
+                Ok(storage) => storage,
+                Err(e) => {
+                    *status.lock().unwrap() = format!("Failed to unlock vault: {}", e);
+                    return;
+                }
+            };
+
+            let result = match action {
+                EntryAction::Delete => storage
+                    .remove(&service)
+                    .map(|_| format!("Removed '{}'.", service)),
+                EntryAction::Rename => storage
+                    .rename(&service, &target)
+                    .map(|_| format!("Renamed '{}' to '{}'.", service, target)),
+                EntryAction::Copy => storage
+                    .copy(&service, &target)
+                    .map(|_| format!("Copied '{}' to '{}'.", service, target)),
+            };
+
+            match result {
+                Ok(message) => {
+                    *status.lock().unwrap() = message;
+                    *refresh_flag.lock().unwrap() = true;
+                }
+                Err(e) => {
+                    *status.lock().unwrap() = format!("Failed on '{}': {}", service, e);
+                }
+            }
+        });
+    }
+
+    fn load_details(&self, service: &str, master: &str) {
+        let master = master.to_string();
+        let status = self.status.clone();
+        let details = self.details.clone();
+
+        let service = match ServiceName::new(service) {
+            Ok(service) => service,
+            Err(e) => {
+                *status.lock().unwrap() = e.to_string();
+                return;
+            }
+        };
+
+        std::thread::spawn(move || {
+            *status.lock().unwrap() = "Decrypting...".to_string();
+
+            let storage = match PassmanStorage::unlock(master.clone()) {
+                Ok(storage) => storage,
+                Err(e) => {
+                    *status.lock().unwrap() = format!("Failed to unlock vault: {}", e);
+                    return;
+                }
+            };
+
+            match storage.retrieve_entry(&service) {
+                Ok(entry) => {
+                    *status.lock().unwrap() = format!("Loaded '{}'.", service);
+                    *details.lock().unwrap() = Some(EntryDetails::new(service, master, entry));
+                }
+                Err(e) => {
+                    *status.lock().unwrap() =
+                        format!("Failed to decrypt '{}': {}", service, e);
+                }
+            }
+        });
+    }
+
+    fn save_details(&self, service: ServiceName, master: String, entry: Entry) {
+        let status = self.status.clone();
+
+        std::thread::spawn(move || {
+            *status.lock().unwrap() = "Saving...".to_string();
+
+            let storage = PassmanStorage::new(master);
+            let kdf = Config::current().kdf;
+            match storage.replace_entry(&service, &entry, kdf.m_cost, kdf.t_cost, kdf.p_cost) {
+                Ok(_) => *status.lock().unwrap() = format!("Saved '{}'.", service),
+                Err(e) => {
+                    *status.lock().unwrap() = format!("Failed to save '{}': {}", service, e);
+                }
+            }
+        });
+    }
+
+    fn show_details_window(&mut self, ctx: &egui::Context) {
+        let mut details_guard = self.details.lock().unwrap();
+        let Some(details) = details_guard.as_mut() else {
+            return;
+        };
+
+        let mut close = false;
+        let mut save = false;
+        let mut copy_request = None;
+
+        egui::Window::new(format!("Details for '{}'", details.service))
+            .collapsible(false)
+            .resizable(true)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::Grid::new("entry_details").num_columns(2).show(ui, |ui| {
+                    ui.label("Password:");
+                    ui.horizontal(|ui| {
+                        if details.show_password {
+                            ui.label(&details.entry.password);
+                        } else {
+                            ui.label("••••••••");
+                        }
+                        ui.checkbox(&mut details.show_password, "Show");
+                        if ui.button("📋 Copy").clicked() {
+                            copy_request = Some(details.entry.password.clone());
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Username:");
+                    ui.text_edit_singleline(&mut details.username);
+                    ui.end_row();
+
+                    ui.label("URLs (one per line):");
+                    ui.text_edit_multiline(&mut details.urls);
+                    ui.end_row();
+
+                    ui.label("Notes:");
+                    ui.text_edit_multiline(&mut details.notes);
+                    ui.end_row();
+                });
+
+                ui.separator();
+                ui.label("Custom fields:");
+
+                let mut remove_index = None;
+                for (i, (key, value)) in details.entry.fields.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(key.as_str());
+                        ui.add(egui::TextEdit::singleline(value).password(true));
+                        if ui.button("📋").clicked() {
+                            copy_request = Some(value.clone());
+                        }
+                        if ui.button("🗑").clicked() {
+                            remove_index = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove_index {
+                    details.entry.fields.remove(i);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut details.new_field_key)
+                            .hint_text("Name")
+                            .desired_width(100.0),
+                    );
+                    ui.add(
+                        egui::TextEdit::singleline(&mut details.new_field_value)
+                            .password(true)
+                            .hint_text("Value"),
+                    );
+                    if ui.button("➕ Add").clicked() && !details.new_field_key.trim().is_empty() {
+                        let key = details.new_field_key.trim().to_string();
+                        let value = std::mem::take(&mut details.new_field_value);
+                        details.entry.set_field(&key, &value);
+                        details.new_field_key.clear();
+                    }
+                });
+
+                ui.separator();
+
+                let format_time = |t: Option<u64>| t.map(format_timestamp).unwrap_or("-".to_string());
+                ui.label(format!("Created: {}", format_time(details.entry.created)));
+                ui.label(format!("Modified: {}", format_time(details.entry.modified)));
+
+                ui.horizontal(|ui| {
+                    if ui.button("💾 Save").clicked() {
+                        save = true;
+                        close = true;
+                    }
+                    if ui.button("Close").clicked() {
+                        close = true;
+                    }
+                });
+            });
+
+        if save {
+            let (service, master, entry) =
+                (details.service.clone(), details.master.clone(), details.to_entry());
+            self.save_details(service, master, entry);
+        }
+
+        if close {
+            *details_guard = None;
+        }
+        drop(details_guard);
+
+        if let Some(text) = copy_request {
+            self.copy_secret(&text);
         }
     }
 
+    fn close_action_popup(&mut self) {
+        self.show_action_popup = None;
+        self.action_target_name.clear();
+        self.action_master_input.clear();
+        self.action_confirmed = false;
+    }
+
+    fn open_folder(&self) {
+        let path = PassmanStorage::get_default_path();
+        let path_str = path.to_string_lossy().to_string();
+        *self.status.lock().unwrap() = format!("Opening folder: {}", path_str);
+
+        #[cfg(target_os = "windows")]
+        let _ = Command::new("explorer").arg(path_str).spawn();
+        #[cfg(target_os = "linux")]
+        let _ = Command::new("xdg-open").arg(path_str).spawn();
+        #[cfg(target_os = "macos")]
+        let _ = Command::new("open").arg(path_str).spawn();
+    }
+
     fn create_new_file(&mut self) {
         let master = self.master_password.clone();
         let service = self.new_service_name.trim().to_string();
@@ -131,13 +528,35 @@ impl PassmanGui {
             return;
         }
 
+        let service = match ServiceName::new(&service) {
+            Ok(service) => service,
+            Err(e) => {
+                *status.lock().unwrap() = e.to_string();
+                return;
+            }
+        };
+
+        let password_final = match self.new_generator.generate() {
+            Ok(password) => password,
+            Err(e) => {
+                *status.lock().unwrap() = e.to_string();
+                return;
+            }
+        };
+
         *status.lock().unwrap() = "Creating new file...".to_string();
-        let password_final = cli::generate_random_password(20);
 
         std::thread::spawn(move || {
-            let storage = PassmanStorage::new(master.clone());
+            let storage = match PassmanStorage::unlock(master) {
+                Ok(storage) => storage,
+                Err(e) => {
+                    *status.lock().unwrap() = format!("Failed to unlock vault: {}", e);
+                    return;
+                }
+            };
 
-            match storage.store(&service, &password_final, DEFAULT_M_COST, DEFAULT_T_COST, DEFAULT_P_COST) {
+            let kdf = Config::current().kdf;
+            match storage.store(&service, &password_final, kdf.m_cost, kdf.t_cost, kdf.p_cost) {
                 Ok(_) => {
                     *status.lock().unwrap() =
                         format!("Created new entry '{}'.", service);
@@ -185,7 +604,25 @@ impl eframe::App for PassmanGui {
             if ui.button("🔄 Refresh services").clicked() {
                 self.refresh_services();
             }
-            ui.label(format!("{} stored services", self.services.len()));
+            if self.names_encrypted && self.services.is_empty() {
+                ui.horizontal(|ui| {
+                    ui.label("Service names are encrypted.");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.list_master_input)
+                            .password(true)
+                            .hint_text("Master password"),
+                    );
+                    if ui.button("🔓 Unlock list").clicked() {
+                        self.refresh_services();
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.label(format!("{} stored services", self.services.len()));
+                ui.separator();
+                ui.label("Clear clipboard after");
+                ui.add(egui::DragValue::new(&mut self.clear_after_secs).range(0..=600).suffix(" s"));
+            });
 
             ui.separator();
 
@@ -196,11 +633,30 @@ impl eframe::App for PassmanGui {
                     for service in &self.services {
                         ui.horizontal(|ui| {
                             if ui.button("🔑 Get").clicked() {
-                                self.get_service_name = service.clone();
+                                self.get_service_name = service.to_string();
                                 self.get_master_input.clear();
                                 self.show_get_popup = true;
                             }
-                            ui.label(service);
+                            if ui.button("📝 Details").clicked() {
+                                self.details_service_name = service.to_string();
+                                self.details_master_input.clear();
+                                self.show_details_popup = true;
+                            }
+                            if ui.button("✏ Rename").clicked() {
+                                self.action_service_name = service.to_string();
+                                self.action_target_name = service.to_string();
+                                self.show_action_popup = Some(EntryAction::Rename);
+                            }
+                            if ui.button("📄 Copy").clicked() {
+                                self.action_service_name = service.to_string();
+                                self.action_target_name = format!("{}-copy", service);
+                                self.show_action_popup = Some(EntryAction::Copy);
+                            }
+                            if ui.button("🗑 Delete").clicked() {
+                                self.action_service_name = service.to_string();
+                                self.show_action_popup = Some(EntryAction::Delete);
+                            }
+                            ui.label(service.as_str());
                         });
                     }
                 });
@@ -222,6 +678,65 @@ impl eframe::App for PassmanGui {
                     ui.label("Enter service name:");
                     ui.text_edit_singleline(&mut self.new_service_name);
 
+                    ui.collapsing("Password options", |ui| {
+                        let generator = &mut self.new_generator;
+
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut generator.use_passphrase, false, "Random characters");
+                            ui.radio_value(&mut generator.use_passphrase, true, "Passphrase");
+                        });
+
+                        if generator.use_passphrase {
+                            let passphrase = &mut generator.passphrase;
+
+                            ui.add(egui::Slider::new(&mut passphrase.word_count, 3..=12).text("Words"));
+                            ui.horizontal(|ui| {
+                                ui.label("Separator:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut passphrase.separator)
+                                        .desired_width(40.0),
+                                );
+                            });
+                            ui.checkbox(&mut passphrase.capitalize, "Capitalize words");
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut passphrase.include_digit, "Add a digit");
+                                ui.checkbox(&mut passphrase.include_symbol, "Add a symbol");
+                            });
+                        } else {
+                            let policy = &mut generator.password;
+
+                            ui.add(egui::Slider::new(&mut policy.length, 4..=128).text("Length"));
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut policy.lowercase, "a-z");
+                                ui.checkbox(&mut policy.uppercase, "A-Z");
+                                ui.checkbox(&mut policy.digits, "0-9");
+                                ui.checkbox(&mut policy.symbols, "Symbols");
+                            });
+                            ui.add_enabled(
+                                policy.symbols,
+                                egui::TextEdit::singleline(&mut policy.symbol_set).hint_text("Symbols"),
+                            );
+                            ui.add(
+                                egui::TextEdit::singleline(&mut policy.exclude)
+                                    .hint_text("Characters to exclude"),
+                            );
+                            ui.checkbox(&mut policy.exclude_ambiguous, "Avoid look-alikes (0O1l)");
+                            ui.add(
+                                egui::Slider::new(&mut policy.min_per_class, 0..=8)
+                                    .text("Minimum per class"),
+                            );
+                        }
+
+                        match generator.validate() {
+                            Ok(_) => {
+                                ui.label(format!("≈ {:.0} bits of entropy", generator.entropy_bits()));
+                            }
+                            Err(e) => {
+                                ui.colored_label(egui::Color32::RED, e.to_string());
+                            }
+                        }
+                    });
+
                     ui.separator();
 
                     ui.label("Enter master password:");
@@ -300,6 +815,104 @@ impl eframe::App for PassmanGui {
                 });
         }
 
+        //
+        // Popup for deleting, renaming or copying an entry
+        //
+        if let Some(action) = self.show_action_popup {
+            let title = match action {
+                EntryAction::Delete => format!("Delete '{}'", self.action_service_name),
+                EntryAction::Rename => format!("Rename '{}'", self.action_service_name),
+                EntryAction::Copy => format!("Copy '{}'", self.action_service_name),
+            };
+
+            egui::Window::new(title)
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    if action == EntryAction::Delete {
+                        ui.checkbox(&mut self.action_confirmed, "Yes, delete this password forever");
+                    } else {
+                        ui.label("Enter new service name:");
+                        ui.text_edit_singleline(&mut self.action_target_name);
+                    }
+
+                    ui.separator();
+
+                    ui.label("Enter master password:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.action_master_input)
+                            .password(true)
+                            .hint_text("Master password"),
+                    );
+
+                    ui.horizontal(|ui| {
+                        let can_run = action != EntryAction::Delete || self.action_confirmed;
+
+                        if ui.add_enabled(can_run, egui::Button::new("Confirm")).clicked() {
+                            self.run_entry_action(
+                                action,
+                                &self.action_service_name,
+                                &self.action_target_name,
+                                &self.action_master_input,
+                            );
+                            self.close_action_popup();
+                        }
+
+                        if ui.button("Cancel").clicked() {
+                            self.close_action_popup();
+                        }
+                    });
+                });
+        }
+
+        //
+        // Popup for unlocking an entry's details
+        //
+        if self.show_details_popup {
+            egui::Window::new(format!("Open '{}'", self.details_service_name))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label("Enter master password:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.details_master_input)
+                            .password(true)
+                            .hint_text("Master password"),
+                    );
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Open").clicked() {
+                            self.load_details(
+                                &self.details_service_name,
+                                &self.details_master_input,
+                            );
+                            self.show_details_popup = false;
+                            self.details_master_input.clear();
+                        }
+
+                        if ui.button("Cancel").clicked() {
+                            self.show_details_popup = false;
+                            self.details_master_input.clear();
+                        }
+                    });
+                });
+        }
+
+        //
+        // Entry details and editing
+        //
+        self.show_details_window(ctx);
+
         ctx.request_repaint_after(Duration::from_millis(200));
     }
 }
+
+// Clearing runs in a separate process so it still happens if the window is closed first
+fn schedule_clipboard_clear(text: &str, clear_after: u64) -> Result<(), PassmanError> {
+    if clear_after == 0 {
+        return Ok(());
+    }
+    cli::spawn_clipboard_clearer(text, clear_after)
+}
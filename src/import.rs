@@ -0,0 +1,625 @@
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::entry::Entry;
+use crate::error::PassmanError;
+use crate::kdbx;
+use crate::service_name::ServiceName;
+
+// Bitwarden item types, only logins carry a password
+const BITWARDEN_LOGIN: u64 = 1;
+
+/// Export formats `passman import` understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    BitwardenJson,
+    KeepassxcCsv,
+    ChromeCsv,
+    FirefoxCsv,
+    /// Any CSV with a header row, columns are matched by name
+    GenericCsv,
+    /// A KeePass database, opened with its password
+    Kdbx,
+}
+
+impl FromStr for ImportFormat {
+    type Err = PassmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bitwarden-json" => Ok(ImportFormat::BitwardenJson),
+            "keepassxc-csv" => Ok(ImportFormat::KeepassxcCsv),
+            "chrome-csv" => Ok(ImportFormat::ChromeCsv),
+            "firefox-csv" => Ok(ImportFormat::FirefoxCsv),
+            "generic-csv" => Ok(ImportFormat::GenericCsv),
+            "kdbx" => Ok(ImportFormat::Kdbx),
+            _ => Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown format '{}', expected 'bitwarden-json', 'keepassxc-csv', 'chrome-csv', 'firefox-csv', 'generic-csv' or 'kdbx'",
+                    s
+                ),
+            ))),
+        }
+    }
+}
+
+/// What to do when an imported entry has the name of an existing one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Skip,
+    /// Import under the first free `name (2)`, `name (3)`...
+    Rename,
+    Overwrite,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = PassmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            _ => Err(PassmanError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown conflict policy '{}', expected 'skip', 'rename' or 'overwrite'", s),
+            ))),
+        }
+    }
+}
+
+/// One record of the export, before it is given a service name.
+pub struct ImportedEntry {
+    /// Where the record came from, for messages (`row 3`, `item 'GitHub'`)
+    pub origin: String,
+    /// The name the other password manager showed, possibly not a valid service name
+    pub name: String,
+    pub entry: Entry,
+}
+
+/// A record that could not be turned into an entry.
+pub struct Rejected {
+    pub origin: String,
+    pub reason: String,
+}
+
+pub enum Action {
+    Create,
+    Overwrite,
+    /// Imported under another name than the export used
+    Rename { original: String },
+    Skip,
+}
+
+/// What an import will do with one record.
+pub struct PlannedEntry {
+    pub service: ServiceName,
+    pub entry: Entry,
+    pub action: Action,
+}
+
+impl ImportFormat {
+    /// Whether the file is encrypted and `parse` needs its password.
+    pub fn needs_password(self) -> bool {
+        self == ImportFormat::Kdbx
+    }
+}
+
+/// Parses an export held in memory. The plaintext never goes anywhere else.
+pub fn parse(
+    format: ImportFormat,
+    data: &[u8],
+    password: Option<&str>,
+) -> Result<(Vec<ImportedEntry>, Vec<Rejected>), PassmanError> {
+    match format {
+        ImportFormat::BitwardenJson => parse_bitwarden(data),
+        ImportFormat::KeepassxcCsv => parse_csv(data, keepassxc_record),
+        ImportFormat::ChromeCsv => parse_csv(data, chrome_record),
+        ImportFormat::FirefoxCsv => parse_csv(data, firefox_record),
+        ImportFormat::GenericCsv => parse_csv(data, generic_record),
+        ImportFormat::Kdbx => kdbx::read(data, password.unwrap_or_default()),
+    }
+}
+
+/// Gives every entry a valid service name and decides what happens on conflicts,
+/// both with `existing` services and between entries of the same export.
+pub fn plan(
+    imported: Vec<ImportedEntry>,
+    existing: &[ServiceName],
+    policy: ConflictPolicy,
+    rejected: &mut Vec<Rejected>,
+) -> Vec<PlannedEntry> {
+    // `true` for names this import writes, those are never overwritten by a later record
+    let mut taken: HashMap<ServiceName, bool> = existing.iter().map(|s| (s.clone(), false)).collect();
+    let mut planned = Vec::with_capacity(imported.len());
+
+    for imported in imported {
+        let service = match ServiceName::new(&sanitize_name(&imported.name)) {
+            Ok(service) => service,
+            Err(e) => {
+                rejected.push(Rejected {
+                    origin: imported.origin,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let (service, action) = match taken.get(&service) {
+            None => (service, Action::Create),
+            Some(false) if policy == ConflictPolicy::Overwrite => (service, Action::Overwrite),
+            Some(_) if policy == ConflictPolicy::Skip => (service, Action::Skip),
+            Some(_) => match free_name(&service, &taken) {
+                Some(renamed) => {
+                    let original = service.as_str().to_string();
+                    (renamed, Action::Rename { original })
+                }
+                None => {
+                    rejected.push(Rejected {
+                        origin: imported.origin,
+                        reason: format!("no free name left for '{}'", service),
+                    });
+                    continue;
+                }
+            },
+        };
+
+        if !matches!(action, Action::Skip) {
+            taken.insert(service.clone(), true);
+        }
+        planned.push(PlannedEntry {
+            service,
+            entry: imported.entry,
+            action,
+        });
+    }
+
+    planned
+}
+
+/// Turns what other password managers allow in a title into a valid service name.
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+
+    // Empty path segments and dots at either end are the usual offenders, as in "/Work//GitHub."
+    let segments: Vec<&str> = name
+        .split(['/', '\\'])
+        .map(|segment| segment.trim().trim_matches('.').trim())
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    segments.join("/")
+}
+
+fn free_name(service: &ServiceName, taken: &HashMap<ServiceName, bool>) -> Option<ServiceName> {
+    (2..1000)
+        .filter_map(|n| ServiceName::new(&format!("{} ({})", service, n)).ok())
+        .find(|candidate| !taken.contains_key(candidate))
+}
+
+fn parse_bitwarden(data: &[u8]) -> Result<(Vec<ImportedEntry>, Vec<Rejected>), PassmanError> {
+    let export: Value = serde_json::from_slice(data)
+        .map_err(|e| PassmanError::InvalidImport(format!("not valid JSON: {}", e)))?;
+
+    if export["encrypted"].as_bool() == Some(true) {
+        return Err(PassmanError::InvalidImport(
+            "this is an encrypted Bitwarden export, export it again as unencrypted JSON".to_string(),
+        ));
+    }
+    let items = export["items"]
+        .as_array()
+        .ok_or_else(|| PassmanError::InvalidImport("no 'items' list, is this a Bitwarden export?".to_string()))?;
+
+    let folders: HashMap<&str, &str> = export["folders"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|folder| Some((folder["id"].as_str()?, folder["name"].as_str()?)))
+        .collect();
+
+    let mut imported = Vec::new();
+    let mut rejected = Vec::new();
+
+    for (i, item) in items.iter().enumerate() {
+        let title = text(&item["name"]);
+        let origin = match &title {
+            Some(title) => format!("item '{}'", title),
+            None => format!("item {}", i + 1),
+        };
+        if item["type"].as_u64() != Some(BITWARDEN_LOGIN) {
+            rejected.push(Rejected {
+                origin,
+                reason: "not a login (cards, identities and notes are not imported)".to_string(),
+            });
+            continue;
+        }
+
+        let login = &item["login"];
+        let mut entry = Entry::new(text(&login["password"]).unwrap_or_default());
+        entry.username = text(&login["username"]);
+        entry.notes = text(&item["notes"]);
+        entry.urls = login["uris"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|uri| text(&uri["uri"]))
+            .collect();
+        if let Some(totp) = text(&login["totp"]) {
+            entry.set_field("totp", &totp);
+        }
+        for field in item["fields"].as_array().into_iter().flatten() {
+            if let (Some(name), Some(value)) = (text(&field["name"]), text(&field["value"])) {
+                entry.set_field(&name, &value);
+            }
+        }
+
+        let folder = item["folderId"].as_str().and_then(|id| folders.get(id));
+        let name = title
+            .or_else(|| entry.urls.first().map(|url| host(url).to_string()))
+            .unwrap_or_default();
+        let name = match folder {
+            Some(folder) => format!("{}/{}", folder, name),
+            None => name,
+        };
+
+        imported.push(ImportedEntry { origin, name, entry });
+    }
+
+    Ok((imported, rejected))
+}
+
+/// A CSV row with its header, looked up by column name.
+struct Row<'a> {
+    headers: &'a [String],
+    record: &'a csv::StringRecord,
+}
+
+impl Row<'_> {
+    /// The first non-empty value among `columns`, matched case-insensitively.
+    fn get(&self, columns: &[&str]) -> Option<String> {
+        columns.iter().find_map(|column| {
+            let index = self.headers.iter().position(|h| h.eq_ignore_ascii_case(column))?;
+            let value = self.record.get(index)?;
+            (!value.is_empty()).then(|| value.to_string())
+        })
+    }
+}
+
+fn parse_csv<F>(data: &[u8], to_entry: F) -> Result<(Vec<ImportedEntry>, Vec<Rejected>), PassmanError>
+where
+    F: Fn(&Row) -> Result<(String, Entry), String>,
+{
+    // Excel and some exporters start the file with a byte order mark
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| PassmanError::InvalidImport(format!("can't read the CSV header: {}", e)))?
+        .iter()
+        .map(|h| h.trim().to_string())
+        .collect();
+
+    let mut imported = Vec::new();
+    let mut rejected = Vec::new();
+
+    for (i, record) in reader.records().enumerate() {
+        // Row 1 is the header
+        let origin = format!("row {}", i + 2);
+        let record = record.map_err(|e| PassmanError::InvalidImport(format!("{}: {}", origin, e)))?;
+
+        match to_entry(&Row { headers: &headers, record: &record }) {
+            Ok((name, entry)) => imported.push(ImportedEntry { origin, name, entry }),
+            Err(reason) => rejected.push(Rejected { origin, reason }),
+        }
+    }
+
+    Ok((imported, rejected))
+}
+
+fn keepassxc_record(row: &Row) -> Result<(String, Entry), String> {
+    let mut entry = Entry::new(row.get(&["Password"]).unwrap_or_default());
+    entry.username = row.get(&["Username"]);
+    entry.urls = row.get(&["URL"]).into_iter().collect();
+    entry.notes = row.get(&["Notes"]);
+    if let Some(totp) = row.get(&["TOTP"]) {
+        entry.set_field("totp", &totp);
+    }
+
+    let title = row
+        .get(&["Title"])
+        .or_else(|| entry.urls.first().map(|url| host(url).to_string()))
+        .ok_or("no title or URL to name it after")?;
+
+    // Groups become folders, minus the database's root group
+    let name = match row.get(&["Group"]) {
+        Some(group) => {
+            let group = group.split_once('/').map_or("", |(_, rest)| rest);
+            if group.is_empty() {
+                title
+            } else {
+                format!("{}/{}", group, title)
+            }
+        }
+        None => title,
+    };
+
+    Ok((name, entry))
+}
+
+fn chrome_record(row: &Row) -> Result<(String, Entry), String> {
+    let mut entry = Entry::new(row.get(&["password"]).unwrap_or_default());
+    entry.username = row.get(&["username"]);
+    entry.urls = row.get(&["url"]).into_iter().collect();
+    entry.notes = row.get(&["note"]);
+
+    let name = row
+        .get(&["name"])
+        .or_else(|| entry.urls.first().map(|url| host(url).to_string()))
+        .ok_or("no name or URL to name it after")?;
+
+    Ok((name, entry))
+}
+
+fn firefox_record(row: &Row) -> Result<(String, Entry), String> {
+    let mut entry = Entry::new(row.get(&["password"]).unwrap_or_default());
+    entry.username = row.get(&["username"]);
+    entry.urls = row.get(&["url"]).into_iter().collect();
+
+    // Milliseconds since the Unix epoch
+    let seconds = |column: &str| row.get(&[column]).and_then(|ms| ms.parse::<u64>().ok()).map(|ms| ms / 1000);
+    entry.created = seconds("timeCreated").or(entry.created);
+    entry.modified = seconds("timePasswordChanged").or(entry.modified);
+
+    let url = entry.urls.first().ok_or("no URL to name it after")?;
+    Ok((host(url).to_string(), entry))
+}
+
+fn generic_record(row: &Row) -> Result<(String, Entry), String> {
+    const NAME: &[&str] = &["name", "title", "service"];
+    const USERNAME: &[&str] = &["username", "user", "login", "email"];
+    const PASSWORD: &[&str] = &["password", "pass"];
+    const URL: &[&str] = &["url", "uri", "website", "login_uri"];
+    const NOTES: &[&str] = &["notes", "note", "comments", "extra"];
+
+    let password = row.get(PASSWORD).ok_or("no password column, or it is empty")?;
+    let mut entry = Entry::new(password);
+    entry.username = row.get(USERNAME);
+    entry.urls = row.get(URL).into_iter().collect();
+    entry.notes = row.get(NOTES);
+
+    // Columns we don't know about are kept as custom fields
+    let known = [NAME, USERNAME, PASSWORD, URL, NOTES].concat();
+    for (header, value) in row.headers.iter().zip(row.record.iter()) {
+        let is_known = known.iter().any(|column| header.eq_ignore_ascii_case(column));
+        if !is_known && !header.is_empty() && !value.is_empty() {
+            entry.set_field(header, value);
+        }
+    }
+
+    let name = row
+        .get(NAME)
+        .or_else(|| entry.urls.first().map(|url| host(url).to_string()))
+        .ok_or("no name or URL to name it after")?;
+
+    Ok((name, entry))
+}
+
+fn text(value: &Value) -> Option<String> {
+    value.as_str().filter(|s| !s.is_empty()).map(str::to_string)
+}
+
+/// `https://user@www.example.com:8080/login` -> `www.example.com`
+pub(crate) fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    host.split(':').next().unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(format: ImportFormat, data: &str) -> (Vec<ImportedEntry>, Vec<Rejected>) {
+        parse(format, data.as_bytes(), None).unwrap()
+    }
+
+    fn imported(name: &str) -> ImportedEntry {
+        ImportedEntry {
+            origin: format!("item '{}'", name),
+            name: name.to_string(),
+            entry: Entry::new("pw".to_string()),
+        }
+    }
+
+    #[test]
+    fn reads_bitwarden_logins() {
+        let data = r#"{
+            "encrypted": false,
+            "folders": [{"id": "f1", "name": "Work"}],
+            "items": [
+                {
+                    "type": 1, "name": "GitHub", "folderId": "f1", "notes": "2FA on",
+                    "login": {
+                        "username": "octo", "password": "hunter2", "totp": "otpauth://totp/x",
+                        "uris": [{"uri": "https://github.com/login"}, {"uri": "https://gist.github.com"}]
+                    },
+                    "fields": [{"name": "pin", "value": "1234"}]
+                },
+                {"type": 1, "name": "", "login": {"password": "pw", "uris": [{"uri": "https://example.com:8443/"}]}},
+                {"type": 3, "name": "Visa", "card": {}}
+            ]
+        }"#;
+        let (imported, rejected) = parse_ok(ImportFormat::BitwardenJson, data);
+
+        assert_eq!(imported.len(), 2);
+        let github = &imported[0];
+        assert_eq!(github.name, "Work/GitHub");
+        assert_eq!(github.entry.password, "hunter2");
+        assert_eq!(github.entry.username.as_deref(), Some("octo"));
+        assert_eq!(github.entry.notes.as_deref(), Some("2FA on"));
+        assert_eq!(github.entry.urls, ["https://github.com/login", "https://gist.github.com"]);
+        assert_eq!(github.entry.field("totp"), Some("otpauth://totp/x"));
+        assert_eq!(github.entry.field("pin"), Some("1234"));
+        assert_eq!(imported[1].name, "example.com");
+
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].origin, "item 'Visa'");
+    }
+
+    #[test]
+    fn refuses_encrypted_or_foreign_json() {
+        for data in [r#"{"encrypted": true, "items": []}"#, r#"{"entries": []}"#, "not json"] {
+            assert!(matches!(
+                parse(ImportFormat::BitwardenJson, data.as_bytes(), None),
+                Err(PassmanError::InvalidImport(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn reads_keepassxc_csv() {
+        let data = "\u{FEFF}\"Group\",\"Title\",\"Username\",\"Password\",\"URL\",\"Notes\",\"TOTP\"\n\
+            \"Root/Work\",\"GitHub\",\"octo\",\"hunter2\",\"https://github.com\",\"line 1\nline 2\",\"otpauth://totp/x\"\n\
+            \"Root\",\"Mail\",\"me\",\"pw\",\"\",\"\",\"\"\n\
+            \"Root\",\"\",\"me\",\"pw\",\"https://news.example.org/\",\"\",\"\"\n\
+            \"Root\",\"\",\"me\",\"pw\",\"\",\"\",\"\"\n";
+        let (imported, rejected) = parse_ok(ImportFormat::KeepassxcCsv, data);
+
+        let names: Vec<&str> = imported.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["Work/GitHub", "Mail", "news.example.org"]);
+        let github = &imported[0].entry;
+        assert_eq!(github.password, "hunter2");
+        assert_eq!(github.notes.as_deref(), Some("line 1\nline 2"));
+        assert_eq!(github.field("totp"), Some("otpauth://totp/x"));
+
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].origin, "row 5");
+    }
+
+    #[test]
+    fn reads_chrome_csv() {
+        let data = "name,url,username,password,note\n\
+            github.com,https://github.com/,octo,hunter2,personal\n\
+            ,https://accounts.example.com/signin,me,pw,\n";
+        let (imported, rejected) = parse_ok(ImportFormat::ChromeCsv, data);
+
+        assert!(rejected.is_empty());
+        assert_eq!(imported[0].name, "github.com");
+        assert_eq!(imported[0].entry.notes.as_deref(), Some("personal"));
+        assert_eq!(imported[1].name, "accounts.example.com");
+        assert_eq!(imported[1].entry.username.as_deref(), Some("me"));
+    }
+
+    #[test]
+    fn reads_firefox_csv() {
+        let data = "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+            \"https://github.com\",\"octo\",\"hunter2\",,\"https://github.com\",\"{1}\",\"1600000000123\",\"1700000000000\",\"1650000000999\"\n\
+            \"\",\"me\",\"pw\",,,\"{2}\",\"1\",\"1\",\"1\"\n";
+        let (imported, rejected) = parse_ok(ImportFormat::FirefoxCsv, data);
+
+        assert_eq!(imported.len(), 1);
+        let github = &imported[0];
+        assert_eq!(github.name, "github.com");
+        assert_eq!(github.entry.created, Some(1_600_000_000));
+        assert_eq!(github.entry.modified, Some(1_650_000_000));
+        assert_eq!(rejected.len(), 1);
+    }
+
+    #[test]
+    fn reads_generic_csv_by_column_name() {
+        let data = "Title,Login,Pass,Website,Comments,Security question\n\
+            Bank,me,s3cret,https://bank.example,call first,Blue\n\
+            NoPassword,me,,,,\n";
+        let (imported, rejected) = parse_ok(ImportFormat::GenericCsv, data);
+
+        assert_eq!(imported.len(), 1);
+        let bank = &imported[0];
+        assert_eq!(bank.name, "Bank");
+        assert_eq!(bank.entry.password, "s3cret");
+        assert_eq!(bank.entry.username.as_deref(), Some("me"));
+        assert_eq!(bank.entry.urls, ["https://bank.example"]);
+        assert_eq!(bank.entry.notes.as_deref(), Some("call first"));
+        assert_eq!(bank.entry.field("Security question"), Some("Blue"));
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].origin, "row 3");
+    }
+
+    #[test]
+    fn reads_kdbx_with_its_password() {
+        let mut entry = Entry::new("hunter2".to_string());
+        entry.username = Some("octo".to_string());
+        let entries = vec![(ServiceName::new("work/github").unwrap(), entry)];
+        let costs = crate::config::KdfCosts { m_cost: 8, t_cost: 1, p_cost: 1 };
+        let data = kdbx::write(&entries, "kdbx-pw", costs).unwrap();
+
+        assert!(ImportFormat::Kdbx.needs_password());
+        let (imported, rejected) = parse(ImportFormat::Kdbx, &data, Some("kdbx-pw")).unwrap();
+        assert!(rejected.is_empty());
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].name, "work/github");
+        assert_eq!(imported[0].entry.password, "hunter2");
+        assert_eq!(imported[0].entry.username.as_deref(), Some("octo"));
+
+        assert!(parse(ImportFormat::Kdbx, &data, Some("wrong")).is_err());
+        assert!(parse(ImportFormat::Kdbx, &data, None).is_err());
+    }
+
+    #[test]
+    fn sanitizes_names_from_other_managers() {
+        assert_eq!(sanitize_name("/Work//GitHub."), "Work/GitHub");
+        assert_eq!(sanitize_name(" a\tb "), "a b");
+        assert_eq!(sanitize_name(".."), "");
+        assert_eq!(host("https://user@www.example.com:8080/login?x#y"), "www.example.com");
+        assert_eq!(host("example.com"), "example.com");
+    }
+
+    #[test]
+    fn plans_conflicts_by_policy() {
+        let existing = [ServiceName::new("github").unwrap()];
+        let actions = |policy| {
+            let mut rejected = Vec::new();
+            let planned = plan(
+                vec![imported("github"), imported("github"), imported("mail"), imported("..")],
+                &existing,
+                policy,
+                &mut rejected,
+            );
+            assert_eq!(rejected.len(), 1, "'..' must be rejected");
+            planned
+                .into_iter()
+                .map(|p| {
+                    let action = match p.action {
+                        Action::Create => "create",
+                        Action::Overwrite => "overwrite",
+                        Action::Rename { .. } => "rename",
+                        Action::Skip => "skip",
+                    };
+                    (p.service.to_string(), action)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            actions(ConflictPolicy::Skip),
+            [("github".into(), "skip"), ("github".into(), "skip"), ("mail".into(), "create")]
+        );
+        // The second record must not overwrite what the first one just imported
+        assert_eq!(
+            actions(ConflictPolicy::Overwrite),
+            [("github".into(), "overwrite"), ("github (2)".into(), "rename"), ("mail".into(), "create")]
+        );
+        assert_eq!(
+            actions(ConflictPolicy::Rename),
+            [("github (2)".into(), "rename"), ("github (3)".into(), "rename"), ("mail".into(), "create")]
+        );
+    }
+}
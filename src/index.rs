@@ -0,0 +1,246 @@
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use crate::atomic;
+use crate::crypto;
+use crate::error::PassmanError;
+use crate::service_name::ServiceName;
+
+pub const SERVICE_INDEX_FILE: &str = ".passman-index";
+
+const INDEX_MAGIC_NUMBER: &[u8; 4] = b"PMIX";
+const CURRENT_INDEX_VERSION: u8 = 0x01;
+
+/// Maps service names to the opaque file names their entries are stored under,
+/// for vaults whose directory listing should not reveal which services they hold.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceIndex {
+    entries: Vec<(ServiceName, String)>,
+}
+
+impl ServiceIndex {
+    pub fn path(storage_path: &Path) -> PathBuf {
+        storage_path.join(SERVICE_INDEX_FILE)
+    }
+
+    /// Whether the vault stores service names encrypted.
+    pub fn exists(storage_path: &Path) -> bool {
+        Self::path(storage_path).exists()
+    }
+
+    pub fn load(storage_path: &Path, vault_key: &[u8; 32]) -> Result<Self, PassmanError> {
+        let content = fs::read(Self::path(storage_path))?;
+        let header_len = INDEX_MAGIC_NUMBER.len() + 1 + 12;
+        if content.len() < header_len || !content.starts_with(INDEX_MAGIC_NUMBER) {
+            return Err(PassmanError::InvalidFileFormat);
+        }
+        if content[4] != CURRENT_INDEX_VERSION {
+            return Err(PassmanError::UnsupportedVersion);
+        }
+
+        let (header, encrypted) = content.split_at(header_len);
+        let nonce = GenericArray::clone_from_slice(&header[5..]);
+        let cipher = crypto::cipher_from_key(&crypto::derive_index_key(vault_key));
+        let plaintext = cipher.decrypt(
+            &nonce,
+            Payload {
+                msg: encrypted,
+                aad: header,
+            },
+        )?;
+
+        Self::from_bytes(&plaintext)
+    }
+
+    pub fn save(&self, storage_path: &Path, vault_key: &[u8; 32]) -> Result<(), PassmanError> {
+        let nonce = crypto::gen_nonce();
+
+        let mut data = INDEX_MAGIC_NUMBER.to_vec();
+        data.push(CURRENT_INDEX_VERSION);
+        data.extend_from_slice(&nonce);
+
+        let cipher = crypto::cipher_from_key(&crypto::derive_index_key(vault_key));
+        let encrypted = cipher.encrypt(
+            chacha20poly1305::Nonce::from_slice(&nonce),
+            Payload {
+                msg: &self.to_bytes(),
+                aad: &data,
+            },
+        )?;
+        data.extend_from_slice(&encrypted);
+
+        atomic::write_file(&Self::path(storage_path), &data)?;
+        Ok(())
+    }
+
+    pub fn file_name(&self, service_name: &ServiceName) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == service_name)
+            .map(|(_, file_name)| file_name.as_str())
+    }
+
+    pub fn contains_file(&self, file_name: &str) -> bool {
+        self.entries.iter().any(|(_, name)| name == file_name)
+    }
+
+    pub fn services(&self) -> Vec<ServiceName> {
+        let mut services: Vec<ServiceName> =
+            self.entries.iter().map(|(name, _)| name.clone()).collect();
+        services.sort();
+        services
+    }
+
+    /// Records a service under a new random file name and returns it.
+    pub fn insert(&mut self, service_name: &ServiceName) -> String {
+        let file_name = new_file_name();
+        self.entries.push((service_name.clone(), file_name.clone()));
+        file_name
+    }
+
+    pub fn insert_with_file_name(&mut self, service_name: &ServiceName, file_name: &str) {
+        self.entries.push((service_name.clone(), file_name.to_string()));
+    }
+
+    pub fn remove(&mut self, service_name: &ServiceName) -> Option<String> {
+        let position = self.entries.iter().position(|(name, _)| name == service_name)?;
+        Some(self.entries.remove(position).1)
+    }
+
+    pub fn rename(&mut self, service_name: &ServiceName, new_service_name: &ServiceName) {
+        if let Some((name, _)) = self.entries.iter_mut().find(|(name, _)| name == service_name) {
+            *name = new_service_name.clone();
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (service_name, file_name) in &self.entries {
+            for value in [service_name.as_str(), file_name.as_str()] {
+                data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                data.extend_from_slice(value.as_bytes());
+            }
+        }
+        data
+    }
+
+    fn from_bytes(content: &[u8]) -> Result<Self, PassmanError> {
+        let mut cursor = Cursor::new(content);
+        let mut entries = Vec::new();
+
+        let read_string = |cursor: &mut Cursor<&[u8]>| -> Result<String, PassmanError> {
+            let mut len_bytes = [0u8; 4];
+            cursor.read_exact(&mut len_bytes)?;
+            let len = u32::from_le_bytes(len_bytes) as usize;
+            if len > content.len() - cursor.position() as usize {
+                return Err(PassmanError::InvalidFileFormat);
+            }
+            let mut value = vec![0u8; len];
+            cursor.read_exact(&mut value)?;
+            Ok(String::from_utf8(value)?)
+        };
+
+        while (cursor.position() as usize) < content.len() {
+            let service_name = ServiceName::new(&read_string(&mut cursor)?)?;
+            let file_name = read_string(&mut cursor)?;
+            entries.push((service_name, file_name));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+/// 32 hex characters, which says nothing about the service behind it.
+fn new_file_name() -> String {
+    crypto::gen_entry_id()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VAULT_KEY: [u8; 32] = [7; 32];
+
+    fn service(name: &str) -> ServiceName {
+        ServiceName::new(name).unwrap()
+    }
+
+    fn sample() -> ServiceIndex {
+        let mut index = ServiceIndex::default();
+        index.insert(&service("github"));
+        index.insert(&service("work/mail"));
+        index
+    }
+
+    #[test]
+    fn round_trips_through_the_index_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let index = sample();
+        index.save(tmp.path(), &VAULT_KEY).unwrap();
+        assert!(ServiceIndex::exists(tmp.path()));
+
+        let loaded = ServiceIndex::load(tmp.path(), &VAULT_KEY).unwrap();
+        assert_eq!(loaded.entries, index.entries);
+
+        // Nothing about the services is readable on disk
+        let content = fs::read(ServiceIndex::path(tmp.path())).unwrap();
+        assert!(!content.windows(6).any(|window| window == b"github"));
+    }
+
+    #[test]
+    fn rejects_a_wrong_key_or_tampering() {
+        let tmp = tempfile::tempdir().unwrap();
+        sample().save(tmp.path(), &VAULT_KEY).unwrap();
+        assert!(ServiceIndex::load(tmp.path(), &[8; 32]).is_err());
+
+        let path = ServiceIndex::path(tmp.path());
+        let content = fs::read(&path).unwrap();
+        // The nonce and the body
+        for i in [6, content.len() - 1] {
+            let mut tampered = content.clone();
+            tampered[i] ^= 1;
+            fs::write(&path, &tampered).unwrap();
+            assert!(ServiceIndex::load(tmp.path(), &VAULT_KEY).is_err(), "byte {} was not covered", i);
+        }
+
+        let mut newer = content.clone();
+        newer[4] = CURRENT_INDEX_VERSION + 1;
+        fs::write(&path, &newer).unwrap();
+        assert!(matches!(
+            ServiceIndex::load(tmp.path(), &VAULT_KEY),
+            Err(PassmanError::UnsupportedVersion)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_contents() {
+        let data = sample().to_bytes();
+        assert!(ServiceIndex::from_bytes(&data[..data.len() - 1]).is_err());
+        assert!(ServiceIndex::from_bytes(&data[..3]).is_err());
+        assert!(ServiceIndex::from_bytes(&[]).unwrap().services().is_empty());
+    }
+
+    #[test]
+    fn tracks_renames_and_removals() {
+        let mut index = sample();
+        let file_name = index.file_name(&service("github")).unwrap().to_string();
+        assert_eq!(file_name.len(), 32);
+        assert_ne!(index.file_name(&service("work/mail")), Some(file_name.as_str()));
+
+        index.rename(&service("github"), &service("code/github"));
+        assert_eq!(index.file_name(&service("github")), None);
+        assert_eq!(index.file_name(&service("code/github")), Some(file_name.as_str()));
+        assert!(index.contains_file(&file_name));
+
+        assert_eq!(index.remove(&service("code/github")), Some(file_name.clone()));
+        assert!(!index.contains_file(&file_name));
+        assert_eq!(index.remove(&service("code/github")), None);
+        assert_eq!(index.services(), [service("work/mail")]);
+    }
+}
@@ -0,0 +1,970 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+
+use aes::Aes256;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64ct::{Base64, Encoding};
+use chacha20::ChaCha20;
+use chacha20::cipher::{BlockDecryptMut, KeyIvInit, StreamCipher, block_padding::Pkcs7};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use hmac::{Hmac, Mac};
+use quick_xml::Reader;
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256, Sha512};
+use zeroize::Zeroizing;
+
+use crate::config::KdfCosts;
+use crate::entry::Entry;
+use crate::error::PassmanError;
+use crate::import::{self, ImportedEntry, Rejected};
+use crate::service_name::ServiceName;
+
+const SIGNATURE_1: u32 = 0x9AA2_D903;
+const SIGNATURE_2: u32 = 0xB54B_FB67;
+const MAJOR_VERSION_4: u32 = 4;
+// 4.0 is what every KeePass client from the last years reads
+const WRITTEN_VERSION: u32 = 0x0004_0000;
+
+// Outer header fields
+const HEADER_END: u8 = 0;
+const HEADER_CIPHER_ID: u8 = 2;
+const HEADER_COMPRESSION: u8 = 3;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_ENCRYPTION_IV: u8 = 7;
+const HEADER_KDF_PARAMETERS: u8 = 11;
+
+// Inner header fields, at the start of the decrypted payload
+const INNER_HEADER_END: u8 = 0;
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+const INNER_STREAM_CHACHA20: u32 = 3;
+
+const COMPRESSION_NONE: u32 = 0;
+const COMPRESSION_GZIP: u32 = 1;
+
+const CIPHER_AES256: [u8; 16] = [
+    0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff,
+];
+const CIPHER_CHACHA20: [u8; 16] = [
+    0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a,
+];
+const KDF_ARGON2D: [u8; 16] = [
+    0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c,
+];
+const KDF_ARGON2ID: [u8; 16] = [
+    0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6,
+];
+
+// Variant dictionary value types, used for the KDF parameters
+const VARIANT_VERSION: u16 = 0x0100;
+const VARIANT_END: u8 = 0x00;
+const VARIANT_UINT32: u8 = 0x04;
+const VARIANT_UINT64: u8 = 0x05;
+const VARIANT_BYTES: u8 = 0x42;
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+// The costs come from an untrusted file, refuse any that would take the machine down.
+// KeePassXC's defaults are 64 MiB, 10 iterations and 2 lanes
+const MAX_KDF_MEMORY_KIB: u64 = 4 * 1024 * 1024;
+const MAX_KDF_ITERATIONS: u64 = 1000;
+const MAX_KDF_LANES: u64 = 16;
+// Block index the header HMAC is keyed with
+const HEADER_BLOCK_INDEX: u64 = u64::MAX;
+
+// KeePass times count seconds from 0001-01-01, Unix ones from 1970-01-01
+const UNIX_EPOCH_IN_KEEPASS_SECONDS: i64 = 62_135_596_800;
+
+// Standard entry strings, anything else is a custom field
+const TITLE: &str = "Title";
+const USERNAME: &str = "UserName";
+const PASSWORD: &str = "Password";
+const URL: &str = "URL";
+const NOTES: &str = "Notes";
+const STANDARD_KEYS: &[&str] = &[TITLE, USERNAME, PASSWORD, URL, NOTES];
+// KeePassXC keeps TOTP secrets in "otp" and extra URLs in "KP2A_URL", "KP2A_URL_1"...
+const KEEPASS_OTP: &str = "otp";
+const PASSMAN_OTP: &str = "totp";
+const EXTRA_URL_PREFIX: &str = "KP2A_URL";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Reads a KDBX 4 database protected by `password` alone.
+/// Entries in the recycle bin and history are left out, attachments are not read.
+pub fn read(data: &[u8], password: &str) -> Result<(Vec<ImportedEntry>, Vec<Rejected>), PassmanError> {
+    let (header, header_len) = OuterHeader::read(data)?;
+    let (header_bytes, rest) = data.split_at(header_len);
+
+    let (stored_hash, rest) = take(rest, 32)?;
+    if Sha256::digest(header_bytes).as_slice() != stored_hash {
+        return Err(invalid("the header is damaged"));
+    }
+
+    let keys = Keys::derive(password, &header.master_seed, &header.kdf)?;
+    let (stored_hmac, rest) = take(rest, 32)?;
+    keys.block_hmac(HEADER_BLOCK_INDEX, header_bytes)
+        .verify_slice(stored_hmac)
+        .map_err(|_| invalid("wrong password, or the database also needs a key file"))?;
+
+    let encrypted = read_blocks(rest, &keys)?;
+    let compressed = Zeroizing::new(header.cipher.decrypt(keys.encryption_key.as_slice(), &header.iv, encrypted)?);
+
+    let payload = match header.compression {
+        COMPRESSION_GZIP => {
+            let mut payload = Zeroizing::new(Vec::new());
+            GzDecoder::new(compressed.as_slice())
+                .read_to_end(&mut payload)
+                .map_err(|e| invalid(&format!("can't decompress the content: {}", e)))?;
+            payload
+        }
+        _ => compressed,
+    };
+
+    let (mut inner_stream, xml) = read_inner_header(&payload)?;
+    read_xml(xml, &mut inner_stream)
+}
+
+/// Writes `entries` as a KDBX 4 database protected by `password`, using ChaCha20 and Argon2id.
+/// Service names like `work/github` become an entry `github` in a group `work`.
+pub fn write(
+    entries: &[(ServiceName, Entry)],
+    password: &str,
+    kdf: KdfCosts,
+) -> Result<Vec<u8>, PassmanError> {
+    let header = OuterHeader {
+        cipher: Cipher::ChaCha20,
+        compression: COMPRESSION_GZIP,
+        master_seed: random_bytes::<32>().to_vec(),
+        iv: random_bytes::<12>().to_vec(),
+        kdf: KdfSettings {
+            algorithm: Algorithm::Argon2id,
+            salt: random_bytes::<32>().to_vec(),
+            m_cost: kdf.m_cost,
+            t_cost: kdf.t_cost,
+            p_cost: kdf.p_cost,
+        },
+    };
+    let header_bytes = header.to_bytes();
+    let keys = Keys::derive(password, &header.master_seed, &header.kdf)?;
+
+    let inner_key = random_bytes::<64>();
+    let mut inner_stream = inner_stream_cipher(&inner_key);
+
+    let mut payload = Zeroizing::new(Vec::new());
+    write_field(&mut payload, INNER_STREAM_ID, &INNER_STREAM_CHACHA20.to_le_bytes());
+    write_field(&mut payload, INNER_STREAM_KEY, &inner_key);
+    write_field(&mut payload, INNER_HEADER_END, &[]);
+    payload.extend_from_slice(write_xml(entries, &mut inner_stream).as_bytes());
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&payload)?;
+    let mut content = encoder.finish()?;
+    ChaCha20::new(keys.encryption_key.as_slice().into(), header.iv.as_slice().into())
+        .apply_keystream(&mut content);
+
+    let mut file = header_bytes.clone();
+    file.extend_from_slice(&Sha256::digest(&header_bytes));
+    file.extend_from_slice(&keys.block_hmac(HEADER_BLOCK_INDEX, &header_bytes).finalize().into_bytes());
+    write_blocks(&mut file, &content, &keys);
+
+    Ok(file)
+}
+
+enum Cipher {
+    Aes256,
+    ChaCha20,
+}
+
+impl Cipher {
+    fn decrypt(&self, key: &[u8], iv: &[u8], mut data: Vec<u8>) -> Result<Vec<u8>, PassmanError> {
+        match self {
+            Cipher::Aes256 => cbc::Decryptor::<Aes256>::new_from_slices(key, iv)
+                .map_err(|_| invalid("bad AES IV"))?
+                .decrypt_padded_vec_mut::<Pkcs7>(&data)
+                .map_err(|_| invalid("the content is damaged")),
+            Cipher::ChaCha20 => {
+                ChaCha20::new_from_slices(key, iv)
+                    .map_err(|_| invalid("bad ChaCha20 nonce"))?
+                    .apply_keystream(&mut data);
+                Ok(data)
+            }
+        }
+    }
+
+    fn id(&self) -> [u8; 16] {
+        match self {
+            Cipher::Aes256 => CIPHER_AES256,
+            Cipher::ChaCha20 => CIPHER_CHACHA20,
+        }
+    }
+}
+
+struct KdfSettings {
+    algorithm: Algorithm,
+    salt: Vec<u8>,
+    /// In KiB, the file stores bytes
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl KdfSettings {
+    fn from_variants(variants: &HashMap<String, Vec<u8>>) -> Result<Self, PassmanError> {
+        let algorithm = match variants.get("$UUID").map(Vec::as_slice) {
+            Some(uuid) if uuid == KDF_ARGON2D => Algorithm::Argon2d,
+            Some(uuid) if uuid == KDF_ARGON2ID => Algorithm::Argon2id,
+            _ => {
+                return Err(invalid(
+                    "only Argon2 protected databases are supported, change the key derivation in KeePassXC's database settings",
+                ));
+            }
+        };
+
+        let number = |name: &str| -> Result<u64, PassmanError> {
+            let bytes = variants.get(name).ok_or_else(|| invalid("incomplete key derivation settings"))?;
+            match bytes.len() {
+                4 => Ok(u64::from(u32::from_le_bytes(bytes[..].try_into().unwrap()))),
+                8 => Ok(u64::from_le_bytes(bytes[..].try_into().unwrap())),
+                _ => Err(invalid("bad key derivation settings")),
+            }
+        };
+        let bounded = |value: u64, max: u64, what: &str| {
+            if value > max {
+                return Err(invalid(&format!(
+                    "the key derivation {} is above passman's limit of {}, lower it in KeePassXC's database settings",
+                    what, max
+                )));
+            }
+            u32::try_from(value).map_err(|_| invalid("key derivation settings out of range"))
+        };
+
+        Ok(Self {
+            algorithm,
+            salt: variants.get("S").cloned().ok_or_else(|| invalid("no key derivation salt"))?,
+            m_cost: bounded(number("M")? / 1024, MAX_KDF_MEMORY_KIB, "memory (KiB)")?,
+            t_cost: bounded(number("I")?, MAX_KDF_ITERATIONS, "iteration count")?,
+            p_cost: bounded(number("P")?, MAX_KDF_LANES, "parallelism")?,
+        })
+    }
+
+    fn to_variants(&self) -> Vec<u8> {
+        let uuid = match self.algorithm {
+            Algorithm::Argon2d => KDF_ARGON2D,
+            _ => KDF_ARGON2ID,
+        };
+
+        let mut data = VARIANT_VERSION.to_le_bytes().to_vec();
+        write_variant(&mut data, VARIANT_BYTES, "$UUID", &uuid);
+        write_variant(&mut data, VARIANT_BYTES, "S", &self.salt);
+        write_variant(&mut data, VARIANT_UINT32, "P", &self.p_cost.to_le_bytes());
+        write_variant(&mut data, VARIANT_UINT64, "M", &(u64::from(self.m_cost) * 1024).to_le_bytes());
+        write_variant(&mut data, VARIANT_UINT64, "I", &u64::from(self.t_cost).to_le_bytes());
+        write_variant(&mut data, VARIANT_UINT32, "V", &0x13u32.to_le_bytes());
+        data.push(VARIANT_END);
+        data
+    }
+}
+
+/// The clear-text header before the encrypted blocks.
+struct OuterHeader {
+    cipher: Cipher,
+    compression: u32,
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf: KdfSettings,
+}
+
+impl OuterHeader {
+    /// Parses the header, returning it along with its length in bytes.
+    fn read(data: &[u8]) -> Result<(Self, usize), PassmanError> {
+        let (signature, mut rest) = take(data, 12)?;
+        let word = |i: usize| u32::from_le_bytes(signature[i * 4..i * 4 + 4].try_into().unwrap());
+        if word(0) != SIGNATURE_1 || word(1) != SIGNATURE_2 {
+            return Err(invalid("not a KeePass database"));
+        }
+        if word(2) >> 16 != MAJOR_VERSION_4 {
+            return Err(invalid(
+                "only KDBX 4 databases are supported, save it as KDBX 4 in KeePassXC first",
+            ));
+        }
+
+        let mut fields = HashMap::new();
+        loop {
+            let (id, data, next) = read_field(rest)?;
+            rest = next;
+            if id == HEADER_END {
+                break;
+            }
+            fields.insert(id, data.to_vec());
+        }
+        let header_len = data.len() - rest.len();
+
+        let field = |id: u8, name: &str| {
+            fields
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| invalid(&format!("the header has no {}", name)))
+        };
+
+        let cipher = match field(HEADER_CIPHER_ID, "cipher")?.as_slice() {
+            id if id == CIPHER_AES256 => Cipher::Aes256,
+            id if id == CIPHER_CHACHA20 => Cipher::ChaCha20,
+            _ => return Err(invalid("only AES-256 and ChaCha20 encrypted databases are supported")),
+        };
+        let compression = match field(HEADER_COMPRESSION, "compression")?.as_slice() {
+            [a, b, c, d] => u32::from_le_bytes([*a, *b, *c, *d]),
+            _ => return Err(invalid("bad compression setting")),
+        };
+        if compression != COMPRESSION_NONE && compression != COMPRESSION_GZIP {
+            return Err(invalid("unknown compression"));
+        }
+        let master_seed = field(HEADER_MASTER_SEED, "master seed")?;
+        if master_seed.len() != 32 {
+            return Err(invalid("bad master seed"));
+        }
+
+        let header = Self {
+            cipher,
+            compression,
+            master_seed,
+            iv: field(HEADER_ENCRYPTION_IV, "encryption IV")?,
+            kdf: KdfSettings::from_variants(&read_variants(&field(HEADER_KDF_PARAMETERS, "key derivation settings")?)?)?,
+        };
+        Ok((header, header_len))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&SIGNATURE_1.to_le_bytes());
+        data.extend_from_slice(&SIGNATURE_2.to_le_bytes());
+        data.extend_from_slice(&WRITTEN_VERSION.to_le_bytes());
+
+        write_field(&mut data, HEADER_CIPHER_ID, &self.cipher.id());
+        write_field(&mut data, HEADER_COMPRESSION, &self.compression.to_le_bytes());
+        write_field(&mut data, HEADER_MASTER_SEED, &self.master_seed);
+        write_field(&mut data, HEADER_ENCRYPTION_IV, &self.iv);
+        write_field(&mut data, HEADER_KDF_PARAMETERS, &self.kdf.to_variants());
+        write_field(&mut data, HEADER_END, b"\r\n\r\n");
+        data
+    }
+}
+
+/// Keys derived from the password and the file's master seed.
+struct Keys {
+    encryption_key: Zeroizing<[u8; 32]>,
+    hmac_key: Zeroizing<[u8; 64]>,
+}
+
+impl Keys {
+    fn derive(password: &str, master_seed: &[u8], kdf: &KdfSettings) -> Result<Self, PassmanError> {
+        // The composite key hashes each part, a password is the only part passman supports
+        let composite = Sha256::digest(Sha256::digest(password.as_bytes()));
+
+        let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32))?;
+        let mut transformed = Zeroizing::new([0u8; 32]);
+        Argon2::new(kdf.algorithm, Version::V0x13, params).hash_password_into(
+            &composite,
+            &kdf.salt,
+            transformed.as_mut_slice(),
+        )?;
+
+        let mut encryption_key = Zeroizing::new([0u8; 32]);
+        encryption_key.copy_from_slice(&Sha256::new().chain_update(master_seed).chain_update(*transformed).finalize());
+
+        let mut hmac_key = Zeroizing::new([0u8; 64]);
+        hmac_key.copy_from_slice(
+            &Sha512::new()
+                .chain_update(master_seed)
+                .chain_update(*transformed)
+                .chain_update([1u8])
+                .finalize(),
+        );
+
+        Ok(Self { encryption_key, hmac_key })
+    }
+
+    /// HMAC over `data` keyed for block `index`. Data blocks also cover their index and length.
+    fn block_hmac(&self, index: u64, data: &[u8]) -> HmacSha256 {
+        let block_key = Sha512::new()
+            .chain_update(index.to_le_bytes())
+            .chain_update(*self.hmac_key)
+            .finalize();
+        let mut mac = HmacSha256::new_from_slice(&block_key).expect("HMAC takes keys of any length");
+        mac.update(data);
+        mac
+    }
+}
+
+/// Checks and joins the HMAC protected blocks that follow the header.
+fn read_blocks(mut data: &[u8], keys: &Keys) -> Result<Vec<u8>, PassmanError> {
+    let mut content = Vec::new();
+
+    for index in 0u64.. {
+        let (stored_hmac, rest) = take(data, 32)?;
+        let (length_bytes, rest) = take(rest, 4)?;
+        let length = u32::from_le_bytes(length_bytes.try_into().unwrap()) as usize;
+        let (block, rest) = take(rest, length)?;
+        data = rest;
+
+        let mut mac = keys.block_hmac(index, &index.to_le_bytes());
+        mac.update(length_bytes);
+        mac.update(block);
+        mac.verify_slice(stored_hmac)
+            .map_err(|_| invalid(&format!("block {} is damaged", index)))?;
+
+        if length == 0 {
+            break;
+        }
+        content.extend_from_slice(block);
+    }
+
+    Ok(content)
+}
+
+fn write_blocks(file: &mut Vec<u8>, content: &[u8], keys: &Keys) {
+    // An empty block marks the end
+    let blocks = content.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..]));
+
+    for (index, block) in (0u64..).zip(blocks) {
+        let length = (block.len() as u32).to_le_bytes();
+        let mut mac = keys.block_hmac(index, &index.to_le_bytes());
+        mac.update(&length);
+        mac.update(block);
+
+        file.extend_from_slice(&mac.finalize().into_bytes());
+        file.extend_from_slice(&length);
+        file.extend_from_slice(block);
+    }
+}
+
+/// Reads the inner header, returning the cipher for protected values and the XML after it.
+fn read_inner_header(payload: &[u8]) -> Result<(ChaCha20, &[u8]), PassmanError> {
+    let mut rest = payload;
+    let mut stream_id = None;
+    let mut stream_key = None;
+
+    loop {
+        let (id, data, next) = read_field(rest)?;
+        rest = next;
+        match id {
+            INNER_HEADER_END => break,
+            INNER_STREAM_ID => stream_id = data.try_into().ok().map(u32::from_le_bytes),
+            INNER_STREAM_KEY => stream_key = Some(data),
+            // Attachments
+            _ => {}
+        }
+    }
+
+    match (stream_id, stream_key) {
+        (Some(INNER_STREAM_CHACHA20), Some(key)) => Ok((inner_stream_cipher(key), rest)),
+        _ => Err(invalid("only ChaCha20 protected values are supported")),
+    }
+}
+
+fn inner_stream_cipher(key: &[u8]) -> ChaCha20 {
+    let hash = Sha512::digest(key);
+    ChaCha20::new(hash[..32].into(), hash[32..44].into())
+}
+
+/// Where the XML reader is, for entries being read.
+#[derive(Default)]
+struct EntryState {
+    strings: Vec<(String, String)>,
+    created: Option<u64>,
+    modified: Option<u64>,
+}
+
+struct GroupState {
+    name: String,
+    recycled: bool,
+}
+
+fn read_xml(xml: &[u8], inner_stream: &mut ChaCha20) -> Result<(Vec<ImportedEntry>, Vec<Rejected>), PassmanError> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+
+    let mut imported = Vec::new();
+    let mut rejected = Vec::new();
+
+    let mut path: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut protected = false;
+    let mut recycle_bin = None;
+    let mut groups: Vec<GroupState> = Vec::new();
+    let mut entry: Option<EntryState> = None;
+    let mut key = String::new();
+    let mut value = String::new();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| invalid(&format!("bad XML: {}", e)))?;
+
+        match event {
+            // `<Value Protected="True"/>` is empty and takes nothing from the keystream
+            Event::Empty(_) => {}
+            Event::Start(start) => {
+                let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+                text.clear();
+                protected = name == "Value" && is_protected(&start);
+
+                let in_history = path.iter().any(|n| n == "History");
+                match name.as_str() {
+                    "Group" => groups.push(GroupState {
+                        name: String::new(),
+                        recycled: groups.last().is_some_and(|g| g.recycled),
+                    }),
+                    "Entry" if !in_history => entry = Some(EntryState::default()),
+                    "String" => {
+                        key.clear();
+                        value.clear();
+                    }
+                    _ => {}
+                }
+                path.push(name);
+            }
+            Event::Text(t) => text.push_str(&t.unescape().map_err(|e| invalid(&format!("bad XML: {}", e)))?),
+            Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+            Event::End(_) => {
+                let name = path.pop().unwrap_or_default();
+                let parent = path.last().map(String::as_str).unwrap_or("");
+                let in_history = path.iter().any(|n| n == "History");
+
+                match (parent, name.as_str()) {
+                    ("Meta", "RecycleBinUUID") => recycle_bin = Some(text.trim().to_string()),
+                    ("Group", "Name") => {
+                        if let Some(group) = groups.last_mut() {
+                            group.name = text.clone();
+                        }
+                    }
+                    ("Group", "UUID") => {
+                        if let Some(group) = groups.last_mut() {
+                            group.recycled |= recycle_bin.as_deref() == Some(text.trim());
+                        }
+                    }
+                    ("String", "Key") => key = text.clone(),
+                    ("String", "Value") => {
+                        // Every protected value takes its share of the keystream, in document order
+                        value = if protected { unprotect(&text, inner_stream)? } else { text.clone() };
+                    }
+                    ("Entry", "String") if !in_history => {
+                        if let Some(entry) = entry.as_mut() {
+                            entry.strings.push((key.clone(), value.clone()));
+                        }
+                    }
+                    ("Times", "CreationTime") | ("Times", "LastModificationTime") if !in_history => {
+                        if let Some(entry) = entry.as_mut() {
+                            let time = parse_time(text.trim());
+                            if name == "CreationTime" {
+                                entry.created = time;
+                            } else {
+                                entry.modified = time;
+                            }
+                        }
+                    }
+                    (_, "Entry") if !in_history => {
+                        let finished = entry.take().unwrap_or_default();
+                        if groups.last().is_some_and(|g| g.recycled) {
+                            continue;
+                        }
+                        // The root group is the database itself, not a folder
+                        let folders: Vec<&str> = groups.iter().skip(1).map(|g| g.name.as_str()).collect();
+                        match to_imported(finished, &folders) {
+                            Ok(item) => imported.push(item),
+                            Err(item) => rejected.push(item),
+                        }
+                    }
+                    (_, "Group") => {
+                        groups.pop();
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok((imported, rejected))
+}
+
+fn is_protected(start: &BytesStart) -> bool {
+    matches!(
+        start.try_get_attribute("Protected"),
+        Ok(Some(attribute)) if attribute.value.eq_ignore_ascii_case(b"true")
+    )
+}
+
+fn unprotect(text: &str, inner_stream: &mut ChaCha20) -> Result<String, PassmanError> {
+    let mut bytes = Base64::decode_vec(text.trim()).map_err(|_| invalid("bad protected value"))?;
+    inner_stream.apply_keystream(&mut bytes);
+    String::from_utf8(bytes).map_err(|_| invalid("bad protected value"))
+}
+
+fn to_imported(state: EntryState, folders: &[&str]) -> Result<ImportedEntry, Rejected> {
+    let mut title = None;
+    let mut entry = Entry::new(String::new());
+    entry.created = state.created.or(entry.created);
+    entry.modified = state.modified.or(entry.modified);
+
+    for (key, value) in state.strings {
+        if value.is_empty() {
+            continue;
+        }
+        match key.as_str() {
+            TITLE => title = Some(value),
+            USERNAME => entry.username = Some(value),
+            PASSWORD => entry.password = value,
+            URL => entry.urls.insert(0, value),
+            NOTES => entry.notes = Some(value),
+            KEEPASS_OTP => entry.set_field(PASSMAN_OTP, &value),
+            _ if key.starts_with(EXTRA_URL_PREFIX) => entry.urls.push(value),
+            _ => entry.set_field(&key, &value),
+        }
+    }
+
+    let Some(title) = title.or_else(|| entry.urls.first().map(|url| import::host(url).to_string())) else {
+        let origin = match folders.is_empty() {
+            true => "an entry".to_string(),
+            false => format!("an entry in '{}'", folders.join("/")),
+        };
+        return Err(Rejected {
+            origin,
+            reason: "no title or URL to name it after".to_string(),
+        });
+    };
+
+    let mut name = folders.join("/");
+    if !name.is_empty() {
+        name.push('/');
+    }
+    name.push_str(&title);
+
+    Ok(ImportedEntry {
+        origin: format!("entry '{}'", title),
+        name,
+        entry,
+    })
+}
+
+/// Groups built from the `/` separated parts of service names.
+#[derive(Default)]
+struct GroupNode<'a> {
+    entries: Vec<(&'a str, &'a Entry)>,
+    groups: BTreeMap<&'a str, GroupNode<'a>>,
+}
+
+fn write_xml(entries: &[(ServiceName, Entry)], inner_stream: &mut ChaCha20) -> Zeroizing<String> {
+    let mut root = GroupNode::default();
+    for (service, entry) in entries {
+        let mut parts: Vec<&str> = service.as_str().split('/').collect();
+        let title = parts.pop().unwrap_or_default();
+
+        let mut group = &mut root;
+        for part in parts {
+            group = group.groups.entry(part).or_default();
+        }
+        group.entries.push((title, entry));
+    }
+
+    let mut xml = Zeroizing::new(String::new());
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<KeePassFile>\n");
+    xml.push_str("<Meta><Generator>passman</Generator><DatabaseName>passman</DatabaseName>");
+    xml.push_str("<MemoryProtection><ProtectTitle>False</ProtectTitle><ProtectUserName>False</ProtectUserName>");
+    xml.push_str("<ProtectPassword>True</ProtectPassword><ProtectURL>False</ProtectURL><ProtectNotes>False</ProtectNotes>");
+    xml.push_str("</MemoryProtection></Meta>\n<Root>\n");
+    write_group(&mut xml, "passman", &root, inner_stream);
+    xml.push_str("</Root>\n</KeePassFile>\n");
+    xml
+}
+
+fn write_group(xml: &mut String, name: &str, group: &GroupNode, inner_stream: &mut ChaCha20) {
+    xml.push_str(&format!(
+        "<Group><UUID>{}</UUID><Name>{}</Name>\n",
+        Base64::encode_string(&random_bytes::<16>()),
+        xml_text(name)
+    ));
+
+    for (title, entry) in &group.entries {
+        write_entry(xml, title, entry, inner_stream);
+    }
+    for (name, child) in &group.groups {
+        write_group(xml, name, child, inner_stream);
+    }
+
+    xml.push_str("</Group>\n");
+}
+
+fn write_entry(xml: &mut String, title: &str, entry: &Entry, inner_stream: &mut ChaCha20) {
+    let now = crate::entry::unix_now();
+    xml.push_str(&format!(
+        "<Entry><UUID>{}</UUID><Times><CreationTime>{}</CreationTime><LastModificationTime>{}</LastModificationTime><Expires>False</Expires></Times>\n",
+        Base64::encode_string(&random_bytes::<16>()),
+        format_time(entry.created.unwrap_or(now)),
+        format_time(entry.modified.unwrap_or(now)),
+    ));
+
+    let mut write_string = |key: &str, value: &str, protect: bool| {
+        if protect {
+            let mut bytes = value.as_bytes().to_vec();
+            inner_stream.apply_keystream(&mut bytes);
+            xml.push_str(&format!(
+                "<String><Key>{}</Key><Value Protected=\"True\">{}</Value></String>\n",
+                xml_text(key),
+                Base64::encode_string(&bytes)
+            ));
+        } else {
+            xml.push_str(&format!(
+                "<String><Key>{}</Key><Value>{}</Value></String>\n",
+                xml_text(key),
+                xml_text(value)
+            ));
+        }
+    };
+
+    write_string(TITLE, title, false);
+    write_string(USERNAME, entry.username.as_deref().unwrap_or(""), false);
+    write_string(PASSWORD, &entry.password, true);
+    write_string(URL, entry.urls.first().map(String::as_str).unwrap_or(""), false);
+    write_string(NOTES, entry.notes.as_deref().unwrap_or(""), false);
+
+    for (i, url) in entry.urls.iter().skip(1).enumerate() {
+        let key = match i {
+            0 => EXTRA_URL_PREFIX.to_string(),
+            _ => format!("{}_{}", EXTRA_URL_PREFIX, i),
+        };
+        write_string(&key, url, false);
+    }
+    for (name, value) in &entry.fields {
+        let name = match name.as_str() {
+            PASSMAN_OTP => KEEPASS_OTP.to_string(),
+            // KeePass keys are unique, a custom field can't take a standard one's name
+            _ if STANDARD_KEYS.contains(&name.as_str()) => format!("{} (field)", name),
+            _ => name.clone(),
+        };
+        // passman never shows custom fields unless asked, so they are kept protected
+        write_string(&name, value, true);
+    }
+
+    xml.push_str("</Entry>\n");
+}
+
+/// Escapes `text` for XML, dropping the characters XML 1.0 doesn't allow at all
+/// (control characters other than tab and line breaks), which would make the file unreadable.
+fn xml_text(text: &str) -> String {
+    // The `Char` production of the XML 1.0 spec
+    let allowed = |c: &char| {
+        matches!(c, '\t' | '\n' | '\r' | ' '..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+    };
+    let cleaned: String = text.chars().filter(allowed).collect();
+    escape(&cleaned).into_owned()
+}
+
+/// KDBX 4 stores times as base64 of little-endian seconds since 0001-01-01.
+fn parse_time(text: &str) -> Option<u64> {
+    let bytes: [u8; 8] = Base64::decode_vec(text).ok()?.try_into().ok()?;
+    let seconds = i64::from_le_bytes(bytes) - UNIX_EPOCH_IN_KEEPASS_SECONDS;
+    u64::try_from(seconds).ok()
+}
+
+fn format_time(unix_seconds: u64) -> String {
+    let seconds = unix_seconds as i64 + UNIX_EPOCH_IN_KEEPASS_SECONDS;
+    Base64::encode_string(&seconds.to_le_bytes())
+}
+
+fn read_field(data: &[u8]) -> Result<(u8, &[u8], &[u8]), PassmanError> {
+    let (id, rest) = take(data, 1)?;
+    let (length, rest) = take(rest, 4)?;
+    let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+    let (field, rest) = take(rest, length)?;
+    Ok((id[0], field, rest))
+}
+
+fn write_field(data: &mut Vec<u8>, id: u8, field: &[u8]) {
+    data.push(id);
+    data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    data.extend_from_slice(field);
+}
+
+/// Reads a variant dictionary, keeping each value's raw bytes.
+fn read_variants(data: &[u8]) -> Result<HashMap<String, Vec<u8>>, PassmanError> {
+    let (version, mut rest) = take(data, 2)?;
+    if version[1] != (VARIANT_VERSION >> 8) as u8 {
+        return Err(invalid("unknown key derivation settings format"));
+    }
+
+    let mut variants = HashMap::new();
+    loop {
+        let (kind, next) = take(rest, 1)?;
+        if kind[0] == VARIANT_END {
+            return Ok(variants);
+        }
+        let (name_len, next) = take(next, 4)?;
+        let (name, next) = take(next, u32::from_le_bytes(name_len.try_into().unwrap()) as usize)?;
+        let (value_len, next) = take(next, 4)?;
+        let (value, next) = take(next, u32::from_le_bytes(value_len.try_into().unwrap()) as usize)?;
+        variants.insert(String::from_utf8_lossy(name).into_owned(), value.to_vec());
+        rest = next;
+    }
+}
+
+fn write_variant(data: &mut Vec<u8>, kind: u8, name: &str, value: &[u8]) {
+    data.push(kind);
+    data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    data.extend_from_slice(name.as_bytes());
+    data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    data.extend_from_slice(value);
+}
+
+fn take(data: &[u8], len: usize) -> Result<(&[u8], &[u8]), PassmanError> {
+    if data.len() < len {
+        return Err(invalid("the file is truncated"));
+    }
+    Ok(data.split_at(len))
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    ChaCha20Rng::from_os_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+fn invalid(reason: &str) -> PassmanError {
+    PassmanError::InvalidImport(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written by tests/fixtures/kdbx/make_fixtures.py, an implementation independent of this one
+    const FIXTURES: &[(&str, &[u8])] = &[
+        ("AES-256 and Argon2d", include_bytes!("../tests/fixtures/kdbx/aes-argon2d.kdbx")),
+        ("ChaCha20 and Argon2id", include_bytes!("../tests/fixtures/kdbx/chacha20-argon2id.kdbx")),
+    ];
+    const FIXTURE_PASSWORD: &str = "keepass-pw";
+
+    fn weak_costs() -> KdfCosts {
+        KdfCosts { m_cost: 8, t_cost: 1, p_cost: 1 }
+    }
+
+    fn to_entries(imported: Vec<ImportedEntry>) -> Vec<(ServiceName, Entry)> {
+        imported
+            .into_iter()
+            .map(|i| (ServiceName::new(&i.name).unwrap(), i.entry))
+            .collect()
+    }
+
+    #[test]
+    fn reads_keepassxc_databases() {
+        for (name, data) in FIXTURES {
+            let (imported, rejected) = read(data, FIXTURE_PASSWORD).unwrap();
+
+            // History, the recycle bin and the entry without a title or URL are left out
+            let names: Vec<&str> = imported.iter().map(|i| i.name.as_str()).collect();
+            assert_eq!(names, ["GitHub", "Work/Mail/Outlook", "Work/jira.example.com", "Last <one>"], "{}", name);
+            assert_eq!(rejected.len(), 1, "{}", name);
+            assert_eq!(rejected[0].origin, "an entry in 'Work'");
+
+            let github = &imported[0].entry;
+            assert_eq!(github.password, "gh-s3cret<&>");
+            assert_eq!(github.username.as_deref(), Some("octo & cat"));
+            assert_eq!(github.urls, ["https://github.com/login", "https://gist.github.com"]);
+            assert_eq!(github.notes.as_deref(), Some("line one\nline two"));
+            assert_eq!(github.field(PASSMAN_OTP), Some("otpauth://totp/x?secret=ABC"));
+            assert_eq!(github.field("Recovery codes"), Some("rec-123 rec-456"));
+            assert_eq!(github.field("Employee ID"), Some("E-42"));
+            assert_eq!(github.field("Empty"), None);
+            assert_eq!(github.created, Some(1_600_000_000));
+            assert_eq!(github.modified, Some(1_700_000_000));
+
+            assert_eq!(imported[1].entry.password, "mail-pw ü");
+            assert_eq!(imported[2].entry.password, "jira");
+            assert_eq!(imported[3].entry.password, "last-pw");
+        }
+    }
+
+    #[test]
+    fn writes_back_what_it_read() {
+        for (name, data) in FIXTURES {
+            let (imported, _) = read(data, FIXTURE_PASSWORD).unwrap();
+            let mut entries = to_entries(imported);
+
+            let written = write(&entries, "passman-pw", weak_costs()).unwrap();
+            let (reread, rejected) = read(&written, "passman-pw").unwrap();
+            assert!(rejected.is_empty(), "{}", name);
+
+            let mut reread = to_entries(reread);
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            reread.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(reread, entries, "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_a_wrong_password_or_damaged_file() {
+        let (_, data) = FIXTURES[0];
+        assert!(read(data, "wrong").is_err());
+
+        let mut damaged = data.to_vec();
+        damaged[20] ^= 1;
+        assert!(read(&damaged, FIXTURE_PASSWORD).is_err());
+
+        let mut damaged = data.to_vec();
+        let last = damaged.len() - 40;
+        damaged[last] ^= 1;
+        assert!(read(&damaged, FIXTURE_PASSWORD).is_err());
+
+        assert!(read(&data[..data.len() / 2], FIXTURE_PASSWORD).is_err());
+    }
+
+    #[test]
+    fn refuses_excessive_key_derivation_costs() {
+        let variants = |m: u64, i: u64, p: u32| -> HashMap<String, Vec<u8>> {
+            HashMap::from([
+                ("$UUID".to_string(), KDF_ARGON2ID.to_vec()),
+                ("S".to_string(), vec![0; 32]),
+                ("M".to_string(), (m * 1024).to_le_bytes().to_vec()),
+                ("I".to_string(), i.to_le_bytes().to_vec()),
+                ("P".to_string(), p.to_le_bytes().to_vec()),
+            ])
+        };
+
+        assert!(KdfSettings::from_variants(&variants(64 * 1024, 10, 2)).is_ok());
+        assert!(KdfSettings::from_variants(&variants(MAX_KDF_MEMORY_KIB, MAX_KDF_ITERATIONS, 16)).is_ok());
+        assert!(KdfSettings::from_variants(&variants(MAX_KDF_MEMORY_KIB + 1, 10, 2)).is_err());
+        assert!(KdfSettings::from_variants(&variants(64 * 1024, MAX_KDF_ITERATIONS + 1, 2)).is_err());
+        assert!(KdfSettings::from_variants(&variants(64 * 1024, 10, 17)).is_err());
+        assert!(KdfSettings::from_variants(&variants(64 * 1024, u64::MAX, 2)).is_err());
+    }
+
+    #[test]
+    fn drops_characters_xml_does_not_allow() {
+        assert_eq!(xml_text("a\u{0}b\u{1B}c\td\u{FFFE}<&>"), "abc\td&lt;&amp;&gt;");
+        assert_eq!(xml_text("line\r\nü 🔑"), "line\r\nü 🔑");
+
+        let mut entry = Entry::new("pass\u{7}word".to_string());
+        entry.username = Some("u\u{8}ser".to_string());
+        entry.notes = Some("one\u{0}\ntwo".to_string());
+        entry.urls = vec!["https://example.com/\u{1}".to_string()];
+        entry.set_field("ke\u{1F}y", "va\u{3}lue");
+        let entries = vec![(ServiceName::new("work/example").unwrap(), entry)];
+
+        let written = write(&entries, "pw", weak_costs()).unwrap();
+        let (reread, _) = read(&written, "pw").unwrap();
+        let entry = &reread[0].entry;
+        // Protected values are base64 and keep every character
+        assert_eq!(entry.password, "pass\u{7}word");
+        assert_eq!(entry.username.as_deref(), Some("user"));
+        assert_eq!(entry.notes.as_deref(), Some("one\ntwo"));
+        assert_eq!(entry.urls, ["https://example.com/"]);
+        assert_eq!(entry.field("key"), Some("va\u{3}lue"));
+    }
+}
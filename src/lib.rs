@@ -0,0 +1,23 @@
+pub mod agent;
+pub mod atomic;
+pub mod backup;
+pub mod calibrate;
+pub mod cli;
+pub mod clipboard;
+pub mod commands;
+pub mod config;
+pub mod crypto;
+pub mod defaults;
+pub mod doctor;
+pub mod entry;
+pub mod error;
+pub mod generator;
+pub mod gui;
+pub mod import;
+pub mod index;
+pub mod kdbx;
+pub mod lock;
+pub mod permissions;
+pub mod service_name;
+pub mod storage;
+pub mod vault;
@@ -0,0 +1,204 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::PassmanError;
+use crate::permissions;
+
+const LOCK_FILE_SUFFIX: &str = ".lock";
+
+/// How long to wait for another passman process before giving up.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+struct HeldLock {
+    // Only kept open, closing it releases the lock
+    _file: File,
+    exclusive: bool,
+    depth: usize,
+}
+
+thread_local! {
+    // Locks this thread holds, so nested storage calls don't wait on themselves
+    static HELD_LOCKS: RefCell<HashMap<PathBuf, HeldLock>> = RefCell::new(HashMap::new());
+}
+
+/// Advisory lock on a vault, shared between readers and exclusive for writers.
+///
+/// The lock file sits next to the vault directory rather than inside it, so it
+/// stays in place while a master password change swaps the directory out.
+/// Released when dropped.
+///
+/// Taking it again on the same thread nests, except that a write can't start inside
+/// a read: upgrading the lock in place may drop it for a moment, so write paths take
+/// the exclusive lock up front.
+pub struct VaultLock {
+    lock_path: PathBuf,
+}
+
+impl VaultLock {
+    pub fn shared(storage_path: &Path) -> Result<Self, PassmanError> {
+        Self::acquire(storage_path, false)
+    }
+
+    pub fn exclusive(storage_path: &Path) -> Result<Self, PassmanError> {
+        Self::acquire(storage_path, true)
+    }
+
+    fn acquire(storage_path: &Path, exclusive: bool) -> Result<Self, PassmanError> {
+        let lock_path = lock_file_path(storage_path);
+
+        let reentered = HELD_LOCKS.with_borrow_mut(|held| -> Result<bool, PassmanError> {
+            let Some(lock) = held.get_mut(&lock_path) else {
+                return Ok(false);
+            };
+            if exclusive && !lock.exclusive {
+                return Err(PassmanError::IoError(io::Error::other(
+                    "can't write to the vault while reading it, the exclusive lock must be taken first",
+                )));
+            }
+            lock.depth += 1;
+            Ok(true)
+        })?;
+
+        if !reentered {
+            if let Some(parent) = lock_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = permissions::private_open_options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&lock_path)?;
+            wait_for(&file, &lock_path, exclusive)?;
+            record_pid(&file);
+
+            HELD_LOCKS.with_borrow_mut(|held| {
+                held.insert(
+                    lock_path.clone(),
+                    HeldLock {
+                        _file: file,
+                        exclusive,
+                        depth: 1,
+                    },
+                )
+            });
+        }
+
+        Ok(Self { lock_path })
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        HELD_LOCKS.with_borrow_mut(|held| {
+            if let Some(lock) = held.get_mut(&self.lock_path) {
+                lock.depth -= 1;
+                if lock.depth == 0 {
+                    // Closing the file releases the lock
+                    held.remove(&self.lock_path);
+                }
+            }
+        });
+    }
+}
+
+pub(crate) fn lock_file_path(storage_path: &Path) -> PathBuf {
+    let mut file_name = storage_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(LOCK_FILE_SUFFIX);
+    storage_path.with_file_name(file_name)
+}
+
+fn wait_for(file: &File, lock_path: &Path, exclusive: bool) -> Result<(), PassmanError> {
+    let deadline = Instant::now() + LOCK_TIMEOUT;
+
+    loop {
+        let result = if exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(RETRY_INTERVAL);
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(PassmanError::VaultLocked(read_pid(lock_path)));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+/// Leaves our PID in the lock file so whoever waits on us can say who to look for.
+fn record_pid(mut file: &File) {
+    let _ = file.set_len(0);
+    let _ = file.seek(SeekFrom::Start(0));
+    let _ = write!(file, "{}", std::process::id());
+}
+
+fn read_pid(lock_path: &Path) -> Option<u32> {
+    let mut content = String::new();
+    File::open(lock_path).ok()?.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Another process, as far as flock is concerned: a separate open of the lock file
+    fn other_process(storage_path: &Path) -> File {
+        File::open(lock_file_path(storage_path)).unwrap()
+    }
+
+    #[test]
+    fn nests_on_the_same_thread() {
+        let tmp = tempfile::tempdir().unwrap();
+        let vault = tmp.path().join("vault");
+
+        let outer = VaultLock::exclusive(&vault).unwrap();
+        let inner_write = VaultLock::exclusive(&vault).unwrap();
+        let inner_read = VaultLock::shared(&vault).unwrap();
+        drop(inner_write);
+        drop(inner_read);
+
+        // Still exclusive until the outermost lock goes
+        assert!(other_process(&vault).try_lock_shared().is_err());
+        drop(outer);
+        assert!(other_process(&vault).try_lock().is_ok());
+    }
+
+    #[test]
+    fn readers_share_the_lock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let vault = tmp.path().join("vault");
+
+        let _read = VaultLock::shared(&vault).unwrap();
+        let _nested = VaultLock::shared(&vault).unwrap();
+        assert!(other_process(&vault).try_lock_shared().is_ok());
+        assert!(other_process(&vault).try_lock().is_err());
+    }
+
+    #[test]
+    fn refuses_to_upgrade_and_keeps_the_shared_lock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let vault = tmp.path().join("vault");
+
+        let read = VaultLock::shared(&vault).unwrap();
+        assert!(VaultLock::exclusive(&vault).is_err());
+
+        // The refused upgrade neither dropped the read lock nor counted as nested
+        assert!(other_process(&vault).try_lock().is_err());
+        drop(read);
+        assert!(other_process(&vault).try_lock().is_ok());
+    }
+}
@@ -1,11 +1,7 @@
-mod cli;
-mod commands;
-mod crypto;
-mod error;
-mod storage;
-
-use commands::CommandType;
-use error::PassmanError;
+use passman::cli;
+use passman::commands::CommandType;
+use passman::config::Config;
+use passman::error::PassmanError;
 use std::env;
 
 fn print_error(error: &PassmanError) {
@@ -16,18 +12,46 @@ fn print_error(error: &PassmanError) {
         PassmanError::Argon2(_) => "Password hashing error.".to_string(),
         PassmanError::InvalidFileFormat => "Invalid password file format.".to_string(),
         PassmanError::UnsupportedVersion => "Unsupported file version.".to_string(),
+        PassmanError::InvalidServiceName(reason) => format!("Invalid service name: {}.", reason),
+        PassmanError::WrongMasterPassword => "Wrong master password.".to_string(),
+        PassmanError::VaultNotInitialized => {
+            "Vault is not initialized. Run 'passman init' first.".to_string()
+        }
+        PassmanError::InvalidPasswordPolicy(reason) => {
+            format!("Invalid password policy: {}.", reason)
+        }
+        PassmanError::ServiceNamesEncrypted => {
+            "Service names are encrypted, unlock the vault to list them.".to_string()
+        }
+        PassmanError::VaultLocked(Some(pid)) => format!(
+            "Vault is in use by another passman process (PID {}), try again once it is done.",
+            pid
+        ),
+        PassmanError::VaultLocked(None) => {
+            "Vault is in use by another passman process, try again once it is done.".to_string()
+        }
+        PassmanError::InvalidConfig(reason) => format!("Invalid config: {}.", reason),
+        PassmanError::InvalidImport(reason) => format!("Can't import this file: {}.", reason),
+        PassmanError::InvalidBackup(reason) => format!("Can't restore this backup: {}.", reason),
     };
 
     eprintln!("Error: {}", message);
 }
 
 fn run_app(args: &[String]) -> Result<(), PassmanError> {
+    let args = cli::take_global_options(args)?;
+
     if args.len() == 1 {
         cli::print_help();
         return Ok(());
     }
 
-    let command = CommandType::parse(args)?;
+    // A broken config file must not stop 'passman config' from repairing it
+    if args[1] != "config" {
+        Config::init()?;
+    }
+
+    let command = CommandType::parse(&args)?;
 
     if command.requires_auth() {
         command.execute_with_auth()?;
@@ -1,55 +1,43 @@
-mod cli;
-mod commands;
-mod crypto;
-mod error;
-mod storage;
-mod gui;
+use passman::cli::{self, CLEAR_CLIPBOARD_COMMAND};
+use passman::commands::CommandType;
+use passman::config::Config;
+use passman::gui;
+use passman::storage::PassmanStorage;
+use std::path::PathBuf;
 
-use commands::CommandType;
-use error::PassmanError;
-use std::env;
-
-fn print_error(error: &PassmanError) {
-    const DEBUG: bool = true; // set to true for full debug info
-
-    if DEBUG {
-        eprintln!("Full error debug: {:#?}", error);
-    }
-
-    let message = match error {
-        PassmanError::IoError(e) => format!("File access error: {}", e),
-        PassmanError::ChaChaPoly(_) => "Encryption error. Wrong master password?".to_string(),
-        PassmanError::FromUtf8(_) => "Invalid password file contents.".to_string(),
-        PassmanError::Argon2(_) => "Password hashing error.".to_string(),
-        PassmanError::InvalidFileFormat => "Invalid password file format.".to_string(),
-        PassmanError::UnsupportedVersion => "Unsupported file version.".to_string(),
-    };
-
-    eprintln!("Error: {}", message);
-}
-
-fn run_app(args: &[String]) -> Result<(), PassmanError> {
-    if args.len() == 1 {
-        cli::print_help();
-        return Ok(());
+fn main() -> eframe::Result<()> {
+    let all_args: Vec<String> = std::env::args().collect();
+
+    // cli::spawn_clipboard_clearer re-runs this executable to clear a copied secret
+    if all_args.iter().any(|arg| arg == CLEAR_CLIPBOARD_COMMAND) {
+        let result = cli::take_global_options(&all_args)
+            .and_then(|args| CommandType::parse(&args))
+            .and_then(|command| command.execute_public());
+        std::process::exit(if result.is_ok() { 0 } else { 1 });
     }
 
-    let command = CommandType::parse(args)?;
-
-    if command.requires_auth() {
-        command.execute_with_auth()?;
-    } else {
-        command.execute_public()?;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--vault" {
+            match args.next() {
+                Some(path) => PassmanStorage::set_default_path(PathBuf::from(path)),
+                None => eprintln!("Warning: '--vault' expects a path, using the default vault"),
+            }
+        }
     }
 
-    Ok(())
-}
-
-fn main() -> eframe::Result<()> {
-    let native_options = eframe::NativeOptions::default();
+    let config = Config::current();
+    let native_options = eframe::NativeOptions {
+        viewport: eframe::egui::ViewportBuilder::default()
+            .with_inner_size([config.gui_size.0, config.gui_size.1]),
+        ..Default::default()
+    };
     eframe::run_native(
         "PassMan GUI",
         native_options,
-        Box::new(|_cc| Ok(Box::new(gui::PassmanGui::default()))),
+        Box::new(|cc| {
+            cc.egui_ctx.set_theme(config.gui_theme);
+            Ok(Box::new(gui::PassmanGui::default()))
+        }),
     )
-}
\ No newline at end of file
+}
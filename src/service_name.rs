@@ -0,0 +1,186 @@
+use crate::error::PassmanError;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+// Characters that can't appear in a file name on at least one platform,
+// plus '%' itself so the encoding stays reversible
+const ENCODED_CHARS: &[char] = &['%', '/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+// Device names Windows refuses to use as file names, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const MAX_FILE_NAME_LENGTH: usize = 255;
+
+/// A service name that is safe to turn into a file inside the vault.
+///
+/// Separators such as in `work/github` or `a:b` are allowed, they are
+/// percent-encoded when building the file name so an entry can never
+/// escape the vault directory.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceName(String);
+
+impl ServiceName {
+    pub fn new(name: &str) -> Result<Self, PassmanError> {
+        let invalid = |reason: &str| Err(PassmanError::InvalidServiceName(reason.to_string()));
+
+        if name.is_empty() {
+            return invalid("name cannot be empty");
+        }
+
+        if name.trim() != name {
+            return invalid("name cannot start or end with whitespace");
+        }
+
+        if name.chars().any(char::is_control) {
+            return invalid("name cannot contain control characters");
+        }
+
+        if name.starts_with('/') || name.starts_with('\\') || has_drive_prefix(name) {
+            return invalid("name cannot be an absolute path");
+        }
+
+        for segment in name.split(['/', '\\']) {
+            match segment {
+                "" => return invalid("name cannot contain empty path segments"),
+                "." | ".." => return invalid("name cannot contain '.' or '..' segments"),
+                _ => {}
+            }
+        }
+
+        // Dot-files are kept for vault metadata
+        if name.starts_with('.') {
+            return invalid("name cannot start with '.'");
+        }
+
+        if name.ends_with('.') {
+            return invalid("name cannot end with '.'");
+        }
+
+        let stem = name.split('.').next().unwrap_or(name);
+        if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+            return invalid("name is reserved by the operating system");
+        }
+
+        let service_name = Self(name.to_string());
+        if service_name.file_name().len() > MAX_FILE_NAME_LENGTH {
+            return invalid("name is too long");
+        }
+
+        Ok(service_name)
+    }
+
+    /// Recovers a service name from a file found in the vault directory,
+    /// returns `None` for files that passman did not create.
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let mut decoded = Vec::with_capacity(file_name.len());
+        let mut bytes = file_name.bytes();
+
+        while let Some(byte) = bytes.next() {
+            if byte == b'%' {
+                let high = (bytes.next()? as char).to_digit(16)?;
+                let low = (bytes.next()? as char).to_digit(16)?;
+                decoded.push((high * 16 + low) as u8);
+            } else {
+                decoded.push(byte);
+            }
+        }
+
+        let name = String::from_utf8(decoded).ok()?;
+        let service_name = Self::new(&name).ok()?;
+
+        // Only accept the canonical encoding, so two files can't map to one name
+        (service_name.file_name() == file_name).then_some(service_name)
+    }
+
+    pub fn file_name(&self) -> String {
+        let mut file_name = String::with_capacity(self.0.len());
+
+        for c in self.0.chars() {
+            if ENCODED_CHARS.contains(&c) {
+                file_name.push_str(&format!("%{:02X}", c as u32));
+            } else {
+                file_name.push(c);
+            }
+        }
+
+        file_name
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn has_drive_prefix(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'/' || bytes[2] == b'\\')
+}
+
+impl Display for ServiceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ServiceName {
+    type Err = PassmanError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::new(name)
+    }
+}
+
+impl AsRef<str> for ServiceName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_separators_and_decodes_back() {
+        let name = ServiceName::new("work/github:a%b").unwrap();
+        assert_eq!(name.file_name(), "work%2Fgithub%3Aa%25b");
+        assert_eq!(ServiceName::from_file_name(&name.file_name()), Some(name));
+    }
+
+    #[test]
+    fn plain_names_keep_their_file_name() {
+        let name = ServiceName::new("github.com").unwrap();
+        assert_eq!(name.file_name(), "github.com");
+        assert_eq!(ServiceName::from_file_name("github.com"), Some(name));
+    }
+
+    #[test]
+    fn rejects_names_that_could_escape_the_vault() {
+        for name in ["", "/etc/passwd", "\\share", "C:\\x", "..", "a/../b", "./a", "a//b", "a/", ".hidden"] {
+            assert!(ServiceName::new(name).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn rejects_whitespace_control_and_reserved_names() {
+        for name in [" github", "github ", "git\nhub", "CON", "nul.txt", "github."] {
+            assert!(ServiceName::new(name).is_err(), "{:?} was accepted", name);
+        }
+        assert!(ServiceName::new(&"a".repeat(MAX_FILE_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn ignores_files_passman_did_not_write() {
+        assert_eq!(ServiceName::from_file_name(".passman-vault"), None);
+        assert_eq!(ServiceName::from_file_name("bad%zz"), None);
+        // Lowercase hex is not the canonical encoding
+        assert_eq!(ServiceName::from_file_name("a%2fb"), None);
+        assert_eq!(ServiceName::from_file_name("%2E%2E"), None);
+    }
+}
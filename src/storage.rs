@@ -1,95 +1,629 @@
 use argon2::Version;
 use chacha20poly1305::aead::generic_array::GenericArray;
-use std::fs::{self, File, create_dir_all};
-use std::io::prelude::*;
+use std::fs::{self, File};
 use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
 use std::env;
+use std::sync::OnceLock;
 
+use crate::atomic;
+use crate::config::Config;
 use crate::crypto::{self, KdfParameters};
+use crate::entry::Entry;
 use crate::error::PassmanError;
-use chacha20poly1305::aead::Aead;
+use crate::index::{SERVICE_INDEX_FILE, ServiceIndex};
+use crate::lock::{self, VaultLock};
+use crate::permissions;
+use crate::service_name::ServiceName;
+use crate::vault::{VAULT_METADATA_FILE, VaultMetadata};
+use chacha20poly1305::aead::{Aead, Payload};
 
 const FILE_MAGIC_NUMBER: &[u8; 4] = b"PMAN";
-const CURRENT_FILE_VERSION: u8 = 0x01;
-const KDF_ARGON2ID: u8 = 0x01;
-const ENCRYPTION_CHACHA20POLY1305: u8 = 0x01;
+/// Overrides every other way of choosing the vault folder, except `--vault`.
+pub const VAULT_DIR_ENV_VAR: &str = "PASSMAN_DIR";
+const VAULT_DIR_NAME: &str = "passman";
+// Where vaults lived before they moved to the data folder, next to the executable
+const LEGACY_VAULT_DIR_NAME: &str = "PassmanPasswords";
+const LEGACY_MIGRATION_SUFFIX: &str = ".migrating";
+
+static DEFAULT_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+const ROTATION_STAGING_SUFFIX: &str = ".rotate-staging";
+const ROTATION_BACKUP_SUFFIX: &str = ".rotate-backup";
+const ROTATION_COMPLETE_MARKER: &str = ".rotate-complete";
+const FILE_VERSION_1: u8 = 0x01;
+const FILE_VERSION_2: u8 = 0x02;
+const FILE_VERSION_3: u8 = 0x03;
+const FILE_VERSION_4: u8 = 0x04;
+const FILE_VERSION_5: u8 = 0x05;
+pub(crate) const CURRENT_FILE_VERSION: u8 = FILE_VERSION_5;
+const SUPPORTED_FILE_VERSIONS: &[u8] = &[
+    FILE_VERSION_1,
+    FILE_VERSION_2,
+    FILE_VERSION_3,
+    FILE_VERSION_4,
+    FILE_VERSION_5,
+];
+
+// Header flags, from version 5
+const FLAG_PADDED: u8 = 0x01;
+const KNOWN_FLAGS: u8 = FLAG_PADDED;
+
+pub(crate) const KDF_ARGON2ID: u8 = 0x01;
+pub(crate) const KDF_HKDF_SHA256: u8 = 0x02;
+pub(crate) const ENCRYPTION_CHACHA20POLY1305: u8 = 0x01;
 
 pub struct PassmanStorage {
     pub(crate) master_password: String,
     pub(crate) storage_path: PathBuf,
+    /// Unwrapped on first use, `None` inside means a vault from before vault keys
+    vault_key: OnceLock<Option<[u8; 32]>>,
+    /// Bucket size new entries are padded to, 0 or 1 turns padding off
+    pub padding_bucket: usize,
 }
 
 impl PassmanStorage {
-     pub fn get_default_path() -> PathBuf {
-        let exe_dir = env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|d| d.to_path_buf()))
-            .unwrap_or_else(|| PathBuf::from("."));
-        
-        let storage_dir = exe_dir.join("PassmanPasswords");
-
-        if let Err(e) = create_dir_all(&storage_dir) {
+    /// Makes the vault at `path` the default one, for `--vault`. Only the first call counts.
+    pub fn set_default_path(path: PathBuf) {
+        let _ = DEFAULT_PATH.set(path);
+    }
+
+    /// The vault chosen with `--vault`, `PASSMAN_DIR` or the config file, in that order,
+    /// else `passman` in the user's data folder.
+    pub fn get_default_path() -> PathBuf {
+        let storage_dir = DEFAULT_PATH.get_or_init(resolve_default_path).clone();
+
+        if let Err(e) = recover_interrupted_rotation(&storage_dir) {
+            eprintln!("Warning: failed to recover from an interrupted password change: {}", e);
+        }
+
+        if let Err(e) = permissions::create_private_dir(&storage_dir) {
             eprintln!("Warning: failed to create storage folder: {}", e);
         }
 
         fs::canonicalize(&storage_dir).unwrap_or(storage_dir)
     }
 
+    /// Like `get_default_path`, but leaves the folder alone, for checks that must not create it.
+    pub fn find_default_path() -> PathBuf {
+        let storage_dir = DEFAULT_PATH.get_or_init(resolve_default_path).clone();
+        fs::canonicalize(&storage_dir).unwrap_or(storage_dir)
+    }
+
     pub fn new(master_password: String) -> Self {
-        Self {
-            master_password,
-            storage_path: Self::get_default_path(),
-        }
+        Self::with_path(master_password, Self::get_default_path())
     }
 
     pub fn with_path(master_password: String, storage_path: PathBuf) -> Self {
         Self {
             master_password,
             storage_path,
+            vault_key: OnceLock::new(),
+            padding_bucket: Config::current().padding,
+        }
+    }
+
+    /// Storage for code that holds the vault key but not the master password, like the agent.
+    pub(crate) fn with_vault_key(storage_path: PathBuf, vault_key: Option<[u8; 32]>) -> Self {
+        let storage = Self::with_path(String::new(), storage_path);
+        let _ = storage.vault_key.set(vault_key);
+        storage
+    }
+
+    /// Opens the default vault, unwrapping its vault key with the master password first.
+    pub fn unlock(master_password: String) -> Result<Self, PassmanError> {
+        let storage = Self::new(master_password);
+        storage.vault_key()?;
+        Ok(storage)
+    }
+
+    /// The key every entry key is derived from, `None` for vaults not migrated to one yet.
+    /// Costs one Argon2 run the first time, nothing afterwards.
+    pub fn vault_key(&self) -> Result<Option<[u8; 32]>, PassmanError> {
+        if let Some(vault_key) = self.vault_key.get() {
+            return Ok(*vault_key);
         }
+
+        let metadata = {
+            let _lock = self.lock_shared()?;
+            VaultMetadata::load(&self.storage_path)?
+        };
+        let vault_key = metadata.unlock(&self.master_password)?;
+        Ok(*self.vault_key.get_or_init(|| vault_key))
+    }
+
+    /// Gives a version 1 vault a vault key, so new and migrated entries stop needing Argon2.
+    /// Returns the storage to keep using, older entries stay readable with the master password.
+    pub fn upgrade_vault_key(
+        self,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<Self, PassmanError> {
+        let lock = self.lock_exclusive()?;
+        if self.vault_key()?.is_some() {
+            return Ok(self);
+        }
+
+        let vault_key = crypto::gen_vault_key();
+        VaultMetadata::create(&self.master_password, &vault_key, m_cost, t_cost, p_cost)?
+            .save(&self.storage_path)?;
+
+        drop(lock);
+        let upgraded = Self::with_path(self.master_password, self.storage_path);
+        let _ = upgraded.vault_key.set(Some(vault_key));
+        Ok(upgraded)
+    }
+
+    /// Wraps the vault key again with new Argon2 costs. Entries are left alone,
+    /// their keys only depend on the vault key.
+    pub fn rewrap_vault_key(&self, m_cost: u32, t_cost: u32, p_cost: u32) -> Result<(), PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        let vault_key = self.require_vault_key()?;
+        VaultMetadata::create(&self.master_password, &vault_key, m_cost, t_cost, p_cost)?
+            .save(&self.storage_path)
+    }
+
+    pub fn verify_master_password(&self) -> Result<(), PassmanError> {
+        VaultMetadata::load(&self.storage_path)?.verify(&self.master_password)
     }
    
 
-    pub fn has_service(&self, service_name: &str) -> bool {
+    pub fn list_services(&self) -> Result<Vec<ServiceName>, PassmanError> {
+        let _lock = self.lock_shared()?;
+        if self.names_encrypted() {
+            return Ok(self.load_index()?.services());
+        }
+        list_services_in(&self.storage_path)
+    }
+
+    pub fn has_service(&self, service_name: &ServiceName) -> bool {
+        if self.names_encrypted() {
+            return self
+                .load_index()
+                .is_ok_and(|index| index.file_name(service_name).is_some());
+        }
         self.get_service_file_path(service_name).exists()
     }
 
+    /// Whether entries are stored under opaque file names listed in an encrypted index.
+    pub fn names_encrypted(&self) -> bool {
+        ServiceIndex::exists(&self.storage_path)
+    }
+
+    /// Moves every entry to an opaque file name and records the real names in the
+    /// encrypted index. Re-running it finishes an interrupted conversion.
+    pub fn encrypt_service_names(&self) -> Result<usize, PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        let vault_key = self.require_vault_key()?;
+        let mut index = if self.names_encrypted() {
+            self.load_index()?
+        } else {
+            ServiceIndex::default()
+        };
+
+        let mut moves = Vec::new();
+        for entry in fs::read_dir(&self.storage_path)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else { continue };
+            if index.contains_file(file_name) {
+                continue;
+            }
+            if let Some(service) = ServiceName::from_file_name(file_name) {
+                let target = match index.file_name(&service) {
+                    Some(target) => target.to_string(),
+                    None => index.insert(&service),
+                };
+                moves.push((entry.path(), self.storage_path.join(target)));
+            }
+        }
+
+        // The index goes first, an interruption then leaves names it already knows about
+        index.save(&self.storage_path, &vault_key)?;
+        for (from, to) in &moves {
+            fs::rename(from, to)?;
+        }
+        atomic::sync_dir(&self.storage_path)?;
+
+        Ok(moves.len())
+    }
+
+    /// Puts every entry back under a file named after its service and removes the index.
+    pub fn decrypt_service_names(&self) -> Result<usize, PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        if !self.names_encrypted() {
+            return Ok(0);
+        }
+
+        let index = self.load_index()?;
+        let mut moved = 0;
+
+        for service in index.services() {
+            let from = self.storage_path.join(index.file_name(&service).unwrap_or_default());
+            let to = self.get_service_file_path(&service);
+
+            // Already moved by an earlier, interrupted run
+            if !from.exists() {
+                continue;
+            }
+            if to.exists() {
+                return Err(PassmanError::IoError(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("'{}' already exists", to.display()),
+                )));
+            }
+
+            fs::rename(from, to)?;
+            moved += 1;
+        }
+
+        fs::remove_file(ServiceIndex::path(&self.storage_path))?;
+        atomic::sync_dir(&self.storage_path)?;
+        Ok(moved)
+    }
+
+    /// Creates a new entry, refusing to overwrite an existing one.
     pub fn store(
         &self,
-        service_name: &str,
-        content: &String,
+        service_name: &ServiceName,
+        content: &str,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<(), PassmanError> {
+        self.store_entry(service_name, &Entry::new(content.to_string()), m_cost, t_cost, p_cost)
+    }
+
+    /// Creates a new entry, refusing to overwrite an existing one.
+    /// The Argon2 costs only apply to vaults without a vault key, which still derive one key per file.
+    pub fn store_entry(
+        &self,
+        service_name: &ServiceName,
+        entry: &Entry,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<(), PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        self.ensure_service_absent(service_name)?;
+        self.write_entry(service_name, entry, m_cost, t_cost, p_cost)
+    }
+
+    /// Overwrites an existing entry, failing with `NotFound` if there is none.
+    pub fn replace_entry(
+        &self,
+        service_name: &ServiceName,
+        entry: &Entry,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<(), PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        self.existing_file_path(service_name)?.metadata()?;
+        self.write_entry(service_name, entry, m_cost, t_cost, p_cost)
+    }
+
+    fn write_entry(
+        &self,
+        service_name: &ServiceName,
+        entry: &Entry,
         m_cost: u32,
         t_cost: u32,
         p_cost: u32,
     ) -> Result<(), PassmanError> {
         self.ensure_storage_dir()?;
 
-        let file_path = self.get_service_file_path(service_name);
-        let encrypted_data = self.encrypt_content(content.as_bytes(), m_cost, t_cost, p_cost)?;
+        let encrypted_data = self.encrypt_content(&entry.to_bytes(), m_cost, t_cost, p_cost)?;
+
+        if !self.names_encrypted() {
+            atomic::write_file(&self.get_service_file_path(service_name), &encrypted_data)?;
+            return Ok(());
+        }
+
+        let mut index = self.load_index()?;
+        let (file_name, is_new) = match index.file_name(service_name) {
+            Some(file_name) => (file_name.to_string(), false),
+            None => (index.insert(service_name), true),
+        };
+
+        atomic::write_file(&self.storage_path.join(file_name), &encrypted_data)?;
+
+        if is_new {
+            self.save_index(&index)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn retrieve(&self, service_name: &ServiceName) -> Result<String, PassmanError> {
+        Ok(self.retrieve_entry(service_name)?.password)
+    }
+
+    pub fn retrieve_entry(&self, service_name: &ServiceName) -> Result<Entry, PassmanError> {
+        self.retrieve_entry_with(service_name, |file_key| self.file_key(file_key))
+    }
+
+    /// Like `retrieve_entry`, but asks `derive_key` for the file key instead of using the master password.
+    pub fn retrieve_entry_with<F>(
+        &self,
+        service_name: &ServiceName,
+        derive_key: F,
+    ) -> Result<Entry, PassmanError>
+    where
+        F: FnOnce(&FileKey) -> Result<[u8; 32], PassmanError>,
+    {
+        let content = {
+            let _lock = self.lock_shared()?;
+            fs::read(self.existing_file_path(service_name)?)?
+        };
+
+        decrypt_content(&content, derive_key)
+    }
+
+    /// Deletes an entry, once the master password has been checked against it.
+    pub fn remove(&self, service_name: &ServiceName) -> Result<(), PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        self.retrieve(service_name)?;
+        fs::remove_file(self.existing_file_path(service_name)?)?;
+        atomic::sync_dir(&self.storage_path)?;
+
+        if self.names_encrypted() {
+            let mut index = self.load_index()?;
+            index.remove(service_name);
+            self.save_index(&index)?;
+        }
 
-        let mut file = File::create(file_path)?;
-        file.write_all(&encrypted_data)?;
+        Ok(())
+    }
+
+    /// Renames an entry, refusing to overwrite an existing one.
+    pub fn rename(
+        &self,
+        service_name: &ServiceName,
+        new_service_name: &ServiceName,
+    ) -> Result<(), PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        self.retrieve(service_name)?;
+        self.ensure_service_absent(new_service_name)?;
+
+        // The file name says nothing about the service, only the index changes
+        if self.names_encrypted() {
+            let mut index = self.load_index()?;
+            index.rename(service_name, new_service_name);
+            return self.save_index(&index);
+        }
 
+        fs::rename(
+            self.get_service_file_path(service_name),
+            self.get_service_file_path(new_service_name),
+        )?;
+        atomic::sync_dir(&self.storage_path)?;
         Ok(())
     }
 
-    pub fn retrieve(&self, service_name: &str) -> Result<String, PassmanError> {
-        let file_path = self.get_service_file_path(service_name);
-        let mut file = File::open(file_path)?;
-        let mut content = Vec::new();
-        file.read_to_end(&mut content)?;
+    /// Duplicates an entry under a new name, refusing to overwrite an existing one.
+    pub fn copy(
+        &self,
+        service_name: &ServiceName,
+        new_service_name: &ServiceName,
+    ) -> Result<(), PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        self.retrieve(service_name)?;
+        self.ensure_service_absent(new_service_name)?;
+
+        let content = fs::read(self.existing_file_path(service_name)?)?;
 
-        self.decrypt_content(&content)
+        if self.names_encrypted() {
+            let mut index = self.load_index()?;
+            atomic::write_file(&self.storage_path.join(index.insert(new_service_name)), &content)?;
+            return self.save_index(&index);
+        }
+
+        atomic::write_file(&self.get_service_file_path(new_service_name), &content)?;
+        Ok(())
+    }
+
+    /// Re-encrypts every entry under a new master password.
+    ///
+    /// Everything is written to a staging directory next to the vault which is
+    /// then swapped in, so a crash never leaves entries under mixed passwords.
+    pub fn rotate_master_password<F>(
+        &self,
+        new_master_password: &str,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+        mut progress: F,
+    ) -> Result<(), PassmanError>
+    where
+        F: FnMut(usize, usize, &ServiceName),
+    {
+        let _lock = self.lock_exclusive()?;
+        self.vault_key()?;
+
+        let staging_path = sibling_path(&self.storage_path, ROTATION_STAGING_SUFFIX);
+        let backup_path = sibling_path(&self.storage_path, ROTATION_BACKUP_SUFFIX);
+
+        // Leftovers of a rotation that never got to the swap
+        if staging_path.exists() {
+            fs::remove_dir_all(&staging_path)?;
+        }
+
+        // A new vault key too, so a copy of the old key file opens nothing written from now on
+        let vault_key = crypto::gen_vault_key();
+        let staging = Self::with_path(new_master_password.to_string(), staging_path.clone());
+        let _ = staging.vault_key.set(Some(vault_key));
+        let services = self.list_services()?;
+
+        let result = (|| {
+            staging.ensure_storage_dir()?;
+            if self.names_encrypted() {
+                ServiceIndex::default().save(&staging_path, &vault_key)?;
+            }
+
+            for (i, service) in services.iter().enumerate() {
+                progress(i + 1, services.len(), service);
+                let entry = self.retrieve_entry(service)?;
+                staging.store_entry(service, &entry, m_cost, t_cost, p_cost)?;
+            }
+
+            VaultMetadata::create(new_master_password, &vault_key, m_cost, t_cost, p_cost)?
+                .save(&staging_path)?;
+
+            // Carry over anything that isn't an entry or the old verifier
+            for entry in fs::read_dir(&self.storage_path)? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let is_entry = file_name
+                    .to_str()
+                    .and_then(ServiceName::from_file_name)
+                    .is_some();
+
+                let is_vault_file = file_name == VAULT_METADATA_FILE
+                    || file_name == SERVICE_INDEX_FILE
+                    || file_name.to_str().is_some_and(atomic::is_temp_file);
+
+                if !is_entry && !is_vault_file && entry.file_type()?.is_file() {
+                    fs::copy(entry.path(), staging_path.join(&file_name))?;
+                }
+            }
+
+            File::create(staging_path.join(ROTATION_COMPLETE_MARKER))?.sync_all()?;
+            atomic::sync_dir(&staging_path)?;
+            Ok::<(), PassmanError>(())
+        })();
+
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&staging_path);
+            return Err(e);
+        }
+
+        let parent = self.storage_path.parent().unwrap_or(Path::new("."));
+        fs::rename(&self.storage_path, &backup_path)?;
+        fs::rename(&staging_path, &self.storage_path)?;
+        atomic::sync_dir(parent)?;
+        fs::remove_file(self.storage_path.join(ROTATION_COMPLETE_MARKER))?;
+        fs::remove_dir_all(&backup_path)?;
+        let _ = fs::remove_file(lock::lock_file_path(&staging_path));
+
+        Ok(())
+    }
+
+    pub fn file_version(&self, service_name: &ServiceName) -> Result<u8, PassmanError> {
+        let _lock = self.lock_shared()?;
+        let content = fs::read(self.existing_file_path(service_name)?)?;
+        Ok(FileHeader::read(&content)?.0.version)
+    }
+
+    /// Re-encrypts an entry in the current file format if it uses an older one.
+    /// Returns whether the entry was upgraded.
+    pub fn migrate(
+        &self,
+        service_name: &ServiceName,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<bool, PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        // Without a vault key, the newest format this vault can write is the per-file one
+        let target_version = match self.vault_key()? {
+            Some(_) => CURRENT_FILE_VERSION,
+            None => FILE_VERSION_3,
+        };
+        if self.file_version(service_name)? >= target_version {
+            return Ok(false);
+        }
+
+        // Old headers are not authenticated, so their KDF costs are not reused
+        let entry = self.retrieve_entry(service_name)?;
+        self.replace_entry(service_name, &entry, m_cost, t_cost, p_cost)?;
+
+        Ok(true)
+    }
+
+    /// Re-encrypts an entry whose own Argon2 costs are below `m_cost` or `t_cost`.
+    /// Entries keyed by the vault key have no costs of their own and are left alone.
+    /// Returns whether the entry was re-encrypted.
+    pub fn upgrade_entry_kdf(
+        &self,
+        service_name: &ServiceName,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<bool, PassmanError> {
+        let _lock = self.lock_exclusive()?;
+        let content = fs::read(self.existing_file_path(service_name)?)?;
+        match FileHeader::read(&content)?.0.key {
+            FileKey::Password(kdf_params) if kdf_params.is_weaker_than(m_cost, t_cost, p_cost) => {}
+            _ => return Ok(false),
+        }
+
+        let entry = self.retrieve_entry(service_name)?;
+        self.replace_entry(service_name, &entry, m_cost, t_cost, p_cost)?;
+
+        Ok(true)
+    }
+
+    /// Held while reading, so a concurrent writer can't swap files out underneath.
+    fn lock_shared(&self) -> Result<VaultLock, PassmanError> {
+        VaultLock::shared(&self.storage_path)
+    }
+
+    /// Held across a whole write, including the checks that decide what to write.
+    fn lock_exclusive(&self) -> Result<VaultLock, PassmanError> {
+        VaultLock::exclusive(&self.storage_path)
+    }
+
+    fn ensure_service_absent(&self, service_name: &ServiceName) -> Result<(), PassmanError> {
+        if self.has_service(service_name) {
+            return Err(PassmanError::IoError(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Service '{}' already exists", service_name),
+            )));
+        }
+        Ok(())
     }
 
     fn ensure_storage_dir(&self) -> Result<(), PassmanError> {
-        create_dir_all(&self.storage_path)?;
+        permissions::create_private_dir(&self.storage_path)?;
         Ok(())
     }
 
-    fn get_service_file_path(&self, service_name: &str) -> PathBuf {
-        self.storage_path.join(service_name)
+    fn get_service_file_path(&self, service_name: &ServiceName) -> PathBuf {
+        self.storage_path.join(service_name.file_name())
+    }
+
+    /// Where an entry is stored, looked up in the index when names are encrypted.
+    fn existing_file_path(&self, service_name: &ServiceName) -> Result<PathBuf, PassmanError> {
+        if !self.names_encrypted() {
+            return Ok(self.get_service_file_path(service_name));
+        }
+
+        match self.load_index()?.file_name(service_name) {
+            Some(file_name) => Ok(self.storage_path.join(file_name)),
+            None => Err(PassmanError::IoError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Service '{}' not found", service_name),
+            ))),
+        }
+    }
+
+    fn require_vault_key(&self) -> Result<[u8; 32], PassmanError> {
+        self.vault_key()?.ok_or_else(|| {
+            PassmanError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Encrypted service names need a vault key, run 'passman migrate' first",
+            ))
+        })
+    }
+
+    fn load_index(&self) -> Result<ServiceIndex, PassmanError> {
+        ServiceIndex::load(&self.storage_path, &self.require_vault_key()?)
+    }
+
+    fn save_index(&self, index: &ServiceIndex) -> Result<(), PassmanError> {
+        index.save(&self.storage_path, &self.require_vault_key()?)
     }
 
     fn encrypt_content(
@@ -99,24 +633,47 @@ impl PassmanStorage {
         t_cost: u32,
         p_cost: u32,
     ) -> Result<Vec<u8>, PassmanError> {
-        let (cipher, kdf_params, nonce) =
-            crypto::gen_new_cipher(self.master_password.as_bytes(), m_cost, t_cost, p_cost)?;
+        let (cipher, header) = match self.vault_key()? {
+            Some(vault_key) => {
+                let entry_id = crypto::gen_entry_id();
+                let cipher = crypto::cipher_from_key(&crypto::derive_entry_key(&vault_key, &entry_id));
+                let header = FileHeader {
+                    version: CURRENT_FILE_VERSION,
+                    key: FileKey::VaultKey(entry_id),
+                    flags: if self.padding_bucket > 1 { FLAG_PADDED } else { 0 },
+                    nonce: crypto::gen_nonce(),
+                };
+                (cipher, header)
+            }
+            None => {
+                let (cipher, kdf_params, nonce) =
+                    crypto::gen_new_cipher(self.master_password.as_bytes(), m_cost, t_cost, p_cost)?;
+                let header = FileHeader {
+                    version: FILE_VERSION_3,
+                    key: FileKey::Password(kdf_params),
+                    flags: 0,
+                    nonce,
+                };
+                (cipher, header)
+            }
+        };
 
-        let encrypted_content =
-            cipher.encrypt(chacha20poly1305::Nonce::from_slice(&nonce), content)?;
+        let mut file_data = header.to_bytes();
 
-        let mut file_data = Vec::new();
+        let content = if header.flags & FLAG_PADDED != 0 {
+            pad(content, self.padding_bucket)
+        } else {
+            content.to_vec()
+        };
 
-        // Write the Header
-        file_data.extend_from_slice(FILE_MAGIC_NUMBER);
-        file_data.push(CURRENT_FILE_VERSION);
-        file_data.push(KDF_ARGON2ID);
-        file_data.extend_from_slice(&kdf_params.salt);
-        file_data.extend_from_slice(&kdf_params.m_cost.to_le_bytes());
-        file_data.extend_from_slice(&kdf_params.t_cost.to_le_bytes());
-        file_data.extend_from_slice(&kdf_params.p_cost.to_le_bytes());
-        file_data.push(ENCRYPTION_CHACHA20POLY1305);
-        file_data.extend_from_slice(&nonce);
+        // The header goes in as associated data, tampering with it fails decryption
+        let encrypted_content = cipher.encrypt(
+            chacha20poly1305::Nonce::from_slice(&header.nonce),
+            Payload {
+                msg: &content,
+                aad: &file_data,
+            },
+        )?;
 
         // Write the encrypted body (ciphertext and tag are combined)
         file_data.extend_from_slice(&encrypted_content);
@@ -124,7 +681,67 @@ impl PassmanStorage {
         Ok(file_data)
     }
 
-    fn decrypt_content(&self, file_content: &[u8]) -> Result<String, PassmanError> {
+    fn file_key(&self, file_key: &FileKey) -> Result<[u8; 32], PassmanError> {
+        match file_key {
+            FileKey::Password(kdf_params) => {
+                crypto::derive_key(self.master_password.as_bytes(), kdf_params)
+            }
+            FileKey::VaultKey(entry_id) => match self.vault_key()? {
+                Some(vault_key) => Ok(crypto::derive_entry_key(&vault_key, entry_id)),
+                // An entry from a vault key based vault copied into an older vault
+                None => Err(PassmanError::WrongMasterPassword),
+            },
+        }
+    }
+}
+
+/// How the key of a password file is obtained.
+pub enum FileKey {
+    /// Argon2id over the master password with the file's own salt, up to version 3
+    Password(KdfParameters),
+    /// HKDF over the vault key and the file's random entry id, from version 4
+    VaultKey([u8; 16]),
+}
+
+/// The clear-text header at the start of every password file.
+pub struct FileHeader {
+    pub version: u8,
+    pub key: FileKey,
+    /// Always 0 before version 5
+    pub flags: u8,
+    pub nonce: [u8; 12],
+}
+
+impl FileHeader {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        data.extend_from_slice(FILE_MAGIC_NUMBER);
+        data.push(self.version);
+        match &self.key {
+            FileKey::Password(kdf_params) => {
+                data.push(KDF_ARGON2ID);
+                data.extend_from_slice(&kdf_params.salt);
+                data.extend_from_slice(&kdf_params.m_cost.to_le_bytes());
+                data.extend_from_slice(&kdf_params.t_cost.to_le_bytes());
+                data.extend_from_slice(&kdf_params.p_cost.to_le_bytes());
+            }
+            FileKey::VaultKey(entry_id) => {
+                data.push(KDF_HKDF_SHA256);
+                data.extend_from_slice(entry_id);
+            }
+        }
+        data.push(ENCRYPTION_CHACHA20POLY1305);
+        if self.version >= FILE_VERSION_5 {
+            data.push(self.flags);
+        }
+        data.extend_from_slice(&self.nonce);
+
+        data
+    }
+
+    /// Parses the header, returning it along with its length in bytes.
+    pub fn read(file_content: &[u8]) -> Result<(Self, usize), PassmanError> {
         let mut cursor = Cursor::new(file_content);
 
         // Read and validate the Header
@@ -136,35 +753,42 @@ impl PassmanStorage {
 
         let mut version_byte = [0u8; 1];
         cursor.read_exact(&mut version_byte)?;
-        if version_byte[0] != CURRENT_FILE_VERSION {
+        if !SUPPORTED_FILE_VERSIONS.contains(&version_byte[0]) {
             return Err(PassmanError::UnsupportedVersion);
         }
 
         let mut kdf_type_byte = [0u8; 1];
         cursor.read_exact(&mut kdf_type_byte)?;
-        // For now, we only support Argon2id. Add logic here for other KDFs later.
-        if kdf_type_byte[0] != KDF_ARGON2ID {
-            return Err(PassmanError::InvalidFileFormat);
-        }
 
-        let mut salt = [0u8; 16];
-        cursor.read_exact(&mut salt)?;
-        let mut m_cost_bytes = [0u8; 4];
-        cursor.read_exact(&mut m_cost_bytes)?;
-        let m_cost = u32::from_le_bytes(m_cost_bytes);
-        let mut t_cost_bytes = [0u8; 4];
-        cursor.read_exact(&mut t_cost_bytes)?;
-        let t_cost = u32::from_le_bytes(t_cost_bytes);
-        let mut p_cost_bytes = [0u8; 4];
-        cursor.read_exact(&mut p_cost_bytes)?;
-        let p_cost = u32::from_le_bytes(p_cost_bytes);
-
-        let kdf_params = KdfParameters {
-            salt,
-            version: Version::V0x13,
-            m_cost,
-            t_cost,
-            p_cost,
+        // Version 4 switched from per-file Argon2 to keys derived from the vault key
+        let key = match (version_byte[0], kdf_type_byte[0]) {
+            (FILE_VERSION_4..=FILE_VERSION_5, KDF_HKDF_SHA256) => {
+                let mut entry_id = [0u8; 16];
+                cursor.read_exact(&mut entry_id)?;
+                FileKey::VaultKey(entry_id)
+            }
+            (FILE_VERSION_1..=FILE_VERSION_3, KDF_ARGON2ID) => {
+                let mut salt = [0u8; 16];
+                cursor.read_exact(&mut salt)?;
+                let mut m_cost_bytes = [0u8; 4];
+                cursor.read_exact(&mut m_cost_bytes)?;
+                let m_cost = u32::from_le_bytes(m_cost_bytes);
+                let mut t_cost_bytes = [0u8; 4];
+                cursor.read_exact(&mut t_cost_bytes)?;
+                let t_cost = u32::from_le_bytes(t_cost_bytes);
+                let mut p_cost_bytes = [0u8; 4];
+                cursor.read_exact(&mut p_cost_bytes)?;
+                let p_cost = u32::from_le_bytes(p_cost_bytes);
+
+                FileKey::Password(KdfParameters {
+                    salt,
+                    version: Version::V0x13,
+                    m_cost,
+                    t_cost,
+                    p_cost,
+                })
+            }
+            _ => return Err(PassmanError::InvalidFileFormat),
         };
 
         let mut encryption_type_byte = [0u8; 1];
@@ -173,17 +797,587 @@ impl PassmanStorage {
             return Err(PassmanError::InvalidFileFormat);
         }
 
+        let mut flags = [0u8; 1];
+        if version_byte[0] >= FILE_VERSION_5 {
+            cursor.read_exact(&mut flags)?;
+            // A flag we don't know could change how the payload must be read
+            if flags[0] & !KNOWN_FLAGS != 0 {
+                return Err(PassmanError::UnsupportedVersion);
+            }
+        }
+
         let mut nonce = [0u8; 12];
         cursor.read_exact(&mut nonce)?;
 
-        let mut encrypted_content = Vec::new();
-        cursor.read_to_end(&mut encrypted_content)?;
+        let header = Self {
+            version: version_byte[0],
+            key,
+            flags: flags[0],
+            nonce,
+        };
+        Ok((header, cursor.position() as usize))
+    }
+}
+
+fn decrypt_content<F>(file_content: &[u8], derive_key: F) -> Result<Entry, PassmanError>
+where
+    F: FnOnce(&FileKey) -> Result<[u8; 32], PassmanError>,
+{
+    let (header, header_len) = FileHeader::read(file_content)?;
+    let (header_bytes, encrypted_content) = file_content.split_at(header_len);
+
+    // Version 1 files did not authenticate their header
+    let aad: &[u8] = match header.version {
+        FILE_VERSION_1 => &[],
+        _ => header_bytes,
+    };
+
+    let nonce = GenericArray::clone_from_slice(&header.nonce);
+    let cipher = crypto::cipher_from_key(&derive_key(&header.key)?);
+
+    let mut decrypted_content = cipher.decrypt(
+        &nonce,
+        Payload {
+            msg: encrypted_content,
+            aad,
+        },
+    )?;
+
+    if header.flags & FLAG_PADDED != 0 {
+        decrypted_content = unpad(&decrypted_content)?;
+    }
+
+    // Older versions only ever stored the password itself
+    match header.version {
+        FILE_VERSION_1 | FILE_VERSION_2 => {
+            Ok(Entry::from_password(String::from_utf8(decrypted_content)?))
+        }
+        _ => Entry::from_bytes(&decrypted_content),
+    }
+}
+
+/// Prefixes the content with its length and fills it with zeros up to a multiple of `bucket`.
+fn pad(content: &[u8], bucket: usize) -> Vec<u8> {
+    let mut padded = (content.len() as u32).to_le_bytes().to_vec();
+    padded.extend_from_slice(content);
+    padded.resize(padded.len().div_ceil(bucket) * bucket, 0);
+    padded
+}
+
+fn unpad(padded: &[u8]) -> Result<Vec<u8>, PassmanError> {
+    let (len, rest) = padded
+        .split_first_chunk::<4>()
+        .ok_or(PassmanError::InvalidFileFormat)?;
+    let len = u32::from_le_bytes(*len) as usize;
+
+    rest.get(..len)
+        .map(<[u8]>::to_vec)
+        .ok_or(PassmanError::InvalidFileFormat)
+}
+
+/// Lists a vault without unlocking it, which fails when its service names are encrypted.
+pub fn list_services_in(storage_path: &Path) -> Result<Vec<ServiceName>, PassmanError> {
+    if !storage_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    if ServiceIndex::exists(storage_path) {
+        return Err(PassmanError::ServiceNamesEncrypted);
+    }
+
+    let mut services = Vec::new();
+    let entries = fs::read_dir(storage_path)?;
+
+    for entry in entries {
+        let entry = entry?;
+        // Skip anything passman did not write, like vault metadata or OS files
+        if let Some(service) = entry.file_name().to_str().and_then(ServiceName::from_file_name) {
+            services.push(service);
+        }
+    }
+
+    services.sort();
+    Ok(services)
+}
+
+fn resolve_default_path() -> PathBuf {
+    if let Some(path) = env::var_os(VAULT_DIR_ENV_VAR).filter(|path| !path.is_empty()) {
+        return expand_home(PathBuf::from(path));
+    }
+
+    if let Some(path) = &Config::current().vault {
+        return expand_home(path.clone());
+    }
+
+    let legacy_path = env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|d| d.join(LEGACY_VAULT_DIR_NAME)))
+        .unwrap_or_else(|| PathBuf::from(LEGACY_VAULT_DIR_NAME));
+
+    let Some(data_dir) = dirs::data_dir() else {
+        return legacy_path;
+    };
+    let storage_path = data_dir.join(VAULT_DIR_NAME);
+
+    if !storage_path.exists() && legacy_path.is_dir() {
+        match move_legacy_vault(&legacy_path, &storage_path) {
+            Ok(()) => eprintln!(
+                "Moved your vault from {} to {}",
+                legacy_path.display(),
+                storage_path.display()
+            ),
+            Err(e) => {
+                eprintln!(
+                    "Warning: failed to move your vault from {}, still using it there: {}",
+                    legacy_path.display(),
+                    e
+                );
+                return legacy_path;
+            }
+        }
+    }
+
+    storage_path
+}
+
+/// Moves a vault from next to the executable to the data folder, once.
+/// Copies when a rename can't cross file systems, the old folder only goes once the copy is complete.
+fn move_legacy_vault(legacy_path: &Path, storage_path: &Path) -> Result<(), PassmanError> {
+    recover_interrupted_rotation(legacy_path)?;
+    let lock = VaultLock::exclusive(legacy_path)?;
+
+    if let Some(parent) = storage_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::rename(legacy_path, storage_path).is_err() {
+        let copy_path = sibling_path(storage_path, LEGACY_MIGRATION_SUFFIX);
+        if copy_path.exists() {
+            fs::remove_dir_all(&copy_path)?;
+        }
+        permissions::create_private_dir(&copy_path)?;
+
+        for entry in fs::read_dir(legacy_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                let content = fs::read(entry.path())?;
+                atomic::write_file(&copy_path.join(entry.file_name()), &content)?;
+            }
+        }
+
+        fs::rename(&copy_path, storage_path)?;
+        fs::remove_dir_all(legacy_path)?;
+    }
+
+    drop(lock);
+    let _ = fs::remove_file(lock::lock_file_path(legacy_path));
+    Ok(())
+}
+
+/// Lets paths in the config file and `PASSMAN_DIR` start with `~/`.
+fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path,
+    }
+}
+
+fn sibling_path(storage_path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = storage_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    storage_path.with_file_name(file_name)
+}
+
+/// Finishes or rolls back a master password change that was interrupted midway.
+fn recover_interrupted_rotation(storage_path: &Path) -> Result<(), PassmanError> {
+    let staging_path = sibling_path(storage_path, ROTATION_STAGING_SUFFIX);
+    let backup_path = sibling_path(storage_path, ROTATION_BACKUP_SUFFIX);
+
+    if !staging_path.exists() && !backup_path.exists() {
+        return Ok(());
+    }
+
+    // Waits out a password change still running in another process
+    let _lock = VaultLock::exclusive(storage_path)?;
+
+    if staging_path.exists() {
+        if staging_path.join(ROTATION_COMPLETE_MARKER).exists() && !storage_path.exists() {
+            // Crashed between the two renames, the new vault is complete
+            fs::rename(&staging_path, storage_path)?;
+            fs::remove_file(storage_path.join(ROTATION_COMPLETE_MARKER))?;
+        } else {
+            // Crashed before the swap, the old vault is untouched
+            fs::remove_dir_all(&staging_path)?;
+        }
+    }
+
+    if backup_path.exists() {
+        if storage_path.exists() {
+            fs::remove_dir_all(&backup_path)?;
+        } else {
+            fs::rename(&backup_path, storage_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "correct horse";
+    const VAULT_KEY: [u8; 32] = [7; 32];
+
+    // Small enough to keep the tests fast, the costs are stored in each file anyway
+    fn weak_kdf_params() -> KdfParameters {
+        crypto::gen_kdf_parameters(8, 1, 1)
+    }
+
+    fn file_key(file_key: &FileKey) -> Result<[u8; 32], PassmanError> {
+        match file_key {
+            FileKey::Password(kdf_params) => crypto::derive_key(PASSWORD.as_bytes(), kdf_params),
+            FileKey::VaultKey(entry_id) => Ok(crypto::derive_entry_key(&VAULT_KEY, entry_id)),
+        }
+    }
+
+    /// Writes a file the way the given version did.
+    fn sealed_file(version: u8, flags: u8, content: &[u8]) -> Vec<u8> {
+        let key = match version {
+            FILE_VERSION_1..=FILE_VERSION_3 => FileKey::Password(weak_kdf_params()),
+            _ => FileKey::VaultKey(crypto::gen_entry_id()),
+        };
+        let header = FileHeader {
+            version,
+            key,
+            flags,
+            nonce: crypto::gen_nonce(),
+        };
+
+        let mut data = header.to_bytes();
+        let aad = if version == FILE_VERSION_1 { Vec::new() } else { data.clone() };
+        let cipher = crypto::cipher_from_key(&file_key(&header.key).unwrap());
+        let sealed = cipher
+            .encrypt(
+                chacha20poly1305::Nonce::from_slice(&header.nonce),
+                Payload { msg: content, aad: &aad },
+            )
+            .unwrap();
+        data.extend_from_slice(&sealed);
+        data
+    }
+
+    fn sample_entry() -> Entry {
+        let mut entry = Entry::new("s3cret".to_string());
+        entry.username = Some("octocat".to_string());
+        entry.urls.push("https://github.com".to_string());
+        entry
+    }
+
+    #[test]
+    fn reads_every_file_version() {
+        for version in [FILE_VERSION_1, FILE_VERSION_2] {
+            let file = sealed_file(version, 0, b"s3cret");
+            let entry = decrypt_content(&file, file_key).unwrap();
+            assert_eq!(entry, Entry::from_password("s3cret".to_string()), "version {}", version);
+        }
+
+        let entry = sample_entry();
+        for version in [FILE_VERSION_3, FILE_VERSION_4, FILE_VERSION_5] {
+            let file = sealed_file(version, 0, &entry.to_bytes());
+            assert_eq!(decrypt_content(&file, file_key).unwrap(), entry, "version {}", version);
+        }
+
+        let padded = pad(&entry.to_bytes(), 256);
+        let file = sealed_file(FILE_VERSION_5, FLAG_PADDED, &padded);
+        assert_eq!(decrypt_content(&file, file_key).unwrap(), entry);
+    }
+
+    #[test]
+    fn rejects_a_tampered_header() {
+        let entry = sample_entry();
+
+        for version in [FILE_VERSION_2, FILE_VERSION_3, FILE_VERSION_4, FILE_VERSION_5] {
+            let file = sealed_file(version, 0, &entry.to_bytes());
+            let (_, header_len) = FileHeader::read(&file).unwrap();
+            // The last header byte is part of the nonce, which the key doesn't depend on
+            let mut tampered = file.clone();
+            tampered[header_len - 1] ^= 1;
+            assert!(decrypt_content(&tampered, file_key).is_err(), "version {}", version);
+        }
+
+        // Turning padding off would otherwise hand the padded bytes to the entry parser
+        let file = sealed_file(FILE_VERSION_5, FLAG_PADDED, &pad(&entry.to_bytes(), 256));
+        let flags_at = FileHeader::read(&file).unwrap().1 - 13;
+        let mut tampered = file.clone();
+        tampered[flags_at] = 0;
+        assert!(decrypt_content(&tampered, file_key).is_err());
+    }
+
+    #[test]
+    fn rejects_a_tampered_body() {
+        let mut file = sealed_file(FILE_VERSION_5, 0, &sample_entry().to_bytes());
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        assert!(decrypt_content(&file, file_key).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_flags() {
+        let mut file = sealed_file(FILE_VERSION_5, 0, &sample_entry().to_bytes());
+        file[4] = CURRENT_FILE_VERSION + 1;
+        assert!(matches!(FileHeader::read(&file), Err(PassmanError::UnsupportedVersion)));
+
+        let file = sealed_file(FILE_VERSION_5, 0x80, &sample_entry().to_bytes());
+        assert!(matches!(FileHeader::read(&file), Err(PassmanError::UnsupportedVersion)));
+
+        let mut file = sealed_file(FILE_VERSION_5, 0, &sample_entry().to_bytes());
+        file[0] = b'X';
+        assert!(matches!(FileHeader::read(&file), Err(PassmanError::InvalidFileFormat)));
+    }
+
+    #[test]
+    fn header_round_trips() {
+        for version in [FILE_VERSION_3, FILE_VERSION_4, FILE_VERSION_5] {
+            let file = sealed_file(version, 0, b"");
+            let (header, header_len) = FileHeader::read(&file).unwrap();
+            assert_eq!(header.version, version);
+            assert_eq!(header.to_bytes(), file[..header_len]);
+        }
+    }
+
+    #[test]
+    fn pads_to_the_next_bucket() {
+        // The 4 byte length prefix counts towards the bucket
+        for (len, padded_len) in [(0, 256), (252, 256), (253, 512), (508, 512), (509, 768)] {
+            let content = vec![0xAB; len];
+            let padded = pad(&content, 256);
+            assert_eq!(padded.len(), padded_len, "content of {} bytes", len);
+            assert_eq!(unpad(&padded).unwrap(), content);
+        }
+        assert_eq!(pad(b"abc", 1).len(), 7);
+    }
+
+    #[test]
+    fn rejects_bad_padding() {
+        assert!(unpad(b"").is_err());
+        assert!(unpad(&[3, 0, 0]).is_err());
+
+        let mut padded = pad(b"abc", 16);
+        assert_eq!(unpad(&padded[..7]).unwrap(), b"abc");
+        assert!(unpad(&padded[..6]).is_err());
+        padded[..4].copy_from_slice(&13u32.to_le_bytes());
+        assert!(unpad(&padded).is_err());
+        padded[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(unpad(&padded).is_err());
+    }
+
+    /// A vault at `dir` holding `services`, each with its name as the password.
+    fn new_vault(dir: &Path, password: &str, services: &[&str]) -> PassmanStorage {
+        VaultMetadata::create(password, &crypto::gen_vault_key(), 8, 1, 1)
+            .unwrap()
+            .save(dir)
+            .unwrap();
+        let storage = PassmanStorage::with_path(password.to_string(), dir.to_path_buf());
+        for service in services {
+            storage.store(&ServiceName::new(service).unwrap(), service, 8, 1, 1).unwrap();
+        }
+        storage
+    }
+
+    fn open(dir: &Path, password: &str) -> Result<PassmanStorage, PassmanError> {
+        let storage = PassmanStorage::with_path(password.to_string(), dir.to_path_buf());
+        storage.vault_key()?;
+        Ok(storage)
+    }
+
+    fn assert_holds(storage: &PassmanStorage, services: &[&str]) {
+        let mut listed = storage.list_services().unwrap();
+        listed.sort();
+        let mut expected: Vec<ServiceName> = services.iter().map(|s| ServiceName::new(s).unwrap()).collect();
+        expected.sort();
+        assert_eq!(listed, expected);
+        for service in expected {
+            assert_eq!(storage.retrieve(&service).unwrap(), service.as_str());
+        }
+    }
+
+    #[test]
+    fn rotates_the_master_password() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let storage = new_vault(&dir, "old", &["github", "work/mail"]);
+
+        let mut seen = Vec::new();
+        storage
+            .rotate_master_password("new", 8, 1, 1, |i, total, service| seen.push((i, total, service.to_string())))
+            .unwrap();
+        assert_eq!(seen.len(), 2);
+
+        assert_holds(&open(&dir, "new").unwrap(), &["github", "work/mail"]);
+        assert!(matches!(open(&dir, "old"), Err(PassmanError::WrongMasterPassword)));
+        assert!(!sibling_path(&dir, ROTATION_STAGING_SUFFIX).exists());
+        assert!(!sibling_path(&dir, ROTATION_BACKUP_SUFFIX).exists());
+        assert!(!dir.join(ROTATION_COMPLETE_MARKER).exists());
+    }
+
+    #[test]
+    fn rotation_keeps_encrypted_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let storage = new_vault(&dir, "old", &["github"]);
+        storage.encrypt_service_names().unwrap();
+
+        storage.rotate_master_password("new", 8, 1, 1, |_, _, _| {}).unwrap();
+
+        let storage = open(&dir, "new").unwrap();
+        assert!(storage.names_encrypted());
+        assert_holds(&storage, &["github"]);
+    }
+
+    #[test]
+    fn drops_a_rotation_interrupted_before_the_swap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        new_vault(&dir, "old", &["github"]);
+
+        // Half written, no completion marker
+        let staging = sibling_path(&dir, ROTATION_STAGING_SUFFIX);
+        new_vault(&staging, "new", &[]);
+
+        recover_interrupted_rotation(&dir).unwrap();
+        assert!(!staging.exists());
+        assert_holds(&open(&dir, "old").unwrap(), &["github"]);
+        assert!(open(&dir, "new").is_err());
+    }
+
+    #[test]
+    fn finishes_a_rotation_interrupted_between_the_renames() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let backup = sibling_path(&dir, ROTATION_BACKUP_SUFFIX);
+        let staging = sibling_path(&dir, ROTATION_STAGING_SUFFIX);
+        new_vault(&dir, "old", &["github"]);
+        new_vault(&staging, "new", &["github"]);
+        File::create(staging.join(ROTATION_COMPLETE_MARKER)).unwrap();
+        fs::rename(&dir, &backup).unwrap();
+
+        recover_interrupted_rotation(&dir).unwrap();
+        assert!(!staging.exists());
+        assert!(!backup.exists());
+        assert!(!dir.join(ROTATION_COMPLETE_MARKER).exists());
+        assert_holds(&open(&dir, "new").unwrap(), &["github"]);
+        assert!(open(&dir, "old").is_err());
+    }
+
+    #[test]
+    fn restores_the_backup_when_the_new_vault_is_missing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let backup = sibling_path(&dir, ROTATION_BACKUP_SUFFIX);
+        new_vault(&backup, "old", &["github"]);
+
+        recover_interrupted_rotation(&dir).unwrap();
+        assert!(!backup.exists());
+        assert_holds(&open(&dir, "old").unwrap(), &["github"]);
+    }
+
+    #[test]
+    fn removes_the_backup_once_swapped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let backup = sibling_path(&dir, ROTATION_BACKUP_SUFFIX);
+        new_vault(&dir, "new", &["github"]);
+        new_vault(&backup, "old", &["github"]);
+
+        recover_interrupted_rotation(&dir).unwrap();
+        assert!(!backup.exists());
+        assert_holds(&open(&dir, "new").unwrap(), &["github"]);
+    }
+
+    /// Entry files in `dir`, leaving out the metadata and index.
+    fn entry_files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn encrypts_and_decrypts_service_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let storage = new_vault(&dir, PASSWORD, &["github", "work/mail"]);
+        let plain_files = entry_files(&dir);
+
+        assert_eq!(storage.encrypt_service_names().unwrap(), 2);
+        assert!(storage.names_encrypted());
+        let opaque = entry_files(&dir);
+        assert_eq!(opaque.len(), 2);
+        assert!(opaque.iter().all(|name| name.len() == 32 && name.chars().all(|c| c.is_ascii_hexdigit())));
+        assert_holds(&storage, &["github", "work/mail"]);
+
+        // Re-running has nothing left to move
+        assert_eq!(storage.encrypt_service_names().unwrap(), 0);
+        assert_eq!(entry_files(&dir), opaque);
+
+        assert_eq!(storage.decrypt_service_names().unwrap(), 2);
+        assert!(!storage.names_encrypted());
+        assert_eq!(entry_files(&dir), plain_files);
+        assert_holds(&storage, &["github", "work/mail"]);
+    }
+
+    #[test]
+    fn finishes_an_interrupted_name_encryption() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let storage = new_vault(&dir, PASSWORD, &["github", "mail"]);
+
+        // The index was saved with both names but only one file was moved
+        let vault_key = storage.require_vault_key().unwrap();
+        let mut index = ServiceIndex::default();
+        let github = index.insert(&ServiceName::new("github").unwrap());
+        index.insert(&ServiceName::new("mail").unwrap());
+        index.save(&dir, &vault_key).unwrap();
+        fs::rename(dir.join("github"), dir.join(&github)).unwrap();
+
+        assert_eq!(storage.encrypt_service_names().unwrap(), 1);
+        assert_eq!(entry_files(&dir).len(), 2);
+        assert_holds(&storage, &["github", "mail"]);
+    }
+
+    #[test]
+    fn manages_entries_under_opaque_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("vault");
+        let storage = new_vault(&dir, PASSWORD, &["github", "mail"]);
+        storage.encrypt_service_names().unwrap();
+        let service = |name| ServiceName::new(name).unwrap();
+
+        storage.store(&service("bank"), "bank", 8, 1, 1).unwrap();
+        assert_eq!(entry_files(&dir).len(), 3);
+        assert!(!dir.join("bank").exists());
+        assert!(storage.has_service(&service("bank")));
 
-        let nonce = GenericArray::clone_from_slice(&nonce);
-        let cipher = crypto::gen_decrypt_cipher(self.master_password.as_bytes(), &kdf_params)?;
+        // Renaming keeps the file, only the index changes
+        let before = entry_files(&dir);
+        storage.rename(&service("mail"), &service("work/mail")).unwrap();
+        assert_eq!(entry_files(&dir), before);
+        assert!(!storage.has_service(&service("mail")));
+        assert_eq!(storage.retrieve(&service("work/mail")).unwrap(), "mail");
 
-        let decrypted_content = cipher.decrypt(&nonce, encrypted_content.as_ref())?;
+        storage.remove(&service("github")).unwrap();
+        assert_eq!(entry_files(&dir).len(), 2);
+        assert!(matches!(
+            storage.retrieve(&service("github")),
+            Err(PassmanError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
 
-        Ok(String::from_utf8(decrypted_content)?)
+        let mut listed = storage.list_services().unwrap();
+        listed.sort();
+        assert_eq!(listed, [service("bank"), service("work/mail")]);
     }
 }
@@ -0,0 +1,216 @@
+use argon2::Version;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use crate::atomic;
+use crate::crypto::{self, KdfParameters};
+use crate::error::PassmanError;
+use crate::permissions;
+use crate::storage::{ENCRYPTION_CHACHA20POLY1305, KDF_ARGON2ID};
+
+pub const VAULT_METADATA_FILE: &str = ".passman-vault";
+
+const VAULT_MAGIC_NUMBER: &[u8; 4] = b"PMVT";
+const VAULT_VERSION_1: u8 = 0x01;
+const VAULT_VERSION_2: u8 = 0x02;
+const CURRENT_VAULT_VERSION: u8 = VAULT_VERSION_2;
+
+// Known plaintext sealed with the master key, only the right password can open it
+const VERIFIER_PLAINTEXT: &[u8] = b"passman vault verifier";
+
+/// Vault-wide metadata, used to check the master password before touching any entry.
+///
+/// Version 1 only holds a verifier. From version 2 it holds the vault key wrapped
+/// with the master password, entry keys are then derived from it without Argon2.
+pub struct VaultMetadata {
+    version: u8,
+    kdf_params: KdfParameters,
+    nonce: [u8; 12],
+    sealed: Vec<u8>,
+}
+
+impl VaultMetadata {
+    pub fn path(storage_path: &Path) -> PathBuf {
+        storage_path.join(VAULT_METADATA_FILE)
+    }
+
+    pub fn exists(storage_path: &Path) -> bool {
+        Self::path(storage_path).exists()
+    }
+
+    /// Wraps `vault_key` with the master password.
+    pub fn create(
+        master_password: &str,
+        vault_key: &[u8; 32],
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<Self, PassmanError> {
+        let (cipher, kdf_params, nonce) =
+            crypto::gen_new_cipher(master_password.as_bytes(), m_cost, t_cost, p_cost)?;
+
+        let mut metadata = Self {
+            version: CURRENT_VAULT_VERSION,
+            kdf_params,
+            nonce,
+            sealed: Vec::new(),
+        };
+
+        // The header goes in as associated data, tampering with the costs fails the unwrap
+        metadata.sealed = cipher.encrypt(
+            chacha20poly1305::Nonce::from_slice(&nonce),
+            Payload {
+                msg: vault_key,
+                aad: &metadata.header_bytes(),
+            },
+        )?;
+
+        Ok(metadata)
+    }
+
+    pub fn load(storage_path: &Path) -> Result<Self, PassmanError> {
+        let path = Self::path(storage_path);
+        if !path.exists() {
+            return Err(PassmanError::VaultNotInitialized);
+        }
+
+        let mut file = File::open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        Self::from_bytes(&content)
+    }
+
+    pub fn save(&self, storage_path: &Path) -> Result<(), PassmanError> {
+        permissions::create_private_dir(storage_path)?;
+
+        atomic::write_file(&Self::path(storage_path), &self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Checks the master password, failing with `WrongMasterPassword` on mismatch.
+    pub fn verify(&self, master_password: &str) -> Result<(), PassmanError> {
+        self.unlock(master_password).map(|_| ())
+    }
+
+    /// Checks the master password and returns the vault key, `None` for a version 1 vault.
+    pub fn unlock(&self, master_password: &str) -> Result<Option<[u8; 32]>, PassmanError> {
+        let cipher = crypto::gen_decrypt_cipher(master_password.as_bytes(), &self.kdf_params)?;
+        let nonce = GenericArray::clone_from_slice(&self.nonce);
+
+        if self.version == VAULT_VERSION_1 {
+            return match cipher.decrypt(&nonce, self.sealed.as_ref()) {
+                Ok(plaintext) if plaintext == VERIFIER_PLAINTEXT => Ok(None),
+                _ => Err(PassmanError::WrongMasterPassword),
+            };
+        }
+
+        let payload = Payload {
+            msg: self.sealed.as_ref(),
+            aad: &self.header_bytes(),
+        };
+
+        match cipher.decrypt(&nonce, payload) {
+            Ok(vault_key) => vault_key
+                .try_into()
+                .map(Some)
+                .map_err(|_| PassmanError::InvalidFileFormat),
+            Err(_) => Err(PassmanError::WrongMasterPassword),
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn kdf_params(&self) -> &KdfParameters {
+        &self.kdf_params
+    }
+
+    /// Whether entries are encrypted with keys derived from a vault key.
+    pub fn has_vault_key(&self) -> bool {
+        self.version >= VAULT_VERSION_2
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.header_bytes();
+        data.extend_from_slice(&self.sealed);
+        data
+    }
+
+    fn header_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        data.extend_from_slice(VAULT_MAGIC_NUMBER);
+        data.push(self.version);
+        data.push(KDF_ARGON2ID);
+        data.extend_from_slice(&self.kdf_params.salt);
+        data.extend_from_slice(&self.kdf_params.m_cost.to_le_bytes());
+        data.extend_from_slice(&self.kdf_params.t_cost.to_le_bytes());
+        data.extend_from_slice(&self.kdf_params.p_cost.to_le_bytes());
+        data.push(ENCRYPTION_CHACHA20POLY1305);
+        data.extend_from_slice(&self.nonce);
+
+        data
+    }
+
+    fn from_bytes(content: &[u8]) -> Result<Self, PassmanError> {
+        let mut cursor = Cursor::new(content);
+
+        let mut magic_number = [0u8; 4];
+        cursor.read_exact(&mut magic_number)?;
+        if magic_number != *VAULT_MAGIC_NUMBER {
+            return Err(PassmanError::InvalidFileFormat);
+        }
+
+        let mut version_byte = [0u8; 1];
+        cursor.read_exact(&mut version_byte)?;
+        if ![VAULT_VERSION_1, VAULT_VERSION_2].contains(&version_byte[0]) {
+            return Err(PassmanError::UnsupportedVersion);
+        }
+
+        let mut kdf_type_byte = [0u8; 1];
+        cursor.read_exact(&mut kdf_type_byte)?;
+        if kdf_type_byte[0] != KDF_ARGON2ID {
+            return Err(PassmanError::InvalidFileFormat);
+        }
+
+        let mut salt = [0u8; 16];
+        cursor.read_exact(&mut salt)?;
+        let mut cost_bytes = [0u8; 4];
+        cursor.read_exact(&mut cost_bytes)?;
+        let m_cost = u32::from_le_bytes(cost_bytes);
+        cursor.read_exact(&mut cost_bytes)?;
+        let t_cost = u32::from_le_bytes(cost_bytes);
+        cursor.read_exact(&mut cost_bytes)?;
+        let p_cost = u32::from_le_bytes(cost_bytes);
+
+        let mut encryption_type_byte = [0u8; 1];
+        cursor.read_exact(&mut encryption_type_byte)?;
+        if encryption_type_byte[0] != ENCRYPTION_CHACHA20POLY1305 {
+            return Err(PassmanError::InvalidFileFormat);
+        }
+
+        let mut nonce = [0u8; 12];
+        cursor.read_exact(&mut nonce)?;
+
+        let mut sealed = Vec::new();
+        cursor.read_to_end(&mut sealed)?;
+
+        Ok(Self {
+            version: version_byte[0],
+            kdf_params: KdfParameters {
+                salt,
+                version: Version::V0x13,
+                m_cost,
+                t_cost,
+                p_cost,
+            },
+            nonce,
+            sealed,
+        })
+    }
+}
@@ -0,0 +1,306 @@
+#!/usr/bin/env python3
+"""Writes the KDBX 4 databases the kdbx tests read, the way KeePassXC lays them out.
+
+The writer is independent of passman's: Argon2 follows RFC 9106 (checked against
+its test vectors), everything else uses hashlib and the cryptography package.
+Run it from this folder, the password of every database is "keepass-pw".
+"""
+import base64, gzip, hashlib, hmac, os, struct
+
+from cryptography.hazmat.primitives import padding
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+PASSWORD = "keepass-pw"
+
+AES256 = bytes.fromhex("31c1f2e6bf714350be5805216afc5aff")
+CHACHA20 = bytes.fromhex("d6038a2b8b6f4cb5a524339a31dbb59a")
+KDF_ARGON2D = bytes.fromhex("ef636ddf8c29444b91f7a9a403e30a0c")
+KDF_ARGON2ID = bytes.fromhex("9e298b1956db4773b23dfc3ec6f0a1e6")
+
+M64 = (1 << 64) - 1
+
+
+def blake2b(data, n):
+    return hashlib.blake2b(data, digest_size=n).digest()
+
+def h_prime(n, data):
+    data = struct.pack('<I', n) + data
+    if n <= 64:
+        return blake2b(data, n)
+    v = blake2b(data, 64)
+    out = v[:32]
+    while n - len(out) > 64:
+        v = blake2b(v, 64)
+        out += v[:32]
+    return out + blake2b(v, n - len(out))
+
+def rotr(x, n):
+    return ((x >> n) | (x << (64 - n))) & M64
+
+def gb(v, a, b, c, d):
+    v[a] = (v[a] + v[b] + 2 * (v[a] & 0xFFFFFFFF) * (v[b] & 0xFFFFFFFF)) & M64
+    v[d] = rotr(v[d] ^ v[a], 32)
+    v[c] = (v[c] + v[d] + 2 * (v[c] & 0xFFFFFFFF) * (v[d] & 0xFFFFFFFF)) & M64
+    v[b] = rotr(v[b] ^ v[c], 24)
+    v[a] = (v[a] + v[b] + 2 * (v[a] & 0xFFFFFFFF) * (v[b] & 0xFFFFFFFF)) & M64
+    v[d] = rotr(v[d] ^ v[a], 16)
+    v[c] = (v[c] + v[d] + 2 * (v[c] & 0xFFFFFFFF) * (v[d] & 0xFFFFFFFF)) & M64
+    v[b] = rotr(v[b] ^ v[c], 63)
+
+def permute(v):
+    gb(v, 0, 4, 8, 12); gb(v, 1, 5, 9, 13); gb(v, 2, 6, 10, 14); gb(v, 3, 7, 11, 15)
+    gb(v, 0, 5, 10, 15); gb(v, 1, 6, 11, 12); gb(v, 2, 7, 8, 13); gb(v, 3, 4, 9, 14)
+
+def compress(x, y):
+    r = [a ^ b for a, b in zip(x, y)]
+    q = r[:]
+    for row in range(8):
+        v = q[16 * row:16 * row + 16]
+        permute(v)
+        q[16 * row:16 * row + 16] = v
+    for col in range(8):
+        idx = [16 * row + 2 * col + k for row in range(8) for k in (0, 1)]
+        v = [q[i] for i in idx]
+        permute(v)
+        for i, val in zip(idx, v):
+            q[i] = val
+    return [a ^ b for a, b in zip(q, r)]
+
+def to_words(b):
+    return list(struct.unpack('<128Q', b))
+
+ARGON2D, ARGON2I, ARGON2ID = 0, 1, 2
+
+def argon2(kind, password, salt, m, t, p, length=32, secret=b'', data=b''):
+    h0 = blake2b(struct.pack('<IIIIII', p, length, m, t, 0x13, kind)
+                 + struct.pack('<I', len(password)) + password
+                 + struct.pack('<I', len(salt)) + salt
+                 + struct.pack('<I', len(secret)) + secret
+                 + struct.pack('<I', len(data)) + data, 64)
+    m_blocks = 4 * p * (m // (4 * p))
+    q = m_blocks // p
+    seg = q // 4
+    B = [[None] * q for _ in range(p)]
+    for l in range(p):
+        B[l][0] = to_words(h_prime(1024, h0 + struct.pack('<II', 0, l)))
+        B[l][1] = to_words(h_prime(1024, h0 + struct.pack('<II', 1, l)))
+    zero = [0] * 128
+    for r in range(t):
+        for s in range(4):
+            for l in range(p):
+                independent = kind == ARGON2I or (kind == ARGON2ID and r == 0 and s < 2)
+                inp = [0] * 128
+                inp[0:6] = [r, l, s, m_blocks, t, kind]
+                addresses = None
+                def next_addresses():
+                    inp[6] += 1
+                    return compress(zero, compress(zero, inp))
+                start = 0
+                if r == 0 and s == 0:
+                    start = 2
+                    if independent:
+                        addresses = next_addresses()
+                for i in range(start, seg):
+                    j = s * seg + i
+                    prev = B[l][j - 1] if j > 0 else B[l][q - 1]
+                    if independent:
+                        if i % 128 == 0:
+                            addresses = next_addresses()
+                        rand = addresses[i % 128]
+                    else:
+                        rand = prev[0]
+                    ref_lane = (rand >> 32) % p
+                    if r == 0 and s == 0:
+                        ref_lane = l
+                    same = ref_lane == l
+                    if r == 0:
+                        if s == 0:
+                            area = i - 1
+                        elif same:
+                            area = s * seg + i - 1
+                        else:
+                            area = s * seg - (1 if i == 0 else 0)
+                    else:
+                        area = q - seg + i - 1 if same else q - seg - (1 if i == 0 else 0)
+                    rel = rand & 0xFFFFFFFF
+                    rel = (rel * rel) >> 32
+                    rel = area - 1 - ((area * rel) >> 32)
+                    start_pos = 0 if r == 0 or s == 3 else (s + 1) * seg
+                    ref = B[ref_lane][(start_pos + rel) % q]
+                    new = compress(prev, ref)
+                    if r > 0:
+                        new = [a ^ b for a, b in zip(new, B[l][j])]
+                    B[l][j] = new
+    c = B[0][q - 1]
+    for l in range(1, p):
+        c = [a ^ b for a, b in zip(c, B[l][q - 1])]
+    return h_prime(length, struct.pack('<128Q', *c))
+
+
+def chacha20(key, nonce, data):
+    return Cipher(algorithms.ChaCha20(key, b"\0" * 4 + nonce), None).encryptor().update(data)
+
+
+def field(id, data):
+    return bytes([id]) + struct.pack("<I", len(data)) + data
+
+
+def variants(items):
+    data = struct.pack("<H", 0x100)
+    for kind, name, value in items:
+        data += bytes([kind]) + struct.pack("<I", len(name)) + name.encode() + struct.pack("<I", len(value)) + value
+    return data + b"\0"
+
+
+def block_key(index, hmac_key):
+    return hashlib.sha512(struct.pack("<Q", index) + hmac_key).digest()
+
+
+def keepass_time(unix):
+    return base64.b64encode(struct.pack("<q", unix + 62135596800)).decode()
+
+
+def write(path, kdf, cipher, version, compressed, m_kib=256, t=2, p=2):
+    seed, salt = os.urandom(32), os.urandom(32)
+    iv = os.urandom(16 if cipher == AES256 else 12)
+    kdf_parameters = variants([
+        (0x42, "$UUID", KDF_ARGON2D if kdf == ARGON2D else KDF_ARGON2ID),
+        (0x42, "S", salt),
+        (0x04, "P", struct.pack("<I", p)),
+        (0x05, "M", struct.pack("<Q", m_kib * 1024)),
+        (0x05, "I", struct.pack("<Q", t)),
+        (0x04, "V", struct.pack("<I", 0x13)),
+    ])
+    header = struct.pack("<III", 0x9AA2D903, 0xB54BFB67, version)
+    header += field(2, cipher) + field(3, struct.pack("<I", 1 if compressed else 0))
+    header += field(4, seed) + field(7, iv) + field(11, kdf_parameters) + field(0, b"\r\n\r\n")
+
+    composite = hashlib.sha256(hashlib.sha256(PASSWORD.encode()).digest()).digest()
+    transformed = argon2(kdf, composite, salt, m_kib, t, p)
+    encryption_key = hashlib.sha256(seed + transformed).digest()
+    hmac_key = hashlib.sha512(seed + transformed + b"\x01").digest()
+
+    inner_key = os.urandom(64)
+    inner_hash = hashlib.sha512(inner_key).digest()
+    stream = Cipher(algorithms.ChaCha20(inner_hash[:32], b"\0" * 4 + inner_hash[32:44]), None).encryptor()
+
+    def protect(value):
+        return base64.b64encode(stream.update(value.encode())).decode()
+
+    # An attachment in the inner header, which passman skips
+    inner = field(1, struct.pack("<I", 3)) + field(2, inner_key) + field(3, b"\x01attachment") + field(0, b"")
+    payload = inner + xml(protect).encode()
+    if compressed:
+        payload = gzip.compress(payload)
+
+    if cipher == AES256:
+        padder = padding.PKCS7(128).padder()
+        encryptor = Cipher(algorithms.AES(encryption_key), modes.CBC(iv)).encryptor()
+        content = encryptor.update(padder.update(payload) + padder.finalize()) + encryptor.finalize()
+    else:
+        content = chacha20(encryption_key, iv, payload)
+
+    data = header + hashlib.sha256(header).digest()
+    data += hmac.new(block_key(M64, hmac_key), header, "sha256").digest()
+    for index, block in enumerate([content, b""]):
+        length = struct.pack("<I", len(block))
+        mac = hmac.new(block_key(index, hmac_key), struct.pack("<Q", index) + length + block, "sha256")
+        data += mac.digest() + length + block
+
+    with open(path, "wb") as f:
+        f.write(data)
+
+
+def xml(protect):
+    times = f"<Times><CreationTime>{keepass_time(1600000000)}</CreationTime><LastModificationTime>{keepass_time(1700000000)}</LastModificationTime><Expires>False</Expires></Times>"
+    auto_type = "<AutoType><Enabled>True</Enabled><DataTransferObfuscation>0</DataTransferObfuscation></AutoType>"
+    return f"""<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+\t<Meta>
+\t\t<Generator>KeePassXC</Generator>
+\t\t<DatabaseName>Fixture</DatabaseName>
+\t\t<MemoryProtection><ProtectTitle>False</ProtectTitle><ProtectUserName>False</ProtectUserName><ProtectPassword>True</ProtectPassword><ProtectURL>False</ProtectURL><ProtectNotes>False</ProtectNotes></MemoryProtection>
+\t\t<RecycleBinEnabled>True</RecycleBinEnabled>
+\t\t<RecycleBinUUID>cmVjeWNsZWJpbnV1aWQxMg==</RecycleBinUUID>
+\t\t<CustomData><Item><Key>KPXC_DECRYPTION_TIME_PREFERENCE</Key><Value>1000</Value></Item></CustomData>
+\t</Meta>
+\t<Root>
+\t\t<Group>
+\t\t\t<UUID>cm9vdHJvb3Ryb290cm9vdA==</UUID>
+\t\t\t<Name>Root</Name>
+\t\t\t<IconID>48</IconID>
+\t\t\t<Entry>
+\t\t\t\t<UUID>AAAAAAAAAAAAAAAAAAAAAA==</UUID>
+\t\t\t\t<IconID>0</IconID>
+\t\t\t\t<Tags>dev;work</Tags>
+\t\t\t\t{times}
+\t\t\t\t<String><Key>Title</Key><Value>GitHub</Value></String>
+\t\t\t\t<String><Key>UserName</Key><Value>octo &amp; cat</Value></String>
+\t\t\t\t<String><Key>Password</Key><Value Protected="True">{protect("gh-s3cret<&>")}</Value></String>
+\t\t\t\t<String><Key>URL</Key><Value>https://github.com/login</Value></String>
+\t\t\t\t<String><Key>Notes</Key><Value>line one
+line two</Value></String>
+\t\t\t\t<String><Key>otp</Key><Value Protected="True">{protect("otpauth://totp/x?secret=ABC")}</Value></String>
+\t\t\t\t<String><Key>KP2A_URL</Key><Value>https://gist.github.com</Value></String>
+\t\t\t\t<String><Key>Recovery codes</Key><Value Protected="True">{protect("rec-123 rec-456")}</Value></String>
+\t\t\t\t<String><Key>Employee ID</Key><Value>E-42</Value></String>
+\t\t\t\t<String><Key>Empty</Key><Value Protected="True"/></String>
+\t\t\t\t{auto_type}
+\t\t\t\t<History>
+\t\t\t\t\t<Entry>
+\t\t\t\t\t\t<UUID>AAAAAAAAAAAAAAAAAAAAAA==</UUID>
+\t\t\t\t\t\t{times}
+\t\t\t\t\t\t<String><Key>Title</Key><Value>GitHub (old)</Value></String>
+\t\t\t\t\t\t<String><Key>Password</Key><Value Protected="True">{protect("old-pass")}</Value></String>
+\t\t\t\t\t</Entry>
+\t\t\t\t</History>
+\t\t\t</Entry>
+\t\t\t<Group>
+\t\t\t\t<UUID>d29ya3dvcmt3b3Jrd29yaw==</UUID>
+\t\t\t\t<Name>Work</Name>
+\t\t\t\t<Group>
+\t\t\t\t\t<UUID>bWFpbG1haWxtYWlsbWFpbA==</UUID>
+\t\t\t\t\t<Name>Mail</Name>
+\t\t\t\t\t<Entry>
+\t\t\t\t\t\t<UUID>AQAAAAAAAAAAAAAAAAAAAA==</UUID>
+\t\t\t\t\t\t<String><Key>Title</Key><Value>Outlook</Value></String>
+\t\t\t\t\t\t<String><Key>UserName</Key><Value>me@example.com</Value></String>
+\t\t\t\t\t\t<String><Key>Password</Key><Value Protected="True">{protect("mail-pw ü")}</Value></String>
+\t\t\t\t\t</Entry>
+\t\t\t\t</Group>
+\t\t\t\t<Entry>
+\t\t\t\t\t<UUID>AgAAAAAAAAAAAAAAAAAAAA==</UUID>
+\t\t\t\t\t<String><Key>Title</Key><Value></Value></String>
+\t\t\t\t\t<String><Key>URL</Key><Value>https://user@jira.example.com:8443/login</Value></String>
+\t\t\t\t\t<String><Key>Password</Key><Value Protected="True">{protect("jira")}</Value></String>
+\t\t\t\t</Entry>
+\t\t\t\t<Entry>
+\t\t\t\t\t<UUID>AwAAAAAAAAAAAAAAAAAAAA==</UUID>
+\t\t\t\t\t<String><Key>Title</Key><Value></Value></String>
+\t\t\t\t\t<String><Key>Password</Key><Value Protected="True">{protect("nameless")}</Value></String>
+\t\t\t\t</Entry>
+\t\t\t</Group>
+\t\t\t<Group>
+\t\t\t\t<UUID>cmVjeWNsZWJpbnV1aWQxMg==</UUID>
+\t\t\t\t<Name>Recycle Bin</Name>
+\t\t\t\t<Entry>
+\t\t\t\t\t<UUID>BAAAAAAAAAAAAAAAAAAAAA==</UUID>
+\t\t\t\t\t<String><Key>Title</Key><Value>Deleted</Value></String>
+\t\t\t\t\t<String><Key>Password</Key><Value Protected="True">{protect("gone")}</Value></String>
+\t\t\t\t</Entry>
+\t\t\t</Group>
+\t\t\t<Entry>
+\t\t\t\t<UUID>BgAAAAAAAAAAAAAAAAAAAA==</UUID>
+\t\t\t\t<String><Key>Title</Key><Value><![CDATA[Last <one>]]></Value></String>
+\t\t\t\t<String><Key>Password</Key><Value Protected="True">{protect("last-pw")}</Value></String>
+\t\t\t</Entry>
+\t\t</Group>
+\t\t<DeletedObjects/>
+\t</Root>
+</KeePassFile>
+"""
+
+
+if __name__ == "__main__":
+    write("aes-argon2d.kdbx", ARGON2D, AES256, 0x00040000, compressed=True)
+    write("chacha20-argon2id.kdbx", ARGON2ID, CHACHA20, 0x00040001, compressed=False)